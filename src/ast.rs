@@ -1,15 +1,151 @@
-use std::{fmt::Display, marker::PhantomData};
+use std::{collections::HashMap, convert::Infallible, fmt::Display, marker::PhantomData};
 
-#[derive(Debug, Clone, PartialEq)]
+/// Keywords that bind a label or local id.
+static BINDING_KEYWORDS: &[&str] = &["block", "loop", "if", "try", "local", "param"];
+/// Keywords that refer to a label or local id.
+static USE_KEYWORDS: &[&str] = &[
+    "local.get",
+    "local.set",
+    "local.tee",
+    "br",
+    "br_if",
+    "br_table",
+    "end",
+];
+
+#[derive(Debug, Clone)]
 pub struct Node {
     pub name: String,
     pub depth: usize,
     pub items: Vec<Item>,
+    /// Byte offsets of the opening and one past the closing parenthesis in the input the node was parsed from. Nodes created by features have no span. Ignored by `Display` and `PartialEq`.
+    pub span: Option<(usize, usize)>,
+    /// Comments before and after a top-level node, like file headers. Only filled in if the parser keeps comments, always empty for nested nodes.
+    pub leading_comments: Vec<Item>,
+    pub trailing_comments: Vec<Item>,
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.depth == other.depth
+            && self.items == other.items
+            && self.leading_comments == other.leading_comments
+            && self.trailing_comments == other.trailing_comments
+    }
+}
+
+/// The kinds of top-level definitions a module can contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefKind {
+    Func,
+    Global,
+    Memory,
+    Table,
+    Type,
+    Elem,
+    Data,
+}
+
+impl DefKind {
+    pub const ALL: [DefKind; 7] = [
+        DefKind::Func,
+        DefKind::Global,
+        DefKind::Memory,
+        DefKind::Table,
+        DefKind::Type,
+        DefKind::Elem,
+        DefKind::Data,
+    ];
+
+    /// Returns the node name of the definition, e.g. `func`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DefKind::Func => "func",
+            DefKind::Global => "global",
+            DefKind::Memory => "memory",
+            DefKind::Table => "table",
+            DefKind::Type => "type",
+            DefKind::Elem => "elem",
+            DefKind::Data => "data",
+        }
+    }
+
+    /// Returns true for the kinds that can be imported.
+    pub fn is_importable(&self) -> bool {
+        matches!(
+            self,
+            DefKind::Func | DefKind::Global | DefKind::Memory | DefKind::Table
+        )
+    }
+}
+
+/// What an id in a reference position refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdRef {
+    Def(DefKind),
+    Local,
+}
+
+/// Returns what each of the `count` ids following `keyword` refers to, or `None` for ids that refer to a label or aren’t references.
+fn operand_refs(keyword: &str, count: usize) -> Vec<Option<IdRef>> {
+    let all = |kind| vec![Some(IdRef::Def(kind)); count];
+    // `table.init $table $elem` and `memory.init $memory $data`, where only the last id is required.
+    let last_differs = |first, last| {
+        (0..count)
+            .map(|idx| Some(IdRef::Def(if idx + 1 == count { last } else { first })))
+            .collect()
+    };
+    match keyword {
+        "call" | "return_call" | "ref.func" | "start" | "func" | "elem" => all(DefKind::Func),
+        "global.get" | "global.set" | "global" => all(DefKind::Global),
+        "local.get" | "local.set" | "local.tee" => vec![Some(IdRef::Local); count],
+        "type" => all(DefKind::Type),
+        "call_indirect"
+        | "return_call_indirect"
+        | "table"
+        | "table.get"
+        | "table.set"
+        | "table.size"
+        | "table.grow"
+        | "table.fill"
+        | "table.copy" => all(DefKind::Table),
+        "table.init" => last_differs(DefKind::Table, DefKind::Elem),
+        "elem.drop" => all(DefKind::Elem),
+        "memory.init" => last_differs(DefKind::Memory, DefKind::Data),
+        "data.drop" => all(DefKind::Data),
+        _ if keyword == "memory"
+            || keyword.starts_with("memory.")
+            || keyword.contains(".load")
+            || keyword.contains(".store") =>
+        {
+            all(DefKind::Memory)
+        }
+        _ => vec![None; count],
+    }
+}
+
+/// Exports can only refer to the importable definition kinds.
+pub type ExportKind = DefKind;
+
+/// An export of a module, from either an inline `(export ...)` clause or a standalone export node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    /// The exported name, without quotes.
+    pub name: String,
+    pub kind: ExportKind,
+    /// The id of the exported definition, or its index if it has no id.
+    pub target: String,
 }
 
+/// Visitors return true if they modified the node or attribute they were given.
 pub trait Visitor {
-    fn visit_node(&mut self, _node: &mut Node) {}
-    fn visit_attribute(&mut self, _attr: &mut String) {}
+    fn visit_node(&mut self, _node: &mut Node) -> bool {
+        false
+    }
+    fn visit_attribute(&mut self, _attr: &mut String) -> bool {
+        false
+    }
 }
 
 pub struct Walker<'a> {
@@ -34,16 +170,130 @@ impl<'a> Iterator for Walker<'a> {
 }
 
 impl Node {
+    /// Walks the tree depth-first and returns true if the visitor modified anything.
     #[allow(dead_code)]
-    pub fn walk_mut(&mut self, visitor: &mut impl Visitor) {
-        visitor.visit_node(self);
+    pub fn walk_mut(&mut self, visitor: &mut impl Visitor) -> bool {
+        let mut changed = visitor.visit_node(self);
         for item in &mut self.items {
-            match item {
+            changed |= match item {
                 Item::Attribute(attr) => visitor.visit_attribute(attr),
                 Item::Node(node) => node.walk_mut(visitor),
-                Item::Nothing => {}
+                _ => false,
+            };
+        }
+        changed
+    }
+
+    /// Applies the visitor until it stops modifying the tree. Returns the number of passes that modified the tree.
+    pub fn walk_mut_until_stable(&mut self, visitor: &mut impl Visitor) -> usize {
+        let mut passes = 0;
+        while self.walk_mut(visitor) {
+            passes += 1;
+        }
+        passes
+    }
+
+    /// Classifies the node as a definition by its name. Returns `None` for anything else, like instructions.
+    pub fn definition_kind(&self) -> Option<DefKind> {
+        DefKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == self.name)
+    }
+
+    /// Returns true if the node is a definition like `func` or `data`.
+    pub fn is_definition(&self) -> bool {
+        self.definition_kind().is_some()
+    }
+
+    /// Returns all exports of a module in declaration order. Unnamed definitions are referred to by their index, with imports numbered before definitions.
+    pub fn exports(&self) -> Vec<Export> {
+        let is_inline_import = |node: &Node| {
+            node.immediate_node_iter()
+                .any(|child| child.name == "import")
+        };
+        let mut num_imports: HashMap<DefKind, usize> = HashMap::new();
+        for node in self.immediate_node_iter() {
+            let kind = match node.name.as_str() {
+                "import" => node
+                    .immediate_node_iter()
+                    .find_map(|node| node.definition_kind()),
+                _ if is_inline_import(node) => node.definition_kind(),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                *num_imports.entry(kind).or_default() += 1;
+            }
+        }
+
+        let mut imports_seen: HashMap<DefKind, usize> = HashMap::new();
+        let mut definitions_seen: HashMap<DefKind, usize> = HashMap::new();
+        let mut exports = vec![];
+        for node in self.immediate_node_iter() {
+            if node.name == "export" {
+                let name = node.immediate_attribute_iter().next();
+                let descriptor = node.immediate_node_iter().next();
+                if let (Some(name), Some(descriptor)) = (name, descriptor) {
+                    if let (Some(kind), Some(target)) = (
+                        descriptor.definition_kind(),
+                        descriptor.immediate_attribute_iter().next(),
+                    ) {
+                        exports.push(Export {
+                            name: name.trim_matches('"').to_string(),
+                            kind,
+                            target: target.to_string(),
+                        });
+                    }
+                }
+                continue;
+            }
+            if node.name == "import" {
+                if let Some(kind) = node
+                    .immediate_node_iter()
+                    .find_map(|node| node.definition_kind())
+                {
+                    *imports_seen.entry(kind).or_default() += 1;
+                }
+                continue;
+            }
+            let kind = match node.definition_kind() {
+                Some(kind) if kind.is_importable() => kind,
+                _ => continue,
             };
+            let idx = if is_inline_import(node) {
+                let seen = imports_seen.entry(kind).or_default();
+                *seen += 1;
+                *seen - 1
+            } else {
+                let seen = definitions_seen.entry(kind).or_default();
+                *seen += 1;
+                num_imports.get(&kind).copied().unwrap_or(0) + *seen - 1
+            };
+            let target = node
+                .immediate_attribute_iter()
+                .find(|attr| attr.starts_with('$'))
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| format!("{idx}"));
+            for export in node
+                .immediate_node_iter()
+                .filter(|child| child.name == "export")
+            {
+                if let Some(name) = export.immediate_attribute_iter().next() {
+                    exports.push(Export {
+                        name: name.trim_matches('"').to_string(),
+                        kind,
+                        target: target.clone(),
+                    });
+                }
+            }
         }
+        exports
+    }
+
+    /// Returns an iterator that iterates over immediate children, skipping `Item::Nothing` and comments.
+    pub fn significant_items(&self) -> impl DoubleEndedIterator<Item = &Item> {
+        self.items
+            .iter()
+            .filter(|item| !item.is_nothing() && !item.is_comment())
     }
 
     /// Returns an iterator that iterates over immediate children that are nodes.
@@ -68,6 +318,178 @@ impl Node {
             .flat_map(|node| node.as_attribute_mut())
     }
 
+    /// Calls `f` exactly once for every attribute in the tree, depth-first.
+    pub fn for_each_attribute_mut(&mut self, mut f: impl FnMut(&mut String)) {
+        self.visit_attributes_mut(&mut f);
+    }
+
+    fn visit_attributes_mut(&mut self, f: &mut impl FnMut(&mut String)) {
+        for item in &mut self.items {
+            match item {
+                Item::Attribute(attr) => f(attr),
+                Item::Node(node) => node.visit_attributes_mut(f),
+                _ => {}
+            }
+        }
+    }
+
+    /// Clones the node and gives all labels, locals and params bound inside it fresh ids like `$l_0`, numbered from `counter`. Uses of these ids in `local.*` instructions and branches are renamed consistently, references to definitions outside the node are left alone.
+    pub fn clone_with_fresh_ids(&self, counter: &mut usize) -> Node {
+        let mut clone = self.clone();
+        let mut renames: HashMap<String, String> = HashMap::new();
+        clone.visit_bound_ids_mut(&mut |keyword, id| {
+            if BINDING_KEYWORDS.contains(&keyword) && !renames.contains_key(id.as_str()) {
+                renames.insert(id.clone(), format!("{id}_{counter}"));
+                *counter += 1;
+            }
+        });
+        clone.visit_bound_ids_mut(&mut |_, id| {
+            if let Some(new_id) = renames.get(id.as_str()) {
+                *id = new_id.clone();
+            }
+        });
+        clone
+    }
+
+    /// Returns every id that refers to a definition or a local, like `visit_references_mut`.
+    pub fn references(&self) -> Vec<(IdRef, String)> {
+        let mut refs = vec![];
+        self.clone()
+            .visit_references_mut(&mut |id_ref, id| {
+                refs.push((id_ref, id.clone()));
+                Ok::<(), Infallible>(())
+            })
+            .unwrap();
+        refs
+    }
+
+    /// Calls `f` with every id that refers to a definition or a local, in both folded and flat instructions, together with what it refers to. `self` is treated as a top-level node, so its own id is skipped if it is a definition. Labels and ids bound by `param`, `local` or blocks are skipped.
+    pub fn visit_references_mut<E>(
+        &mut self,
+        f: &mut impl FnMut(IdRef, &mut String) -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.visit_references_mut_inner(true, f)
+    }
+
+    fn visit_references_mut_inner<E>(
+        &mut self,
+        is_top_level: bool,
+        f: &mut impl FnMut(IdRef, &mut String) -> Result<(), E>,
+    ) -> Result<(), E> {
+        // Runs of ids with the keyword before them, and whether that keyword is the node name.
+        let mut groups: Vec<(String, bool, Vec<usize>)> = vec![];
+        let mut current = Some((self.name.clone(), true, vec![]));
+        for (idx, item) in self.items.iter().enumerate() {
+            match item {
+                Item::Attribute(attr) if attr.starts_with('$') => {
+                    if let Some((_, _, ids)) = &mut current {
+                        ids.push(idx);
+                    }
+                }
+                Item::Attribute(attr) => {
+                    groups.extend(current.take());
+                    current = Some((attr.clone(), false, vec![]));
+                }
+                Item::Node(_) => {
+                    groups.extend(current.take());
+                    // Function references follow the offset of an elem segment.
+                    current = (self.name == "elem").then(|| ("elem".to_string(), false, vec![]));
+                }
+                _ => {}
+            }
+        }
+        groups.extend(current);
+
+        for (keyword, is_name, mut ids) in groups {
+            let is_definition = is_name && is_top_level && self.is_definition();
+            if (is_definition || BINDING_KEYWORDS.contains(&keyword.as_str())) && !ids.is_empty() {
+                ids.remove(0);
+            }
+            let refs = operand_refs(&keyword, ids.len());
+            for (idx, id_ref) in ids.into_iter().zip(refs) {
+                if let (Some(id_ref), Item::Attribute(attr)) = (id_ref, &mut self.items[idx]) {
+                    f(id_ref, attr)?;
+                }
+            }
+        }
+        // Children of a module are top-level nodes, and so are the descriptors of imports.
+        let children_are_top_level = self.name == "module" || self.name == "import";
+        for child in self.immediate_node_iter_mut() {
+            child.visit_references_mut_inner(children_are_top_level, f)?;
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with every id that directly follows a keyword binding or using a label or local, both folded and flat.
+    fn visit_bound_ids_mut(&mut self, f: &mut impl FnMut(&str, &mut String)) {
+        let mut keyword = Some(self.name.clone());
+        for item in &mut self.items {
+            match item {
+                Item::Attribute(attr) if attr.starts_with('$') => {
+                    if let Some(keyword) = keyword.as_deref() {
+                        if BINDING_KEYWORDS.contains(&keyword) || USE_KEYWORDS.contains(&keyword) {
+                            f(keyword, attr);
+                        }
+                    }
+                }
+                Item::Attribute(attr) => keyword = Some(attr.clone()),
+                Item::Node(node) => {
+                    keyword = None;
+                    node.visit_bound_ids_mut(f);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Rewrites numeric targets of `br`, `br_if` and `br_table` that leave this node, both folded and flat. `remap` is called with the target relative to the node, where 0 is the innermost block around it, and returns the new relative target. Branches to labels inside the node and named branches are unaffected.
+    pub fn adjust_relative_branches(&mut self, mut remap: impl FnMut(usize) -> usize) {
+        self.visit_relative_branches_mut(0, &mut remap);
+    }
+
+    fn visit_relative_branches_mut(
+        &mut self,
+        levels: usize,
+        remap: &mut impl FnMut(usize) -> usize,
+    ) {
+        let is_label_node = |name: &str| matches!(name, "block" | "loop" | "if" | "try");
+        let levels = levels + usize::from(is_label_node(&self.name));
+        let mut in_branch = matches!(self.name.as_str(), "br" | "br_if" | "br_table");
+        let mut is_table = self.name == "br_table";
+        let mut flat_levels = 0;
+        for item in &mut self.items {
+            match item {
+                Item::Attribute(attr) => {
+                    let target = attr.parse::<usize>().ok().filter(|_| in_branch);
+                    if let Some(target) = target {
+                        let current = levels + flat_levels;
+                        if target >= current {
+                            *attr = format!("{}", remap(target - current) + current);
+                        }
+                        in_branch = is_table;
+                        continue;
+                    }
+                    if in_branch && attr.starts_with('$') {
+                        in_branch = is_table;
+                        continue;
+                    }
+                    in_branch = matches!(attr.as_str(), "br" | "br_if" | "br_table");
+                    is_table = attr == "br_table";
+                    if is_label_node(attr) {
+                        flat_levels += 1;
+                    } else if attr == "end" {
+                        flat_levels = flat_levels.saturating_sub(1);
+                    }
+                }
+                Item::Node(node) => {
+                    in_branch = false;
+                    node.visit_relative_branches_mut(levels + flat_levels, remap);
+                }
+                _ => {}
+            }
+        }
+    }
+
     /// Returns an iterator that iterates over all nodes in the tree.
     pub fn node_iter_mut(&mut self) -> Walker<'_> {
         Walker {
@@ -89,16 +511,28 @@ impl Node {
     }
 
     /// Appends a new node to the parent node. Node is assumed to be well-formed, i.e. all `depth` values must be set correctly.
-    pub fn append_node(&mut self, mut node: Node) {
+    pub fn append_node(&mut self, node: Node) {
+        self.insert_node(self.items.len(), node);
+    }
+
+    /// Inserts a new node into the parent node at the given item index. Same assumptions as `append_node` apply.
+    pub fn insert_node(&mut self, idx: usize, mut node: Node) {
         node.node_iter_mut().for_each(|node| {
             node.depth += self.depth;
         });
-        self.items.push(Item::Node(node));
+        self.items.insert(idx, Item::Node(node));
     }
 }
 
 impl Display for Node {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for comment in &self.leading_comments {
+            match comment {
+                // Line comments already end in a line break.
+                Item::LineComment(_) => write!(f, "{comment}")?,
+                _ => writeln!(f, "{comment}")?,
+            }
+        }
         write!(
             f,
             "({}{}{})",
@@ -110,7 +544,11 @@ impl Display for Node {
                 .map(|item| format!("{item}"))
                 .collect::<Vec<String>>()
                 .join(" ")
-        )
+        )?;
+        for comment in &self.trailing_comments {
+            write!(f, " {comment}")?;
+        }
+        Ok(())
     }
 }
 
@@ -119,6 +557,10 @@ pub enum Item {
     Nothing,
     Attribute(String),
     Node(Node),
+    /// A `;; ...` comment, without the trailing newline.
+    LineComment(String),
+    /// A `(; ... ;)` comment, including the delimiters.
+    BlockComment(String),
 }
 
 impl Item {
@@ -151,6 +593,11 @@ impl Item {
         matches!(self, Item::Nothing)
     }
 
+    /// Returns true if the item is a line or block comment.
+    pub fn is_comment(&self) -> bool {
+        matches!(self, Item::LineComment(_) | Item::BlockComment(_))
+    }
+
     /// Returns a string only if the item is an attribute.
     pub fn as_attribute(&self) -> Option<&str> {
         match self {
@@ -166,6 +613,26 @@ impl Item {
             _ => None,
         }
     }
+
+    /// Returns key and value if the item is a memarg attribute like `offset=4` or `align=2`.
+    pub fn as_memarg(&self) -> Option<(&str, &str)> {
+        let (key, value) = self.as_attribute()?.split_once('=')?;
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_lowercase()) {
+            return None;
+        }
+        Some((key, value))
+    }
+
+    /// Returns key and value if the item is a memarg attribute with a literal value like `offset=0x10` or `align=2`. Memargs with an expression as value aren’t numeric.
+    pub fn as_numeric_memarg(&self) -> Option<(&str, u64)> {
+        let (key, value) = self.as_memarg()?;
+        let value = value.replace('_', "");
+        let value = match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => value.parse::<u64>(),
+        };
+        Some((key, value.ok()?))
+    }
 }
 
 impl Display for Item {
@@ -173,13 +640,94 @@ impl Display for Item {
         match self {
             Item::Attribute(str) => write!(f, "{str}"),
             Item::Node(node) => write!(f, "{node}"),
+            // Anything after a line comment has to go on the next line.
+            Item::LineComment(comment) => writeln!(f, "{comment}"),
+            Item::BlockComment(comment) => write!(f, "{comment}"),
             Item::Nothing => write!(f, ""),
         }
     }
 }
 
+/// A small, dependency-free generator of random but well-formed trees for roundtrip tests.
+#[cfg(test)]
+pub mod arbitrary {
+    use super::{Item, Node};
+
+    static NAMES: &[&str] = &[
+        "module",
+        "func",
+        "block",
+        "data",
+        "i32.add",
+        "i32.const",
+        "local.get",
+        "a",
+        "b-c",
+    ];
+    static ATTRIBUTES: &[&str] = &[
+        "$x", "$a.b", "0", "42", "0x1F", "-7", "1.5", "i32", "offset=4",
+    ];
+    static STRINGS: &[&str] = &[
+        r#""""#,
+        r#""hello""#,
+        r#""a b""#,
+        r#""\n\t""#,
+        r#""\"quoted\"""#,
+        r#""\\""#,
+        r#""\41\00""#,
+        r#""\u{e9}""#,
+        r#""(;)""#,
+        r#"";; not a comment""#,
+    ];
+
+    /// Xorshift PRNG, so that failures are reproducible from the seed.
+    pub struct Rng(u64);
+
+    impl Rng {
+        pub fn new(seed: u64) -> Rng {
+            Rng(seed.max(1))
+        }
+
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, values: &[&'a str]) -> &'a str {
+            values[self.below(values.len())]
+        }
+    }
+
+    /// Generates a node with up to `max_depth` levels of nesting below it, with depths set like the parser does.
+    pub fn node(rng: &mut Rng, depth: usize, max_depth: usize) -> Node {
+        let num_items = rng.below(5);
+        let items = (0..num_items)
+            .map(|_| match rng.below(3) {
+                0 if depth < max_depth => Item::Node(node(rng, depth + 1, max_depth)),
+                1 => Item::Attribute(rng.pick(STRINGS).to_string()),
+                _ => Item::Attribute(rng.pick(ATTRIBUTES).to_string()),
+            })
+            .collect();
+        Node {
+            name: rng.pick(NAMES).to_string(),
+            depth,
+            items,
+            span: None,
+            leading_comments: vec![],
+            trailing_comments: vec![],
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::{DefKind, Export, IdRef, Item, Node, Visitor};
     use crate::parser::Parser;
     #[test]
     fn node_iter() {
@@ -218,4 +766,253 @@ mod test {
         }
         assert_eq!(&format!("{ast}"), expected)
     }
+
+    struct Unwrapper;
+
+    impl Visitor for Unwrapper {
+        // Replaces `(wrap x)` with `x`, one level per pass.
+        fn visit_node(&mut self, node: &mut Node) -> bool {
+            let mut changed = false;
+            for item in &mut node.items {
+                let inner = match item.as_node() {
+                    Some(child) if child.name == "wrap" && child.items.len() == 1 => {
+                        child.items[0].clone()
+                    }
+                    _ => continue,
+                };
+                *item = inner;
+                changed = true;
+            }
+            changed
+        }
+    }
+
+    #[test]
+    fn walk_mut_reports_changes() {
+        let mut ast = Parser::new("(module (wrap (func)) (func))")
+            .parse()
+            .unwrap();
+        assert!(ast.walk_mut(&mut Unwrapper));
+        assert_eq!(&format!("{ast}"), "(module (func) (func))");
+        assert!(!ast.walk_mut(&mut Unwrapper));
+    }
+
+    #[test]
+    fn walk_mut_until_stable() {
+        let mut ast = Parser::new("(module (wrap (wrap (wrap (func)))))")
+            .parse()
+            .unwrap();
+        assert_eq!(ast.walk_mut_until_stable(&mut Unwrapper), 2);
+        assert_eq!(&format!("{ast}"), "(module (func))");
+    }
+
+    #[test]
+    fn definition_kind() {
+        let ast = Parser::new(
+            r#"
+                (module
+                    (func $f)
+                    (global $g i32 (i32.const 0))
+                    (memory 1)
+                    (table 1 funcref)
+                    (type $t (func))
+                    (elem (i32.const 0) $f)
+                    (data (i32.const 0) "")
+                    (export "f" (func $f)))
+            "#,
+        )
+        .parse()
+        .unwrap();
+        let kinds: Vec<Option<DefKind>> = ast
+            .immediate_node_iter()
+            .map(|node| node.definition_kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                Some(DefKind::Func),
+                Some(DefKind::Global),
+                Some(DefKind::Memory),
+                Some(DefKind::Table),
+                Some(DefKind::Type),
+                Some(DefKind::Elem),
+                Some(DefKind::Data),
+                None
+            ]
+        );
+        let add = Parser::new("(i32.add (i32.const 1) (i32.const 2))")
+            .parse()
+            .unwrap();
+        assert_eq!(add.definition_kind(), None);
+        assert!(!add.is_definition());
+    }
+
+    #[test]
+    fn for_each_attribute_mut() {
+        let mut ast = Parser::new("(module $a (func $b (c (d (e $x $y)) $z)) (f))")
+            .parse()
+            .unwrap();
+        let mut visited = 0;
+        ast.for_each_attribute_mut(|attr| {
+            visited += 1;
+            *attr = attr.to_uppercase();
+        });
+        assert_eq!(visited, 5);
+        assert_eq!(
+            &format!("{ast}"),
+            "(module $A (func $B (c (d (e $X $Y)) $Z)) (f))"
+        );
+    }
+
+    #[test]
+    fn as_memarg() {
+        assert_eq!(
+            Item::Attribute("offset=4".to_string()).as_memarg(),
+            Some(("offset", "4"))
+        );
+        assert_eq!(
+            Item::Attribute("offset=(i32.const 4)".to_string()).as_memarg(),
+            Some(("offset", "(i32.const 4)"))
+        );
+        assert_eq!(Item::Attribute("$x".to_string()).as_memarg(), None);
+        assert_eq!(Item::Attribute(r#""a=b""#.to_string()).as_memarg(), None);
+    }
+
+    #[test]
+    fn numeric_memarg() {
+        let input = "(i32.load offset=4 align=0x1_0 offset=(i32.const 4) (i32.const 0))";
+        let node = Parser::new(input).parse().unwrap();
+        assert_eq!(format!("{node}"), input);
+        let memargs: Vec<_> = node
+            .items
+            .iter()
+            .map(|item| item.as_numeric_memarg())
+            .collect();
+        assert_eq!(
+            memargs,
+            [Some(("offset", 4)), Some(("align", 16)), None, None]
+        );
+    }
+
+    #[test]
+    fn exports() {
+        let ast = Parser::new(
+            r#"
+                (module
+                    (import "env" "log" (func $log))
+                    (func (export "main"))
+                    (memory $mem 1)
+                    (export "memory" (memory $mem)))
+            "#,
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(
+            ast.exports(),
+            [
+                Export {
+                    name: "main".to_string(),
+                    kind: DefKind::Func,
+                    target: "1".to_string(),
+                },
+                Export {
+                    name: "memory".to_string(),
+                    kind: DefKind::Memory,
+                    target: "$mem".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn adjust_relative_branches() {
+        // Contents of a `(block ...)` that is about to be removed.
+        let mut inner = Parser::new(
+            r#"
+                (loop $l
+                    (br 2)
+                    (br_if 0 (i32.const 1))
+                    (br 0)
+                    (br $l)
+                    (block
+                        br_table 0 1 $l 3
+                        block br 4 end
+                        br 3))
+            "#,
+        )
+        .parse()
+        .unwrap();
+        inner.adjust_relative_branches(|target| target - 1);
+        assert_eq!(
+            format!("{inner}"),
+            "(loop $l (br 1) (br_if 0 (i32.const 1)) (br 0) (br $l) (block br_table 0 1 $l 2 block br 3 end br 2))"
+        );
+    }
+
+    #[test]
+    fn clone_with_fresh_ids() {
+        let block = Parser::new(
+            "(block $l (local.set $x (local.get $x)) (br_if $l (i32.const 1)) (call $f) loop $inner br $inner end $inner)",
+        )
+        .parse()
+        .unwrap();
+        let mut counter = 0;
+        let first = block.clone_with_fresh_ids(&mut counter);
+        let second = block.clone_with_fresh_ids(&mut counter);
+        assert_eq!(
+            format!("{first}"),
+            "(block $l_0 (local.set $x (local.get $x)) (br_if $l_0 (i32.const 1)) (call $f) loop $inner_1 br $inner_1 end $inner_1)"
+        );
+        assert_eq!(
+            format!("{second}"),
+            "(block $l_2 (local.set $x (local.get $x)) (br_if $l_2 (i32.const 1)) (call $f) loop $inner_3 br $inner_3 end $inner_3)"
+        );
+
+        let locals =
+            Parser::new("(func $f (param $a i32) (local $b i32) (local.set $b (local.get $a)))")
+                .parse()
+                .unwrap();
+        assert_eq!(
+            format!("{}", locals.clone_with_fresh_ids(&mut counter)),
+            "(func $f (param $a_4 i32) (local $b_5 i32) (local.set $b_5 (local.get $a_4)))"
+        );
+    }
+
+    #[test]
+    fn visit_references() {
+        let mut module = Parser::new(
+            "(module (func $f (param $p i32) (block $l (call $g (local.get $p)) br $l) memory.init $m $d (export \"f\" (func $f))) (elem (i32.const 0) $f))",
+        )
+        .parse()
+        .unwrap();
+        let mut refs = vec![];
+        let result: Result<(), ()> = module.visit_references_mut(&mut |id_ref, id| {
+            refs.push((id_ref, id.clone()));
+            Ok(())
+        });
+        result.unwrap();
+        let func = IdRef::Def(DefKind::Func);
+        assert_eq!(
+            refs,
+            [
+                (IdRef::Def(DefKind::Memory), "$m".to_string()),
+                (IdRef::Def(DefKind::Data), "$d".to_string()),
+                (func, "$g".to_string()),
+                (IdRef::Local, "$p".to_string()),
+                (func, "$f".to_string()),
+                (func, "$f".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut rng = super::arbitrary::Rng::new(0x5eed);
+        for _ in 0..500 {
+            let node = super::arbitrary::node(&mut rng, 0, 4);
+            let printed = format!("{node}");
+            let parsed = Parser::new(&printed).parse().unwrap();
+            assert_eq!(parsed, node, "{printed}");
+        }
+    }
 }
@@ -1,47 +1,224 @@
+use std::ops::{Add, Div, Mul, Sub};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
 use wasm3::WasmType;
 
 use crate::{
-    ast::Node,
+    ast::{Item, Node},
     error::{Result, SWLError},
-    utils,
+    utils::parse_float_literal,
 };
 
-pub trait WasmTypeName {
+pub trait WasmTypeName: Sized {
     fn wasm_type_name() -> &'static str;
+
+    /// Evaluates simple expressions without wasm3. Returns `None` if the expression needs the full runtime.
+    fn fast_eval(expr: &Node) -> Option<Self>;
 }
 
 impl WasmTypeName for i32 {
     fn wasm_type_name() -> &'static str {
         "i32"
     }
+
+    fn fast_eval(expr: &Node) -> Option<Self> {
+        fast_eval_int(expr, "i32").map(|v| v as i32)
+    }
 }
 
 impl WasmTypeName for i64 {
     fn wasm_type_name() -> &'static str {
         "i64"
     }
+
+    fn fast_eval(expr: &Node) -> Option<Self> {
+        fast_eval_int(expr, "i64")
+    }
 }
 
 impl WasmTypeName for f32 {
     fn wasm_type_name() -> &'static str {
         "f32"
     }
+
+    fn fast_eval(expr: &Node) -> Option<Self> {
+        fast_eval_float(expr, "f32")
+    }
 }
 
 impl WasmTypeName for f64 {
     fn wasm_type_name() -> &'static str {
         "f64"
     }
+
+    fn fast_eval(expr: &Node) -> Option<Self> {
+        fast_eval_float(expr, "f64")
+    }
 }
 
-pub fn eval_expr<V: WasmType + WasmTypeName>(node: &Node, prelude: &str) -> Result<V> {
-    let expr = node
-        .items
-        .get(0)
-        .ok_or(SWLError::Simple("Constexpr is missing expression".into()))?;
+/// Returns the operation of a folded instruction of the given type, like `add` for `(i32.add ...)`. Flat instructions are not supported.
+fn typed_op<'a>(node: &'a Node, typ: &str) -> Option<&'a str> {
+    let (prefix, op) = node.name.split_once('.')?;
+    if prefix != typ {
+        return None;
+    }
+    Some(op)
+}
+
+/// Folds integer constants and basic integer arithmetic. Values of `i32` expressions are kept sign-extended.
+fn fast_eval_int(node: &Node, typ: &str) -> Option<i64> {
+    let op = typed_op(node, typ)?;
+    let bits: i64 = if typ == "i32" { 32 } else { 64 };
+    let wrap = |v: i64| if bits == 32 { v as i32 as i64 } else { v };
+    if op == "const" {
+        let literal = node.immediate_attribute_iter().next()?.replace('_', "");
+        let v = literal.parse::<i64>().ok().or_else(|| {
+            literal
+                .parse::<u64>()
+                .ok()
+                .filter(|_| bits == 64)
+                .map(|v| v as i64)
+        })?;
+        if bits == 32 && !(i32::MIN as i64..=u32::MAX as i64).contains(&v) {
+            return None;
+        }
+        return Some(wrap(v));
+    }
+    if node.immediate_attribute_iter().next().is_some() {
+        return None;
+    }
+    let operands: Vec<i64> = node
+        .immediate_node_iter()
+        .map(|operand| fast_eval_int(operand, typ))
+        .collect::<Option<Vec<i64>>>()?;
+    let (a, b) = match operands.as_slice() {
+        [a, b] => (*a, *b),
+        _ => return None,
+    };
+    let shift = (b & (bits - 1)) as u32;
+    let unsigned = |v: i64| {
+        if bits == 32 {
+            v as u32 as u64
+        } else {
+            v as u64
+        }
+    };
+    let v = match op {
+        "add" => a.wrapping_add(b),
+        "sub" => a.wrapping_sub(b),
+        "mul" => a.wrapping_mul(b),
+        "and" => a & b,
+        "or" => a | b,
+        "xor" => a ^ b,
+        "shl" => a.wrapping_shl(shift),
+        "shr_s" => a >> shift,
+        "shr_u" => (unsigned(a) >> shift) as i64,
+        _ => return None,
+    };
+    Some(wrap(v))
+}
+
+/// Float types the fast path can fold.
+trait Float:
+    std::str::FromStr
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+{
+    fn from_f64(v: f64) -> Self;
+}
+
+impl Float for f32 {
+    fn from_f64(v: f64) -> Self {
+        v as f32
+    }
+}
 
+impl Float for f64 {
+    fn from_f64(v: f64) -> Self {
+        v
+    }
+}
+
+/// Folds float constants and basic float arithmetic. Decimal literals are parsed directly, other forms like `0x1.8p3` or `inf` go through `parse_float_literal`.
+fn fast_eval_float<T: Float>(node: &Node, typ: &str) -> Option<T> {
+    let op = typed_op(node, typ)?;
+    if op == "const" {
+        let literal = node.immediate_attribute_iter().next()?.replace('_', "");
+        return literal
+            .parse::<T>()
+            .ok()
+            .or_else(|| parse_float_literal(&literal).map(T::from_f64));
+    }
+    if node.immediate_attribute_iter().next().is_some() {
+        return None;
+    }
+    let mut operands = node
+        .immediate_node_iter()
+        .map(|operand| fast_eval_float::<T>(operand, typ))
+        .collect::<Option<Vec<T>>>()?
+        .into_iter();
+    let (a, b) = match (operands.next(), operands.next(), operands.next()) {
+        (Some(a), Some(b), None) => (a, b),
+        _ => return None,
+    };
+    match op {
+        "add" => Some(a + b),
+        "sub" => Some(a - b),
+        "mul" => Some(a * b),
+        "div" => Some(a / b),
+        _ => None,
+    }
+}
+
+/// Returns the number of values a block-like expression declares via `(result ...)`. Other expressions are assumed to produce a single value.
+fn result_arity(expr: &Node) -> usize {
+    if !matches!(expr.name.as_str(), "block" | "loop" | "if") {
+        return 1;
+    }
+    expr.immediate_node_iter()
+        .filter(|node| node.name == "result")
+        .map(|node| node.immediate_attribute_iter().count())
+        .sum()
+}
+
+/// Evaluates an expression, preferring the built-in fast path over instantiating wasm3. With `offline`, expressions that need wasm3 are rejected. Evaluations in wasm3 that take longer than `timeout` are aborted.
+pub fn eval_expr<V: WasmType + WasmTypeName + Send + 'static>(
+    node: &Node,
+    prelude: &str,
+    offline: bool,
+    timeout: Duration,
+) -> Result<V> {
     let typ = V::wasm_type_name();
 
+    let exprs: Vec<&Item> = node.significant_items().collect();
+    let expr = exprs
+        .first()
+        .ok_or(SWLError::Simple("Constexpr is missing expression".into()))?;
+    let num_values = match exprs.as_slice() {
+        [Item::Node(expr)] => result_arity(expr),
+        _ => exprs.len(),
+    };
+    if num_values != 1 {
+        return Err(SWLError::Simple(format!(
+            "Constexpr must produce a single {typ} value, but produces {num_values}"
+        )));
+    }
+
+    if let Item::Node(expr) = expr {
+        if let Some(value) = V::fast_eval(expr) {
+            return Ok(value);
+        }
+    }
+    if offline {
+        return Err(SWLError::Simple(format!(
+            "Constexpr {expr} requires the wasm3 runtime, which is disabled by --offline-constexpr"
+        )));
+    }
+
     let wat = format!(
         r#"
 					(module
@@ -53,5 +230,41 @@ pub fn eval_expr<V: WasmType + WasmTypeName>(node: &Node, prelude: &str) -> Resu
 			"#
     );
 
-    utils::run_wat::<V>(&wat)
+    // wasm3 can’t be interrupted, so a runaway evaluation is left behind on its thread.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(run_wat::<V>(&wat));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(SWLError::Simple(format!(
+            "Constexpr {expr} didn’t finish within {}ms",
+            timeout.as_millis()
+        ))),
+        Err(RecvTimeoutError::Disconnected) => Err(SWLError::Simple(format!(
+            "Constexpr {expr} crashed the wasm3 runtime"
+        ))),
+    }
+}
+
+fn run_wat<V: wasm3::WasmType>(wat: &str) -> Result<V> {
+    let binary = wat::parse_str(wat).map_err(|err| SWLError::Other(err.into()))?;
+    let init_error = |err| {
+        SWLError::Simple(format!(
+            "constexpr requires the wasm3 runtime, which failed to initialize: {err}"
+        ))
+    };
+    let env = wasm3::Environment::new().map_err(init_error)?;
+    let rt = env.create_runtime(1024).map_err(init_error)?;
+
+    let module =
+        wasm3::Module::parse(&env, binary).map_err(|err| SWLError::Simple(err.to_string()))?;
+    let module = rt
+        .load_module(module)
+        .map_err(|err| SWLError::Simple(err.to_string()))?;
+    let f = module
+        .find_function::<(), V>("main")
+        .map_err(|err| SWLError::Simple(err.to_string()))?;
+    let result = f.call().map_err(|err| SWLError::Simple(err.to_string()))?;
+    Ok(result)
 }
@@ -0,0 +1,382 @@
+use crate::error::{Result, SWLError};
+use crate::features::FEATURES;
+
+/// Longer descriptions of all features, each with a small before/after example.
+static EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "import",
+        r#"Imports the contents of other .wat files into the current module. Every file is
+only imported once, even if multiple files import it. With `(as "alias")`, all ids
+defined in the imported file are prefixed with the alias.
+
+    (module
+        (import "util.wat" (file (as "u")))
+        (func $main (call $u.helper)))
+    ;; util.wat: (module (func $helper))
+    ;; becomes
+    (module
+        (func $main (call $u.helper))
+        (func $u.helper))"#,
+    ),
+    (
+        "check_import_sigs",
+        r#"Reports an error when the same host import is declared with different signatures,
+which easily happens when multiple files import the same function. Ids are ignored
+when comparing signatures.
+
+    (module
+        (import "env" "log" (func $log (param i32)))
+        (import "env" "log" (func $log2 (param i64))))
+    ;; fails, as "env" "log" is imported with two signatures"#,
+    ),
+    (
+        "check_exports",
+        r#"Reports an error listing all exports that refer to a missing definition, for
+example because an earlier feature removed it. List it after features like tree_shake.
+
+    (module
+        (export "helper" (func $helper)))
+    ;; fails with "Exports refer to missing definitions: "helper" (func $helper)""#,
+    ),
+    (
+        "check_indirect",
+        r#"Reports an error when a function placed into a table by an active elem segment
+doesn’t match the type of a call_indirect on that table, which would trap at runtime.
+
+    (module
+        (table 1 funcref)
+        (elem (i32.const 0) $neg)
+        (func $neg (param f32) (result f32) (f32.neg (local.get 0)))
+        (func (result i32) (call_indirect (type $binop) ...)))
+    ;; fails, as $neg has (param f32) (result f32)"#,
+    ),
+    (
+        "check_known_sections",
+        r#"Reports top-level nodes that aren’t Wasm sections or definitions, which catches
+misspelled keywords. swl.* directives and annotations are accepted. Enabled by --strict.
+
+    (module (fucn $x))
+    ;; fails with "Unknown top-level nodes: (fucn ...)""#,
+    ),
+    (
+        "check_unused_imports",
+        r#"Reports an error listing all imported functions and globals that are never
+referenced. Imports that are exported count as used.
+
+    (module
+        (import "env" "log" (func $log (param i32)))
+        (func $main))
+    ;; fails with "Unused imports: $log""#,
+    ),
+    (
+        "sort",
+        r#"Moves all imports to the front of the module, as `wat2wasm` requires imports to
+come first.
+
+    (module
+        (func $main)
+        (import "env" "log" (func $log (param i32))))
+    ;; becomes
+    (module
+        (import "env" "log" (func $log (param i32)))
+        (func $main))"#,
+    ),
+    (
+        "align_data",
+        r#"Rounds the offset of data segments marked with `(swl.align N)` up to the next
+multiple of N. With `--option align_data.misaligned=error`, misaligned offsets are
+reported instead.
+
+    (data (swl.align 16) (i32.const 10) "My data")
+    ;; becomes
+    (data (i32.const 16) "My data")"#,
+    ),
+    (
+        "size_adjust",
+        r#"Grows `memory` directives to be big enough to hold all active data segments.
+
+    (module
+        (memory 1)
+        (data (i32.const 65536) "x"))
+    ;; becomes
+    (module
+        (memory 2)
+        (data (i32.const 65536) "x"))"#,
+    ),
+    (
+        "assert_memory",
+        r#"Checks that the final memory limits are exactly the asserted ones. Runs after
+`size_adjust`, so accidental memory growth is caught. The directive is removed.
+
+    (module
+        (swl.assert-memory 2 2)
+        (memory 2 2))
+    ;; becomes
+    (module
+        (memory 2 2))"#,
+    ),
+    (
+        "table_adjust",
+        r#"Grows the minimum size of the first funcref table to be big enough to hold all
+active elem segments. Fails if the table's maximum is too small.
+
+    (module
+        (table 1 funcref)
+        (elem (i32.const 1) $f))
+    ;; becomes
+    (module
+        (table 2 funcref)
+        (elem (i32.const 1) $f))"#,
+    ),
+    (
+        "passive_data",
+        r#"Converts active data segments into passive ones that are initialized with
+`memory.init` and `data.drop` in a generated start function, which calls an
+existing start function last. List it after `size_adjust`.
+
+    (module
+        (data (i32.const 16) "abc"))
+    ;; becomes
+    (module
+        (data $_swl_passive_data_0 "abc")
+        (func $_swl_passive_data_init
+            (memory.init $_swl_passive_data_0 (i32.const 16) (i32.const 0) (i32.const 3))
+            (data.drop $_swl_passive_data_0))
+        (start $_swl_passive_data_init))"#,
+    ),
+    (
+        "start_merge",
+        r#"Merges multiple `(start)` directives, as they easily occur in multi-file projects,
+into a single start function that calls all of them in order.
+
+    (module
+        (func $a) (start $a)
+        (func $b) (start $b))
+    ;; becomes
+    (module
+        (func $a)
+        (func $b)
+        (func $_swl_start_merger (call $a) (call $b))
+        (start $_swl_start_merger))"#,
+    ),
+    (
+        "data_import",
+        r#"Embeds the raw contents of another file as a data segment. Paths are resolved like
+file imports.
+
+    (data (i32.const 0) (import "data.bin" (raw)))
+    ;; data.bin contains the bytes 0x41 0x42
+    ;; becomes
+    (data (i32.const 0) "\41\42")"#,
+    ),
+    (
+        "define_globals",
+        r#"Adds an immutable global for every --define with a numeric value. Defines that
+already have a global are skipped.
+
+    ;; --define SIZE=16
+    (module (func (result i32) (global.get $SIZE)))
+    ;; becomes
+    (module (global $SIZE i32 (i32.const 16)) (func (result i32) (global.get $SIZE)))"#,
+    ),
+    (
+        "template",
+        r#"Replaces ${KEY} tokens with the values of --define KEY=VALUE in every file before
+it is parsed. Tokens without a value are an error.
+
+    ;; --define NAME=main
+    (module (func (export "${NAME}")))
+    ;; becomes
+    (module (func (export "main")))"#,
+    ),
+    (
+        "constexpr",
+        r#"Evaluates expressions at compile time. Globals, types and pure functions of the
+module are available. Also works for the offset and align of loads and stores.
+`(swl.heap-base)` evaluates to the first address after all active data segments.
+
+    (module
+        (global $BASE i32 (i32.const 0x4000))
+        (data (i32.constexpr (i32.add (global.get $BASE) (i32.const 0x100))) "x"))
+    ;; becomes
+    (module
+        (global $BASE i32 (i32.const 0x4000))
+        (data (i32.const 16640) "x"))"#,
+    ),
+    (
+        "data_dedupe",
+        r#"Removes active data segments with identical bytes at the same offset, and merges
+identical passive segments, rewriting `memory.init` and `data.drop` references.
+
+    (module
+        (data (i32.const 0) "\01\02")
+        (data (i32.const 0) "\01" "\02"))
+    ;; becomes
+    (module
+        (data (i32.const 0) "\01\02"))"#,
+    ),
+    (
+        "declare_refs",
+        r#"Declares all functions that are used with `ref.func` in function bodies in an
+`(elem declare func ...)` segment, as validation requires. Functions that are in an
+elem segment, exported or referenced by a global already are left out.
+
+    (module
+        (func $f)
+        (func $g (drop (ref.func $f))))
+    ;; becomes
+    (module
+        (func $f)
+        (func $g (drop (ref.func $f)))
+        (elem declare func $f))"#,
+    ),
+    (
+        "tree_shake",
+        r#"Removes functions, globals and types that nothing refers to. Exports, the start
+function, memories, tables and segments are kept along with everything they use. With
+`--only-export NAME`, only that export is kept.
+
+    (module
+        (func $main (export "main") (call $helper))
+        (func $helper)
+        (func $unused))
+    ;; becomes
+    (module
+        (func $main (export "main") (call $helper))
+        (func $helper))"#,
+    ),
+    (
+        "split_data",
+        r#"Splits active data segments bigger than `split_data.max_size` bytes (65536 by
+default) into contiguous segments with adjusted offsets, without cutting escape
+sequences in half.
+
+    ;; with --option split_data.max_size=2
+    (data (i32.const 0) "abc")
+    ;; becomes
+    (data (i32.const 0) "ab")
+    (data (i32.const 2) "c")"#,
+    ),
+    (
+        "numerals",
+        r#"Rewrites hexadecimal and binary integer literals to decimal. Binary literals are
+not part of WAT, so this makes them usable everywhere.
+
+    (i32.and (local.get 0) (i32.const 0b101))
+    (i32.const 0x10)
+    ;; becomes
+    (i32.and (local.get 0) (i32.const 5))
+    (i32.const 16)"#,
+    ),
+    (
+        "repeat",
+        r#"Expands `(swl.repeat N ...)` into N copies of its body. `$swl.index` is replaced
+with the index of the current copy.
+
+    (func (swl.repeat 2 (call $f (i32.const $swl.index))))
+    ;; becomes
+    (func (call $f (i32.const 0)) (call $f (i32.const 1)))"#,
+    ),
+    (
+        "lower_ids",
+        r#"Rewrites symbolic ids to numeric indices and removes them from definitions, for
+runtimes that only support index-based WAT. Labels become relative branch depths.
+
+    (module
+        (func $main (call $f))
+        (func $f))
+    ;; becomes
+    (module
+        (func (call 1))
+        (func))"#,
+    ),
+    (
+        "simplify_control",
+        r#"Removes blocks that only wrap a single node and that no branch can target.
+Numeric branches that cross a removed block are adjusted.
+
+    (func (block (block $inner (br $inner))))
+    ;; becomes
+    (func (block $inner (br $inner)))"#,
+    ),
+    (
+        "hexify",
+        r#"Rewrites large and mask-like integer constants to hexadecimal, grouped every 4
+digits. See `hexify.threshold` and `hexify.skip_offsets` for options.
+
+    (i32.const 4026531840)
+    ;; becomes
+    (i32.const 0xF000_0000)"#,
+    ),
+    (
+        "producers",
+        r#"Adds the linker to the producers annotation of the module, without ever listing
+it twice.
+
+    (module)
+    ;; becomes
+    (module (@producers (processed-by "silly-wat-linker" "<version>")))"#,
+    ),
+    (
+        "modernize",
+        r#"Rewrites legacy instruction names that current tools reject to their modern
+equivalents.
+
+    (get_local $x)
+    (i32.trunc_s/f32 (f32.const 1))
+    ;; becomes
+    (local.get $x)
+    (i32.trunc_f32_s (f32.const 1))"#,
+    ),
+];
+
+/// Number of single-character edits needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Returns the explanation of a feature. Unknown names fail with a suggestion of the closest feature name.
+pub fn explain(name: &str) -> Result<&'static str> {
+    if let Some((_, explanation)) = EXPLANATIONS.iter().find(|(key, _)| *key == name) {
+        return Ok(explanation);
+    }
+    let closest = FEATURES
+        .iter()
+        .map(|(key, _)| *key)
+        .min_by_key(|key| edit_distance(name, key))
+        .unwrap();
+    Err(SWLError::Simple(format!(
+        "Unknown feature {name}, did you mean {closest}?"
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn all_features_explained() {
+        for (name, _) in FEATURES {
+            assert!(explain(name).is_ok(), "{name} has no explanation");
+        }
+    }
+
+    #[test]
+    fn suggests_closest_feature() {
+        let err = explain("constexp").unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something went wrong: Unknown feature constexp, did you mean constexpr?"
+        );
+    }
+}
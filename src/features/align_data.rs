@@ -0,0 +1,161 @@
+use thiserror::Error;
+
+use crate::ast::{Item, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils::{self, parse_number_literal};
+
+#[derive(Error, Debug)]
+pub enum AlignDataError {
+    #[error("Data aligner can only be applied to top-level modules")]
+    NotAModule,
+    #[error("swl.align expected a power of two, got {0}")]
+    InvalidAlignment(String),
+    #[error("swl.align needs an active data segment with a constant i32 offset")]
+    MissingOffset,
+    #[error("Data segment offset {offset} is not aligned to {alignment} bytes")]
+    Misaligned { offset: usize, alignment: usize },
+    #[error("Invalid value for option {0}: {1}, expected round or error")]
+    InvalidOption(String, String),
+}
+
+impl From<AlignDataError> for SWLError {
+    fn from(val: AlignDataError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+static SWL_ALIGN_NAME: &str = "swl.align";
+static MISALIGNED_OPTION: &str = "align_data.misaligned";
+
+fn is_align_item(item: &Item) -> bool {
+    item.as_node()
+        .map(|node| node.name == SWL_ALIGN_NAME)
+        .unwrap_or(false)
+}
+
+fn alignment(align_node: &Node) -> Result<usize> {
+    let attr = align_node
+        .immediate_attribute_iter()
+        .next()
+        .ok_or_else(|| AlignDataError::InvalidAlignment(format!("{align_node}")))?;
+    parse_number_literal(attr)
+        .ok()
+        .and_then(|v| usize::try_from(v).ok())
+        .filter(|v| v.is_power_of_two())
+        .ok_or_else(|| AlignDataError::InvalidAlignment(attr.to_string()).into())
+}
+
+/// Returns the literal of the `i32.const` offset of a data segment. Handles both `(i32.const N)` and `(offset (i32.const N))`.
+fn offset_literal(data_seg: &mut Node) -> Option<&mut String> {
+    let offset_node = data_seg
+        .immediate_node_iter_mut()
+        .find(|node| node.name == "offset" || node.name == "i32.const")?;
+    let const_node = if offset_node.name == "offset" {
+        offset_node
+            .immediate_node_iter_mut()
+            .find(|node| node.name == "i32.const")?
+    } else {
+        offset_node
+    };
+    const_node.immediate_attribute_iter_mut().next()
+}
+
+/// Returns the alignment a data segment requests with `(swl.align N)`, if any.
+pub fn requested_alignment(data_seg: &Node) -> Result<Option<usize>> {
+    data_seg
+        .immediate_node_iter()
+        .find(|node| node.name == SWL_ALIGN_NAME)
+        .map(alignment)
+        .transpose()
+}
+
+fn align_data_segment(data_seg: &mut Node, round: bool) -> Result<()> {
+    let align_node = match data_seg.items.iter().position(is_align_item) {
+        Some(idx) => data_seg.items.remove(idx).into_node(),
+        None => return Ok(()),
+    };
+    let alignment = alignment(&align_node)?;
+    let literal =
+        offset_literal(data_seg).ok_or::<SWLError>(AlignDataError::MissingOffset.into())?;
+    let offset = parse_number_literal(&*literal)
+        .ok()
+        .and_then(|v| usize::try_from(v).ok())
+        .ok_or::<SWLError>(AlignDataError::MissingOffset.into())?;
+    if offset % alignment == 0 {
+        return Ok(());
+    }
+    if !round {
+        return Err(AlignDataError::Misaligned { offset, alignment }.into());
+    }
+    *literal = format!("{}", offset.next_multiple_of(alignment));
+    Ok(())
+}
+
+pub fn align_data(module: &mut Node, linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(AlignDataError::NotAModule.into());
+    }
+    let round = match linker.option(MISALIGNED_OPTION) {
+        None | Some("round") => true,
+        Some("error") => false,
+        Some(other) => {
+            return Err(AlignDataError::InvalidOption(
+                MISALIGNED_OPTION.to_string(),
+                other.to_string(),
+            )
+            .into())
+        }
+    };
+    for data_seg in module
+        .immediate_node_iter_mut()
+        .filter(|node| node.name == "data")
+    {
+        align_data_segment(data_seg, round)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_test(input: &str, options: &[(&str, &str)]) -> Result<Node> {
+        let mut linker = Linker::default();
+        linker.features.push(("align_data", align_data));
+        for (key, value) in options {
+            linker.options.insert(key.to_string(), value.to_string());
+        }
+        linker.link_raw(input)
+    }
+
+    #[test]
+    fn rounds_up_offset() {
+        let module = run_test(
+            r#"
+                (module
+                    (data (swl.align 16) (i32.const 10) "a")
+                    (data (swl.align 8) (offset (i32.const 0x20)) "b"))
+            "#,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            format!("{module}"),
+            r#"(module (data (i32.const 16) "a") (data (offset (i32.const 0x20)) "b"))"#
+        );
+    }
+
+    #[test]
+    fn misaligned_error() {
+        let err = run_test(
+            r#"(module (data (swl.align 16) (i32.const 10) "a"))"#,
+            &[(MISALIGNED_OPTION, "error")],
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Data segment offset 10 is not aligned to 16 bytes"
+        );
+    }
+}
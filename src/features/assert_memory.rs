@@ -0,0 +1,156 @@
+use thiserror::Error;
+
+use crate::ast::Node;
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils::{self, parse_number_literal};
+
+#[derive(Error, Debug)]
+pub enum AssertMemoryError {
+    #[error("Memory assertions can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Memory assertion expects a minimum and an optional maximum number of pages, got {0}")]
+    InvalidAssertion(String),
+    #[error("Memory assertion found no memory in the module")]
+    NoMemory,
+    #[error("Memory limits {actual} don’t match asserted limits {expected}")]
+    Mismatch { expected: String, actual: String },
+}
+
+impl From<AssertMemoryError> for SWLError {
+    fn from(val: AssertMemoryError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+static SWL_ASSERT_MEMORY_NAME: &str = "swl.assert-memory";
+
+#[derive(PartialEq, Eq)]
+struct Limits {
+    min: isize,
+    max: Option<isize>,
+}
+
+impl std::fmt::Display for Limits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) => write!(f, "{} {}", self.min, max),
+            None => write!(f, "{}", self.min),
+        }
+    }
+}
+
+/// Reads the numeric limits from a node, skipping ids and nested nodes like inline exports.
+fn limits(node: &Node) -> Option<Limits> {
+    let numbers: Vec<isize> = node
+        .immediate_attribute_iter()
+        .filter(|attr| !attr.starts_with('$'))
+        .map(|attr| parse_number_literal(attr).ok())
+        .collect::<Option<Vec<isize>>>()?;
+    match numbers.as_slice() {
+        [min] => Some(Limits {
+            min: *min,
+            max: None,
+        }),
+        [min, max] => Some(Limits {
+            min: *min,
+            max: Some(*max),
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the memory node of a module, including imported memories.
+fn find_memory(module: &Node) -> Option<&Node> {
+    module
+        .immediate_node_iter()
+        .find_map(|node| match node.name.as_str() {
+            "memory" => Some(node),
+            "import" => node
+                .immediate_node_iter()
+                .last()
+                .filter(|descriptor| descriptor.name == "memory"),
+            _ => None,
+        })
+}
+
+pub fn assert_memory(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(AssertMemoryError::NotAModule.into());
+    }
+    let assertions: Vec<Node> = module
+        .immediate_node_iter()
+        .filter(|node| node.name == SWL_ASSERT_MEMORY_NAME)
+        .cloned()
+        .collect();
+    if assertions.is_empty() {
+        return Ok(());
+    }
+    module.items.retain(|item| {
+        item.as_node()
+            .map(|node| node.name != SWL_ASSERT_MEMORY_NAME)
+            .unwrap_or(true)
+    });
+
+    let memory = find_memory(module).ok_or::<SWLError>(AssertMemoryError::NoMemory.into())?;
+    for assertion in assertions {
+        let expected = limits(&assertion)
+            .ok_or_else(|| AssertMemoryError::InvalidAssertion(format!("{assertion}")))?;
+        let actual = limits(memory);
+        if actual.as_ref() != Some(&expected) {
+            return Err(AssertMemoryError::Mismatch {
+                expected: format!("{expected}"),
+                actual: actual
+                    .map(|actual| format!("{actual}"))
+                    .unwrap_or_else(|| format!("{memory}")),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::features::size_adjust::size_adjust;
+    use crate::features::Feature;
+
+    fn run_test<T: AsRef<str>>(input: T) -> Result<Node> {
+        let mut linker = Linker::default();
+        linker.features.extend([
+            ("size_adjust", size_adjust as Feature),
+            ("assert_memory", assert_memory),
+        ]);
+        linker.link_raw(input)
+    }
+
+    fn module_with_data(num_bytes: usize) -> String {
+        format!(
+            r#"
+                (module
+                    (swl.assert-memory 2 2)
+                    (memory $mem 1 2)
+                    (data (i32.const 0) "{}"))
+            "#,
+            "x".repeat(num_bytes)
+        )
+    }
+
+    #[test]
+    fn matching_limits() {
+        let module = run_test(module_with_data(64 * 1024 + 1)).unwrap();
+        assert!(module
+            .immediate_node_iter()
+            .all(|node| node.name != SWL_ASSERT_MEMORY_NAME));
+    }
+
+    #[test]
+    fn mismatching_limits() {
+        let err = run_test(module_with_data(1)).unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Memory limits 1 2 don’t match asserted limits 2 2"
+        );
+    }
+}
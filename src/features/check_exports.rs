@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::ast::{DefKind, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum CheckExportsError {
+    #[error("Export checker can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Exports refer to missing definitions: {}", .0.join(", "))]
+    DanglingExports(Vec<String>),
+}
+
+impl From<CheckExportsError> for SWLError {
+    fn from(val: CheckExportsError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+/// Returns the definition a top-level node declares, looking through `(import ...)`.
+fn declared_definition(node: &Node) -> Option<&Node> {
+    if node.name == "import" {
+        return node.immediate_node_iter().find(|node| node.is_definition());
+    }
+    Some(node).filter(|node| node.is_definition())
+}
+
+/// Reports exports whose id or index doesn’t refer to any definition, for example because another feature removed it.
+pub fn check_exports(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(CheckExportsError::NotAModule.into());
+    }
+
+    let mut counts: HashMap<DefKind, usize> = HashMap::new();
+    let mut ids: Vec<(DefKind, &str)> = vec![];
+    for def in module.immediate_node_iter().filter_map(declared_definition) {
+        let kind = def.definition_kind().unwrap();
+        *counts.entry(kind).or_default() += 1;
+        if let Some(id) = def
+            .immediate_attribute_iter()
+            .find(|attr| attr.starts_with('$'))
+        {
+            ids.push((kind, id));
+        }
+    }
+
+    let dangling: Vec<String> = module
+        .exports()
+        .into_iter()
+        .filter(|export| {
+            if export.target.starts_with('$') {
+                return !ids.contains(&(export.kind, export.target.as_str()));
+            }
+            let count = counts.get(&export.kind).copied().unwrap_or(0);
+            !matches!(export.target.parse::<usize>(), Ok(idx) if idx < count)
+        })
+        .map(|export| {
+            format!(
+                "\"{}\" ({} {})",
+                export.name,
+                export.kind.name(),
+                export.target
+            )
+        })
+        .collect();
+    if !dangling.is_empty() {
+        return Err(CheckExportsError::DanglingExports(dangling).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::features::Feature;
+
+    fn run_test(input: &str, features: &[(&'static str, Feature)]) -> Result<Node> {
+        let mut linker = Linker::default();
+        linker.features.extend_from_slice(features);
+        linker.features.push(("check_exports", check_exports));
+        linker.link_raw(input)
+    }
+
+    fn remove_helper(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+        module.items.retain(|item| {
+            item.as_node()
+                .map(|node| {
+                    !node
+                        .immediate_attribute_iter()
+                        .any(|attr| attr == "$helper")
+                })
+                .unwrap_or(true)
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn existing_exports() {
+        run_test(
+            r#"
+                (module
+                    (import "env" "log" (func $log (param i32)))
+                    (func $helper)
+                    (memory 1)
+                    (export "log" (func $log))
+                    (export "helper" (func 1))
+                    (export "memory" (memory 0))
+                    (func (export "main")))
+            "#,
+            &[],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn dangling_export_after_removal() {
+        let err = run_test(
+            r#"
+                (module
+                    (func $helper)
+                    (func $main)
+                    (export "helper" (func $helper))
+                    (export "main" (func $main))
+                    (export "memory" (memory 0)))
+            "#,
+            &[("remove_helper", remove_helper)],
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            r#"Something else went wrong: Exports refer to missing definitions: "helper" (func $helper), "memory" (memory 0)"#
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::ast::{Item, Node};
+use crate::error::{Result, SWLError};
+use crate::features::check_indirect::Signature;
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum CheckImportSigsError {
+    #[error("Import signature checker can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Conflicting signatures for import {module} {field}: {first} vs {second}")]
+    SignatureMismatch {
+        module: String,
+        field: String,
+        first: String,
+        second: String,
+    },
+}
+
+impl From<CheckImportSigsError> for SWLError {
+    fn from(val: CheckImportSigsError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+/// The signature of an imported item. Functions are compared by their resolved params and results. Other items are compared by their text without ids, comments and inline import and export directives.
+#[derive(PartialEq, Eq)]
+enum ImportSignature {
+    Func(Signature),
+    Other(String),
+}
+
+impl ImportSignature {
+    fn of(module: &Node, descriptor: &Node) -> ImportSignature {
+        if descriptor.name == "func" {
+            return ImportSignature::Func(Signature::of(module, descriptor));
+        }
+        let mut descriptor = descriptor.clone();
+        descriptor.items.retain(|item| {
+            item.as_node()
+                .map(|node| node.name != "import" && node.name != "export")
+                .unwrap_or(true)
+        });
+        strip_ids_and_comments(&mut descriptor);
+        ImportSignature::Other(format!("{descriptor}"))
+    }
+}
+
+impl std::fmt::Display for ImportSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportSignature::Func(sig)
+                if sig.params.is_empty()
+                    && sig.results.is_empty()
+                    && sig.unresolved_type.is_none() =>
+            {
+                write!(f, "(func)")
+            }
+            ImportSignature::Func(sig) => write!(f, "(func {sig})"),
+            ImportSignature::Other(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+fn strip_ids_and_comments(node: &mut Node) {
+    node.items.retain(|item| match item {
+        Item::Attribute(attr) => !attr.starts_with('$'),
+        item => !item.is_comment(),
+    });
+    node.immediate_node_iter_mut()
+        .for_each(strip_ids_and_comments);
+}
+
+/// Returns the module name, field name and signature of an import, if the node is one. Handles both `(import "mod" "field" (func ...))` and `(func (import "mod" "field") ...)`.
+fn import_signature(module: &Node, node: &Node) -> Option<(String, String, ImportSignature)> {
+    let (import_node, descriptor) = if node.name == "import" {
+        (node, node.immediate_node_iter().last()?)
+    } else {
+        (
+            node.immediate_node_iter()
+                .find(|node| node.name == "import")?,
+            node,
+        )
+    };
+    let mut names = import_node.immediate_attribute_iter();
+    let module_name = names.next()?.to_string();
+    let field = names.next()?.to_string();
+    Some((module_name, field, ImportSignature::of(module, descriptor)))
+}
+
+pub fn check_import_sigs(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(CheckImportSigsError::NotAModule.into());
+    }
+    let mut signatures: HashMap<(String, String), ImportSignature> = HashMap::new();
+    for (module_name, field, sig) in module
+        .immediate_node_iter()
+        .flat_map(|node| import_signature(module, node))
+    {
+        let key = (module_name, field);
+        match signatures.get(&key) {
+            Some(first) if *first != sig => {
+                return Err(CheckImportSigsError::SignatureMismatch {
+                    module: key.0,
+                    field: key.1,
+                    first: first.to_string(),
+                    second: sig.to_string(),
+                }
+                .into())
+            }
+            Some(_) => {}
+            None => {
+                signatures.insert(key, sig);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_test<T: AsRef<str>>(input: T) -> Result<Node> {
+        let mut linker = Linker::default();
+        linker
+            .features
+            .push(("check_import_sigs", check_import_sigs));
+        linker.link_raw(input)
+    }
+
+    #[test]
+    fn matching_imports() {
+        run_test(
+            r#"
+                (module
+                    (import "env" "log" (func $log (param $v i32)))
+                    (func $log2 (import "env" "log") (param i32)))
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn normalized_signatures() {
+        run_test(
+            r#"
+                (module
+                    (type $log (func (param i32 i64)))
+                    (import "env" "log" (func $a (param i32) (param i64)))
+                    (import "env" "log" (func $b (type $log)))
+                    (import "env" "log" (func $c (param i32 i64) ;; comment
+                    ))
+                    (import "env" "mem" (memory 1))
+                    (import "env" "mem" (memory (; comment ;) 1)))
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn distinct_type_uses() {
+        let err = run_test(
+            r#"
+                (module
+                    (type $a (func (param i32)))
+                    (type $b (func (param f32)))
+                    (import "env" "log" (func (type $a)))
+                    (import "env" "log" (func (type $b))))
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            r#"Something else went wrong: Conflicting signatures for import "env" "log": (func (param i32)) vs (func (param f32))"#
+        );
+
+        let err = run_test(
+            r#"
+                (module
+                    (import "env" "log" (func (type $a)))
+                    (import "env" "log" (func (type $b))))
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            r#"Something else went wrong: Conflicting signatures for import "env" "log": (func (type $a)) vs (func (type $b))"#
+        );
+    }
+
+    #[test]
+    fn conflicting_imports() {
+        let err = run_test(
+            r#"
+                (module
+                    (import "env" "log" (func $log (param i32)))
+                    (import "env" "log" (func $log2 (param i64))))
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            r#"Something else went wrong: Conflicting signatures for import "env" "log": (func (param i32)) vs (func (param i64))"#
+        );
+    }
+}
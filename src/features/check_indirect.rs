@@ -0,0 +1,290 @@
+use thiserror::Error;
+
+use crate::ast::{Item, Node};
+use crate::error::{Result, SWLError};
+use crate::features::start_merge::{find_func, find_type, signature_nodes};
+use crate::linker::Linker;
+use crate::utils::{self, find_id_attribute};
+
+#[derive(Error, Debug)]
+pub enum CheckIndirectError {
+    #[error("Indirect call checker can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Table entries don’t match their call_indirect types: {0}")]
+    SignatureMismatch(String),
+}
+
+impl From<CheckIndirectError> for SWLError {
+    fn from(val: CheckIndirectError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+/// A signature with ids stripped, so `(param $x i32) (param i32)` equals `(param i32 i32)`. A type use that can’t be resolved is kept as is, so it only equals the same type use.
+#[derive(PartialEq, Eq)]
+pub(crate) struct Signature {
+    pub(crate) params: Vec<String>,
+    pub(crate) results: Vec<String>,
+    pub(crate) unresolved_type: Option<String>,
+}
+
+impl Signature {
+    pub(crate) fn of(module: &Node, node: &Node) -> Signature {
+        let nodes = signature_nodes(module, node);
+        let unresolved_type = node
+            .immediate_node_iter()
+            .find(|child| child.name == "type")
+            .and_then(|child| child.immediate_attribute_iter().next())
+            .filter(|_| nodes.is_empty())
+            .filter(|type_id| find_type(module, type_id).is_none())
+            .map(|type_id| type_id.to_string());
+        let types = |name: &str| {
+            nodes
+                .iter()
+                .filter(|node| node.name == name)
+                .flat_map(|node| node.immediate_attribute_iter())
+                .filter(|attr| !attr.starts_with('$'))
+                .map(|attr| attr.to_string())
+                .collect()
+        };
+        Signature {
+            params: types("param"),
+            results: types("result"),
+            unresolved_type,
+        }
+    }
+}
+
+impl std::fmt::Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some(type_id) = &self.unresolved_type {
+            parts.push(format!("(type {type_id})"));
+        }
+        if !self.params.is_empty() {
+            parts.push(format!("(param {})", self.params.join(" ")));
+        }
+        if !self.results.is_empty() {
+            parts.push(format!("(result {})", self.results.join(" ")));
+        }
+        if parts.is_empty() {
+            return write!(f, "no params and results");
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+fn is_func_ref(attr: &str) -> bool {
+    attr.starts_with('$') || attr.parse::<u32>().is_ok()
+}
+
+/// Resolves a table id or index to its index. Imported tables come first.
+fn table_index(module: &Node, table: &str) -> Option<usize> {
+    if let Ok(idx) = table.parse::<usize>() {
+        return Some(idx);
+    }
+    let is_imported = |node: &Node| {
+        node.immediate_node_iter()
+            .any(|child| child.name == "import")
+    };
+    let imported = module
+        .immediate_node_iter()
+        .filter_map(|node| match node.name.as_str() {
+            "import" => node
+                .immediate_node_iter()
+                .find(|child| child.name == "table"),
+            "table" if is_imported(node) => Some(node),
+            _ => None,
+        });
+    let defined = module
+        .immediate_node_iter()
+        .filter(|node| node.name == "table" && !is_imported(node));
+    imported
+        .chain(defined)
+        .position(|node| find_id_attribute(node) == Some(table))
+}
+
+/// Returns the functions listed in an elem segment, as function indices after the offset or as `ref.func` expressions.
+fn listed_funcs(elem: &Node) -> Vec<&str> {
+    let mut funcs = vec![];
+    let mut after_offset = false;
+    for item in elem.significant_items() {
+        match item {
+            Item::Node(node) if node.name == "ref.func" || node.name == "item" => funcs.extend(
+                node.node_iter()
+                    .filter(|node| node.name == "ref.func")
+                    .filter_map(|node| node.immediate_attribute_iter().next()),
+            ),
+            Item::Node(node) if node.name != "table" => after_offset = true,
+            Item::Attribute(attr) if after_offset && is_func_ref(attr) => funcs.push(attr),
+            _ => {}
+        }
+    }
+    funcs
+}
+
+/// Returns the table index and functions of every active elem segment, including those inlined into a `table`.
+fn table_entries(module: &Node) -> Vec<(usize, Vec<&str>)> {
+    let mut entries = vec![];
+    let mut table_idx = 0;
+    for node in module.immediate_node_iter() {
+        match node.name.as_str() {
+            "elem" => {
+                let is_passive_or_declared = !node
+                    .immediate_node_iter()
+                    .any(|child| child.name != "ref.func" && child.name != "item");
+                if is_passive_or_declared {
+                    continue;
+                }
+                let table = node
+                    .immediate_node_iter()
+                    .find(|child| child.name == "table")
+                    .and_then(|table| table.immediate_attribute_iter().next())
+                    .and_then(|table| table_index(module, table))
+                    .unwrap_or(0);
+                entries.push((table, listed_funcs(node)));
+            }
+            "table" => {
+                if let Some(elem) = node
+                    .immediate_node_iter()
+                    .find(|child| child.name == "elem")
+                {
+                    let funcs = elem
+                        .immediate_attribute_iter()
+                        .filter(|attr| is_func_ref(attr))
+                        .collect();
+                    entries.push((table_idx, funcs));
+                }
+                table_idx += 1;
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// Returns the table and the node holding the type use of every `call_indirect`, both folded and flat.
+fn indirect_calls(module: &Node) -> Vec<(String, Node)> {
+    let mut calls = vec![];
+    for node in module.node_iter() {
+        if node.name == "call_indirect" {
+            let table = node.immediate_attribute_iter().next().unwrap_or("0");
+            calls.push((table.to_string(), node.clone()));
+        }
+        let items: Vec<&Item> = node.significant_items().collect();
+        for (idx, item) in items.iter().enumerate() {
+            if item.as_attribute() != Some("call_indirect") {
+                continue;
+            }
+            let mut table = "0";
+            let mut type_use = Node {
+                name: "call_indirect".to_string(),
+                depth: node.depth + 1,
+                items: vec![],
+                span: None,
+                leading_comments: vec![],
+                trailing_comments: vec![],
+            };
+            for item in &items[idx + 1..] {
+                match item {
+                    Item::Attribute(attr) if is_func_ref(attr) && type_use.items.is_empty() => {
+                        table = attr
+                    }
+                    Item::Node(child)
+                        if matches!(child.name.as_str(), "type" | "param" | "result") =>
+                    {
+                        type_use.items.push((*item).clone())
+                    }
+                    _ => break,
+                }
+            }
+            calls.push((table.to_string(), type_use));
+        }
+    }
+    calls
+}
+
+/// Checks that every function placed into a table by an active elem segment has the signature of each `call_indirect` on that table.
+pub fn check_indirect(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(CheckIndirectError::NotAModule.into());
+    }
+    let entries = table_entries(module);
+    let mut mismatches: Vec<String> = vec![];
+    for (table, type_use) in indirect_calls(module) {
+        let table = table_index(module, &table);
+        let expected = Signature::of(module, &type_use);
+        for func_id in entries
+            .iter()
+            .filter(|(idx, _)| Some(*idx) == table)
+            .flat_map(|(_, funcs)| funcs)
+        {
+            let func = match find_func(module, func_id) {
+                Some(func) => func,
+                None => continue,
+            };
+            let got = Signature::of(module, func);
+            let mismatch = format!("{func_id} has {got}, but call_indirect expects {expected}");
+            if got != expected && !mismatches.contains(&mismatch) {
+                mismatches.push(mismatch);
+            }
+        }
+    }
+    if !mismatches.is_empty() {
+        return Err(CheckIndirectError::SignatureMismatch(mismatches.join("; ")).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_test(input: &str) -> Result<Node> {
+        let mut linker = Linker::default();
+        linker.features.push(("check_indirect", check_indirect));
+        linker.link_raw(input)
+    }
+
+    #[test]
+    fn matching_signatures() {
+        run_test(
+            r#"
+                (module
+                    (type $binop (func (param i32 i32) (result i32)))
+                    (table 2 funcref)
+                    (elem (i32.const 0) $add $sub)
+                    (func $add (param $a i32) (param $b i32) (result i32) (i32.add (local.get $a) (local.get $b)))
+                    (func $sub (type $binop) (i32.sub (local.get 0) (local.get 1)))
+                    (func (result i32)
+                        (call_indirect (type $binop) (i32.const 1) (i32.const 2) (i32.const 0))
+                        i32.const 1
+                        i32.const 2
+                        i32.const 1
+                        call_indirect (type $binop)))
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn mismatching_signature() {
+        let err = run_test(
+            r#"
+                (module
+                    (type $binop (func (param i32 i32) (result i32)))
+                    (table $fns 2 funcref)
+                    (elem (table $fns) (i32.const 0) func $add $neg)
+                    (func $add (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1)))
+                    (func $neg (param f32) (result f32) (f32.neg (local.get 0)))
+                    (func (result i32)
+                        (call_indirect $fns (type $binop) (i32.const 1) (i32.const 2) (i32.const 0))))
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Table entries don’t match their call_indirect types: $neg has (param f32) (result f32), but call_indirect expects (param i32 i32) (result i32)"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use thiserror::Error;
+
+use crate::ast::Node;
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum CheckKnownSectionsError {
+    #[error("Known section checker can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Unknown top-level nodes: {}", .0.join(", "))]
+    UnknownSections(Vec<String>),
+}
+
+impl From<CheckKnownSectionsError> for SWLError {
+    fn from(val: CheckKnownSectionsError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+/// Names of the nodes a module may contain, besides `swl.*` directives and `@` annotations. `tag` and `rec` come from the exception handling and GC proposals.
+static KNOWN_SECTIONS: &[&str] = &[
+    "type", "import", "func", "table", "memory", "global", "export", "start", "elem", "data",
+    "tag", "rec",
+];
+
+fn is_known(name: &str) -> bool {
+    KNOWN_SECTIONS.contains(&name) || name.starts_with("swl.") || name.starts_with('@')
+}
+
+/// Reports top-level nodes whose name isn’t a Wasm section or definition, like a misspelled `(fucn $x)`, which would otherwise only be caught by `wat2wasm`.
+pub fn check_known_sections(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(CheckKnownSectionsError::NotAModule.into());
+    }
+    let unknown: Vec<String> = module
+        .immediate_node_iter()
+        .filter(|node| !is_known(&node.name))
+        .map(|node| format!("({} ...)", node.name))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(CheckKnownSectionsError::UnknownSections(unknown).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_test(input: &str) -> Result<Node> {
+        let mut linker = Linker::default();
+        linker.enable_feature("check_known_sections")?;
+        linker.link_raw(input)
+    }
+
+    #[test]
+    fn known_sections() {
+        run_test(
+            r#"
+                (module
+                    (swl.requires check_known_sections)
+                    (@producers)
+                    (memory 1)
+                    (func $x))
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn misspelled_section() {
+        let err = run_test("(module (func $x) (fucn $y) (globl $g i32))").unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Unknown top-level nodes: (fucn ...), (globl ...)"
+        );
+    }
+}
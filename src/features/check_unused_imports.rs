@@ -0,0 +1,120 @@
+use thiserror::Error;
+
+use crate::ast::{DefKind, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum CheckUnusedImportsError {
+    #[error("Unused import checker can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Unused imports: {}", .0.join(", "))]
+    UnusedImports(Vec<String>),
+}
+
+impl From<CheckUnusedImportsError> for SWLError {
+    fn from(val: CheckUnusedImportsError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+/// Returns the id of an imported function or global and whether it is re-exported inline. Handles both `(import "mod" "field" (func $id ...))` and `(func $id (import "mod" "field") ...)`.
+fn imported_id(node: &Node) -> Option<(&str, bool)> {
+    let descriptor = if node.name == "import" {
+        node.immediate_node_iter().last()?
+    } else if node
+        .immediate_node_iter()
+        .any(|child| child.name == "import")
+    {
+        node
+    } else {
+        return None;
+    };
+    if !matches!(
+        descriptor.definition_kind(),
+        Some(DefKind::Func | DefKind::Global)
+    ) {
+        return None;
+    }
+    let id = descriptor
+        .immediate_attribute_iter()
+        .find(|attr| attr.starts_with('$'))?;
+    let exported = descriptor
+        .immediate_node_iter()
+        .any(|child| child.name == "export");
+    Some((id, exported))
+}
+
+pub fn check_unused_imports(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(CheckUnusedImportsError::NotAModule.into());
+    }
+    let unused: Vec<String> = module
+        .immediate_node_iter()
+        .flat_map(imported_id)
+        .filter(|(_, exported)| !exported)
+        .filter(|(id, _)| {
+            // The import’s own declaration accounts for one occurrence.
+            module
+                .node_iter()
+                .flat_map(|node| node.immediate_attribute_iter())
+                .filter(|attr| attr == id)
+                .count()
+                <= 1
+        })
+        .map(|(id, _)| id.to_string())
+        .collect();
+    if !unused.is_empty() {
+        return Err(CheckUnusedImportsError::UnusedImports(unused).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_test<T: AsRef<str>>(input: T) -> Result<Node> {
+        let mut linker = Linker::default();
+        linker
+            .features
+            .push(("check_unused_imports", check_unused_imports));
+        linker.link_raw(input)
+    }
+
+    #[test]
+    fn used_imports() {
+        run_test(
+            r#"
+                (module
+                    (import "env" "log" (func $log (param i32)))
+                    (global $base (import "env" "base") i32)
+                    (import "env" "abort" (func $abort))
+                    (func $main
+                        (call $log (global.get $base)))
+                    (export "abort" (func $abort)))
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn unused_imports() {
+        let err = run_test(
+            r#"
+                (module
+                    (import "env" "log" (func $log (param i32)))
+                    (import "env" "foo" (func $foo))
+                    (global $base (import "env" "base") i32)
+                    (func $main
+                        (call $log (i32.const 0))))
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Unused imports: $foo, $base"
+        );
+    }
+}
@@ -1,9 +1,16 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::time::Duration;
+
 use thiserror::Error;
 
 use crate::ast::{Item, Node};
 use crate::error::{Result, SWLError};
 use crate::eval::eval_expr;
+use crate::features::numerals::normalize_numerals;
+use crate::features::size_adjust::{data_extent, is_memory64};
 use crate::linker::Linker;
+use crate::loader::Loader;
 use crate::utils;
 
 #[derive(Error, Debug)]
@@ -14,6 +21,24 @@ pub enum ConstExprError {
     ExpressionMissing,
     #[error("Unknown constexpr type {0}")]
     UnknownType(String),
+    #[error("Unknown constexpr radix {0}, expected preserve, decimal or hex")]
+    UnknownRadix(String),
+    #[error("swl.crc32 expected a string literal file path")]
+    InvalidChecksumPath,
+    #[error("Invalid value for option {0}: {1}")]
+    InvalidOption(String, String),
+    #[error("Constexpr calls {0}, which is not a function defined in this module")]
+    UnknownFunction(String),
+    #[error("Constexpr calls {0}, which is not pure: {1}")]
+    ImpureFunction(String, String),
+    #[error("Memory {0} must be an integer, got {1}")]
+    NonIntegerMemarg(String, String),
+    #[error("Memory {0} must not be negative, got {1}")]
+    NegativeMemarg(String, String),
+    #[error("swl.heap-base can’t be used in data segments, as it depends on their placement")]
+    HeapBaseInData,
+    #[error("Memory {0} must fit into 32 bits, got {1}. Use a 64-bit memory like (memory i64 1)")]
+    MemargOutOfRange(String, String),
 }
 
 impl From<ConstExprError> for SWLError {
@@ -22,6 +47,273 @@ impl From<ConstExprError> for SWLError {
     }
 }
 
+pub static RADIX_OPTION: &str = "constexpr.radix";
+pub static OFFLINE_OPTION: &str = "constexpr.offline";
+pub static TIMEOUT_OPTION: &str = "constexpr.timeout_ms";
+static DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+static SWL_CRC32_NAME: &str = "swl.crc32";
+static SWL_HEAP_BASE_NAME: &str = "swl.heap-base";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Radix {
+    Preserve,
+    Decimal,
+    Hex,
+}
+
+impl Radix {
+    fn from_linker(linker: &Linker) -> Result<Radix> {
+        match linker.option(RADIX_OPTION) {
+            None | Some("decimal") => Ok(Radix::Decimal),
+            Some("preserve") => Ok(Radix::Preserve),
+            Some("hex") => Ok(Radix::Hex),
+            Some(other) => Err(ConstExprError::UnknownRadix(other.to_string()).into()),
+        }
+    }
+}
+
+/// Returns true if the expression has at least one numeric literal and all of them are hexadecimal.
+fn all_literals_hex(expr: &Node) -> bool {
+    let mut literals = expr
+        .node_iter()
+        .filter(|node| node.name.ends_with(".const"))
+        .flat_map(|node| node.immediate_attribute_iter())
+        .map(|attr| attr.trim_start_matches(['-', '+']))
+        .peekable();
+    literals.peek().is_some() && literals.all(|literal| literal.starts_with("0x"))
+}
+
+/// Replaces all `(swl.crc32 "file")` nodes in an expression with an `i32.const` of the file’s CRC-32.
+fn resolve_checksums(expr: &mut Node, linker: &mut Linker) -> Result<()> {
+    if expr.name != SWL_CRC32_NAME {
+        for child in expr.immediate_node_iter_mut() {
+            resolve_checksums(child, linker)?;
+        }
+        return Ok(());
+    }
+    let file_path = expr
+        .immediate_attribute_iter()
+        .next()
+        .filter(|attr| utils::is_string_literal(attr))
+        .ok_or::<SWLError>(ConstExprError::InvalidChecksumPath.into())?;
+    let contents = linker.load_raw(&file_path[1..file_path.len() - 1])?;
+    expr.name = "i32.const".to_string();
+    expr.items = vec![Item::Attribute(format!(
+        "{}",
+        utils::crc32(&contents) as i32
+    ))];
+    Ok(())
+}
+
+/// Replaces all `(swl.heap-base)` nodes in an expression with an `i32.const` (or `i64.const` for 64-bit memories) of the first address after all active data segments. `heap_base` is `None` while data segments are processed.
+fn resolve_heap_base(expr: &mut Node, heap_base: Option<u64>, memory64: bool) -> Result<()> {
+    if expr.name != SWL_HEAP_BASE_NAME {
+        for child in expr.immediate_node_iter_mut() {
+            resolve_heap_base(child, heap_base, memory64)?;
+        }
+        return Ok(());
+    }
+    let heap_base = heap_base.ok_or::<SWLError>(ConstExprError::HeapBaseInData.into())?;
+    expr.name = if memory64 { "i64.const" } else { "i32.const" }.to_string();
+    expr.items = vec![Item::Attribute(format!("{heap_base}"))];
+    Ok(())
+}
+
+/// Returns the instruction name followed by the immediate attributes of a node. This covers both folded (`(call $f)`) and flat (`call $f`) instructions.
+fn words(node: &Node) -> impl Iterator<Item = &str> {
+    std::iter::once(node.name.as_str()).chain(node.immediate_attribute_iter())
+}
+
+/// Returns the ids of all functions called directly from an expression or function.
+fn called_functions(node: &Node) -> Vec<String> {
+    node.node_iter()
+        .flat_map(|node| {
+            let words: Vec<&str> = words(node).collect();
+            words
+                .windows(2)
+                .filter(|pair| pair[0] == "call" || pair[0] == "return_call")
+                .map(|pair| pair[1].to_string())
+                .collect::<Vec<String>>()
+        })
+        .collect()
+}
+
+/// Returns why a function can’t be evaluated at compile time, if it can’t.
+fn impurity(func: &Node, mutable_globals: &HashSet<String>) -> Option<String> {
+    for node in func.node_iter() {
+        if node.name == "import" {
+            return Some("it is imported".to_string());
+        }
+        if is_constexpr_node(node) {
+            return Some("it contains a constexpr".to_string());
+        }
+        let words: Vec<&str> = words(node).collect();
+        for (idx, &word) in words.iter().enumerate() {
+            if word.contains(".load") || word.contains(".store") || word.starts_with("memory.") {
+                return Some("it accesses memory".to_string());
+            }
+            if word == "call_indirect" || word.starts_with("table.") {
+                return Some("it accesses a table".to_string());
+            }
+            if word == "global.set" {
+                return Some("it writes a global".to_string());
+            }
+            if word == "global.get" {
+                if let Some(&global) = words
+                    .get(idx + 1)
+                    .filter(|id| mutable_globals.contains(**id))
+                {
+                    return Some(format!("it reads mutable global {global}"));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Evaluates constexprs against a fixed prelude. Results are cached by the normalized expression, so identical expressions are only evaluated once.
+struct Evaluator {
+    prelude: String,
+    radix: Radix,
+    /// Only use the built-in fast path and never instantiate wasm3.
+    offline: bool,
+    /// How long a single evaluation in wasm3 may run.
+    timeout: Duration,
+    /// Functions that constexprs may call, by id.
+    functions: HashMap<String, Node>,
+    mutable_globals: HashSet<String>,
+    /// Value of `(swl.heap-base)`, set once all data segments are folded.
+    heap_base: Option<u64>,
+    /// Set if the module has a 64-bit memory, which allows 64-bit addresses.
+    memory64: bool,
+    cache: HashMap<String, String>,
+    evaluations: usize,
+}
+
+impl Evaluator {
+    fn new(prelude: String, radix: Radix) -> Evaluator {
+        Evaluator {
+            prelude,
+            radix,
+            offline: false,
+            timeout: DEFAULT_TIMEOUT,
+            functions: HashMap::new(),
+            mutable_globals: HashSet::new(),
+            heap_base: None,
+            memory64: false,
+            cache: HashMap::new(),
+            evaluations: 0,
+        }
+    }
+
+    /// Renders all functions the expression calls, transitively. Fails if any of them can’t be evaluated at compile time.
+    fn called_function_prelude(&self, expr: &Node) -> Result<String> {
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut queue = called_functions(expr);
+        let mut funcs = vec![];
+        while let Some(id) = queue.pop() {
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            let func = self
+                .functions
+                .get(&id)
+                .ok_or_else(|| ConstExprError::UnknownFunction(id.clone()))?;
+            if let Some(reason) = impurity(func, &self.mutable_globals) {
+                return Err(ConstExprError::ImpureFunction(id, reason).into());
+            }
+            queue.extend(called_functions(func));
+            let mut func = func.clone();
+            func.items.retain(|item| {
+                item.as_node()
+                    .map(|node| node.name != "export")
+                    .unwrap_or(true)
+            });
+            normalize_numerals(&mut func)?;
+            funcs.push(format!("{func}"));
+        }
+        Ok(funcs.join("\n"))
+    }
+
+    /// Returns true if integer results of `expr` should be rendered as hexadecimal.
+    fn wants_hex(&self, expr: &Node) -> bool {
+        match self.radix {
+            Radix::Decimal => false,
+            Radix::Hex => true,
+            Radix::Preserve => all_literals_hex(expr),
+        }
+    }
+
+    /// Evaluates a constexpr and renders the result. Integer results are rendered as hexadecimal when the radix asks for it.
+    fn evaluate(&mut self, expr: &Node, typ: &str, linker: &mut Linker) -> Result<String> {
+        let hex = self.wants_hex(expr);
+        self.evaluate_in_radix(expr, typ, hex, linker)
+    }
+
+    fn evaluate_in_radix(
+        &mut self,
+        expr: &Node,
+        typ: &str,
+        hex: bool,
+        linker: &mut Linker,
+    ) -> Result<String> {
+        let mut expr = expr.clone();
+        resolve_checksums(&mut expr, linker)?;
+        resolve_heap_base(&mut expr, self.heap_base, self.memory64)?;
+        normalize_numerals(&mut expr)?;
+        let key = format!("{typ} {hex} {expr}");
+        if let Some(value) = self.cache.get(&key) {
+            return Ok(value.clone());
+        }
+        let prelude = format!("{}\n{}", self.prelude, self.called_function_prelude(&expr)?);
+        let prelude = prelude.as_str();
+        let value = match typ {
+            "i32" if hex => format!(
+                "{:#x}",
+                eval_expr::<i32>(&expr, prelude, self.offline, self.timeout)?
+            ),
+            "i64" if hex => format!(
+                "{:#x}",
+                eval_expr::<i64>(&expr, prelude, self.offline, self.timeout)?
+            ),
+            "i32" => format!(
+                "{}",
+                eval_expr::<i32>(&expr, prelude, self.offline, self.timeout)?
+            ),
+            "i64" => format!(
+                "{}",
+                eval_expr::<i64>(&expr, prelude, self.offline, self.timeout)?
+            ),
+            "f32" => format_float(eval_expr::<f32>(
+                &expr,
+                prelude,
+                self.offline,
+                self.timeout,
+            )?),
+            "f64" => format_float(eval_expr::<f64>(
+                &expr,
+                prelude,
+                self.offline,
+                self.timeout,
+            )?),
+            _ => return Err(ConstExprError::UnknownType(typ.to_string()).into()),
+        };
+        self.evaluations += 1;
+        self.cache.insert(key, value.clone());
+        Ok(value)
+    }
+}
+
+/// Formats a float as a WAT literal. Rust writes infinities as `inf` already, but NaN as `NaN`. Other values are written in their own precision, so an `f32` 0.1 stays `0.1`.
+fn format_float<T: Display + Into<f64> + Copy>(v: T) -> String {
+    let wide: f64 = v.into();
+    if wide.is_nan() {
+        let sign = if wide.is_sign_negative() { "-" } else { "" };
+        return format!("{sign}nan");
+    }
+    format!("{v}")
+}
+
 fn is_constexpr_node(node: &Node) -> bool {
     node.name.ends_with(".constexpr")
 }
@@ -30,22 +322,22 @@ fn has_constexprs(node: &Node) -> bool {
     node.node_iter().any(is_constexpr_node)
 }
 
-fn process_constexpr(module: &mut Node, prelude: &str) -> Result<()> {
-    for node in module.node_iter_mut() {
-        if !is_constexpr_node(node) {
-            continue;
+fn process_constexpr(
+    node: &mut Node,
+    evaluator: &mut Evaluator,
+    linker: &mut Linker,
+) -> Result<()> {
+    // Recursing instead of using `node_iter_mut()`, as the walker would descend into the replaced items.
+    if !is_constexpr_node(node) {
+        for child in node.immediate_node_iter_mut() {
+            process_constexpr(child, evaluator, linker)?;
         }
-        let typ = node.name.split('.').next().unwrap().to_string();
-        let value = match typ.as_str() {
-            "i32" => format!("{}", eval_expr::<i32>(node, prelude)?),
-            "i64" => format!("{}", eval_expr::<i64>(node, prelude)?),
-            "f32" => format!("{}", eval_expr::<f32>(node, prelude)?),
-            "f64" => format!("{}", eval_expr::<f64>(node, prelude)?),
-            _ => return Err(ConstExprError::UnknownType(typ.clone()).into()),
-        };
-        node.name = node.name.strip_suffix("expr").unwrap().to_string();
-        node.items = vec![Item::Attribute(value)];
+        return Ok(());
     }
+    let typ = node.name.split('.').next().unwrap().to_string();
+    let value = evaluator.evaluate(node, &typ, linker)?;
+    node.name = node.name.strip_suffix("expr").unwrap().to_string();
+    node.items = vec![Item::Attribute(value)];
     Ok(())
 }
 
@@ -53,59 +345,172 @@ fn is_memop(node: &Node) -> bool {
     node.name.contains(".store") || node.name.contains(".load")
 }
 
-fn get_memarg(node: &mut Node) -> Option<&mut String> {
-    node.immediate_attribute_iter_mut()
-        .find(|attr| attr.starts_with("offset="))
-}
-
-fn process_offset_constexpr(module: &mut Node, prelude: &str) -> Result<()> {
+fn process_offset_constexpr(
+    module: &mut Node,
+    evaluator: &mut Evaluator,
+    linker: &mut Linker,
+) -> Result<()> {
     for node in module.node_iter_mut() {
         if !is_memop(node) {
             continue;
         }
-        let memarg = match get_memarg(node) {
-            Some(memarg) => memarg,
-            _ => continue,
-        };
+        for item in node.items.iter_mut() {
+            let (key, expr_str) = match item.as_memarg() {
+                Some((_, "")) => return Err(ConstExprError::ExpressionMissing.into()),
+                Some((key, value)) if value.starts_with('(') => (key.to_string(), value),
+                _ => continue,
+            };
+            let expr_node = crate::parser::Parser::new(expr_str).parse()?;
 
-        let expr_str = memarg
-            .split('=')
-            .nth(1)
-            .ok_or::<SWLError>(ConstExprError::ExpressionMissing.into())?;
-        if !expr_str.starts_with('(') {
-            continue;
+            let typ = expr_node.name.split('.').next().unwrap().to_string();
+            if typ != "i32" && typ != "i64" {
+                return Err(ConstExprError::NonIntegerMemarg(key, typ).into());
+            }
+            // Checked in decimal, as hex renders negative values as their two’s complement.
+            let value = evaluator.evaluate_in_radix(&expr_node, &typ, false, linker)?;
+            let number: i64 = value
+                .parse()
+                .map_err(|err: std::num::ParseIntError| SWLError::Other(err.into()))?;
+            if number < 0 {
+                return Err(ConstExprError::NegativeMemarg(key, value).into());
+            }
+            if !evaluator.memory64 && number > u32::MAX as i64 {
+                return Err(ConstExprError::MemargOutOfRange(key, value).into());
+            }
+            let value = if evaluator.wants_hex(&expr_node) {
+                format!("{number:#x}")
+            } else {
+                value
+            };
+            *item = Item::Attribute(format!("{key}={value}"));
         }
-        let expr_node = crate::parser::Parser::new(expr_str).parse()?;
-
-        let typ = expr_node.name.split('.').next().unwrap().to_string();
-        let value = match typ.as_str() {
-            "i32" => format!("{}", eval_expr::<i32>(&expr_node, prelude)?),
-            "i64" => format!("{}", eval_expr::<i64>(&expr_node, prelude)?),
-            "f32" => format!("{}", eval_expr::<f32>(&expr_node, prelude)?),
-            "f64" => format!("{}", eval_expr::<f64>(&expr_node, prelude)?),
-            _ => return Err(ConstExprError::UnknownType(typ.clone()).into()),
-        };
-        *memarg = format!("offset={value}");
     }
     Ok(())
 }
 
-pub fn constexpr(module: &mut Node, _linker: &mut Linker) -> Result<()> {
-    if !utils::is_module(module) {
-        return Err(ConstExprError::NotAModule.into());
+/// Returns the ids of all globals an initializer reads with `global.get`.
+fn global_dependencies(global: &Node) -> Vec<&str> {
+    global
+        .node_iter()
+        .flat_map(|node| {
+            let words: Vec<&str> = words(node).collect();
+            words
+                .windows(2)
+                .filter(|pair| pair[0] == "global.get")
+                .map(|pair| pair[1])
+                .collect::<Vec<&str>>()
+        })
+        .collect()
+}
+
+/// Orders globals so that every global comes after the globals its initializer depends on. Otherwise, source order is kept. Cycles are left as they are, for the runtime to report.
+fn order_globals<'a>(globals: &[&'a Node]) -> Vec<&'a Node> {
+    fn visit<'a>(
+        idx: usize,
+        globals: &[&'a Node],
+        visited: &mut Vec<bool>,
+        ordered: &mut Vec<&'a Node>,
+    ) {
+        if visited[idx] {
+            return;
+        }
+        visited[idx] = true;
+        for dep in global_dependencies(globals[idx]) {
+            if let Some(dep_idx) = globals
+                .iter()
+                .position(|global| utils::find_id_attribute(global) == Some(dep))
+            {
+                visit(dep_idx, globals, visited, ordered);
+            }
+        }
+        ordered.push(globals[idx]);
+    }
+
+    let mut visited = vec![false; globals.len()];
+    let mut ordered = vec![];
+    for idx in 0..globals.len() {
+        visit(idx, globals, &mut visited, &mut ordered);
     }
+    ordered
+}
 
-    let prelude: String = module
+/// Builds the module that constexprs are evaluated in: all types, followed by all globals without constexprs in dependency order.
+fn prelude(module: &Node) -> Result<String> {
+    let candidates: Vec<&Node> = module
         .immediate_node_iter()
-        .cloned()
-        .filter(|node| node.name == "global")
         .filter(|node| !has_constexprs(node))
-        .map(|node| format!("{node}"))
-        .collect::<Vec<String>>()
-        .join("\n");
+        .collect();
+    let types = candidates
+        .iter()
+        .filter(|node| node.name == "type")
+        .copied();
+    let globals: Vec<&Node> = candidates
+        .iter()
+        .filter(|node| node.name == "global")
+        .copied()
+        .collect();
+    let prelude: Vec<String> =
+        Result::from_iter(types.chain(order_globals(&globals)).map(|node| {
+            let mut node = node.clone();
+            normalize_numerals(&mut node)?;
+            Ok(format!("{node}"))
+        }))?;
+    Ok(prelude.join("\n"))
+}
+
+pub fn constexpr(module: &mut Node, linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(ConstExprError::NotAModule.into());
+    }
+    let radix = Radix::from_linker(linker)?;
 
-    process_constexpr(module, &prelude)?;
-    process_offset_constexpr(module, &prelude)?;
+    let mut evaluator = Evaluator::new(prelude(module)?, radix);
+    evaluator.memory64 = is_memory64(module);
+    if let Some(offline) = linker.option(OFFLINE_OPTION) {
+        evaluator.offline = offline.parse().map_err(|_| {
+            ConstExprError::InvalidOption(OFFLINE_OPTION.to_string(), offline.to_string())
+        })?;
+    }
+    if let Some(timeout) = linker.option(TIMEOUT_OPTION) {
+        let millis = timeout.parse().map_err(|_| {
+            ConstExprError::InvalidOption(TIMEOUT_OPTION.to_string(), timeout.to_string())
+        })?;
+        evaluator.timeout = Duration::from_millis(millis);
+    }
+    for node in module.immediate_node_iter() {
+        let id = match node
+            .immediate_attribute_iter()
+            .find(|attr| attr.starts_with('$'))
+        {
+            Some(id) => id.to_string(),
+            None => continue,
+        };
+        match node.name.as_str() {
+            "func" => {
+                evaluator.functions.insert(id, node.clone());
+            }
+            "global" if node.immediate_node_iter().any(|child| child.name == "mut") => {
+                evaluator.mutable_globals.insert(id);
+            }
+            _ => {}
+        }
+    }
+
+    // Data segments are folded first, so that `(swl.heap-base)` sees their final offsets.
+    for data_seg in module
+        .immediate_node_iter_mut()
+        .filter(|node| node.name == "data")
+    {
+        process_constexpr(data_seg, &mut evaluator, linker)?;
+    }
+    if module
+        .node_iter()
+        .any(|node| node.name == SWL_HEAP_BASE_NAME)
+    {
+        evaluator.heap_base = Some(data_extent(module)?);
+    }
+    process_constexpr(module, &mut evaluator, linker)?;
+    process_offset_constexpr(module, &mut evaluator, linker)?;
 
     Ok(())
 }
@@ -117,6 +522,8 @@ mod test {
     use super::*;
     use crate::linker;
     use crate::loader;
+    use crate::parser;
+    use crate::test_support::assert_module_eq;
 
     fn run_test<T: AsRef<str>>(inputs: &[T], expected: T) {
         let map: HashMap<String, Vec<u8>> = HashMap::from_iter(
@@ -126,10 +533,20 @@ mod test {
                 .map(|(idx, str)| (format!("{idx}"), str.as_ref().to_string().into_bytes())),
         );
         let mut linker = linker::Linker::new(Box::new(loader::MockLoader { map }));
-        linker.features.push(constexpr);
+        linker.features.push(("constexpr", constexpr));
 
         let module = linker.link_file("0").unwrap();
-        assert_eq!(format!("{module}"), expected.as_ref().trim());
+        assert_module_eq(&module, expected.as_ref());
+    }
+
+    fn run_radix_test(input: &str, radix: &str, expected: &str) {
+        let mut linker = Linker::default();
+        linker.features.push(("constexpr", constexpr));
+        linker
+            .options
+            .insert(RADIX_OPTION.to_string(), radix.to_string());
+        let module = linker.link_raw(input).unwrap();
+        assert_module_eq(&module, expected);
     }
 
     #[test]
@@ -225,6 +642,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn prelude_orders_globals() {
+        let module = parser::Parser::new(
+            r#"
+                (module
+                    (global $C i32 (i32.add (global.get $B) (i32.const 1)))
+                    (global $B i32 (i32.add (global.get $A) (i32.const 1)))
+                    (type $t (func))
+                    (global $A i32 (i32.const 0x10))
+                    (global $D (mut i32) (i32.const 0)))
+            "#,
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(
+            prelude(&module).unwrap(),
+            [
+                "(type $t (func))",
+                "(global $A i32 (i32.const 16))",
+                "(global $B i32 (i32.add (global.get $A) (i32.const 1)))",
+                "(global $C i32 (i32.add (global.get $B) (i32.const 1)))",
+                "(global $D (mut i32) (i32.const 0))",
+            ]
+            .join("\n")
+        );
+    }
+
     #[test]
     fn constexpr_offset() {
         run_test(
@@ -243,4 +687,334 @@ mod test {
             "#,
         );
     }
+
+    #[test]
+    fn constexpr_heap_base() {
+        let mut linker = Linker::default();
+        linker.features.push(("constexpr", constexpr));
+        let module = linker
+            .link_raw(
+                r#"
+                    (module
+                        (global $heap_base i32 (i32.constexpr (swl.heap-base)))
+                        (data (i32.constexpr (i32.add (i32.const 8) (i32.const 8))) "abcd")
+                        (data (i32.const 4) "ab")
+                        (func (i32.load offset=(i32.constexpr (i32.add (swl.heap-base) (i32.const 4))) (i32.const 0)) drop))
+                "#,
+            )
+            .unwrap();
+        assert_module_eq(
+            &module,
+            r#"(module (global $heap_base i32 (i32.const 20)) (data (i32.const 16) "abcd") (data (i32.const 4) "ab") (func (i32.load offset=24 (i32.const 0)) drop))"#,
+        );
+
+        let err = linker
+            .link_raw(r#"(module (data (i32.constexpr (swl.heap-base)) "a"))"#)
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: swl.heap-base can’t be used in data segments, as it depends on their placement"
+        );
+    }
+
+    #[test]
+    fn constexpr_offset_memory64() {
+        let mut linker = Linker::default();
+        linker.features.push(("constexpr", constexpr));
+        let module = linker
+            .link_raw(
+                r#"
+                    (module
+                        (memory i64 1)
+                        (func (i64.load offset=(i64.constexpr (i64.mul (i64.const 0x10000) (i64.const 0x10000))) (i64.const 0)) drop))
+                "#,
+            )
+            .unwrap();
+        assert_module_eq(
+            &module,
+            "(module (memory i64 1) (func (i64.load offset=4294967296 (i64.const 0)) drop))",
+        );
+
+        let err = linker
+            .link_raw("(module (memory 1) (func (i32.load offset=(i64.constexpr (i64.const 0x100000000)) (i32.const 0)) drop))")
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Memory offset must fit into 32 bits, got 4294967296. Use a 64-bit memory like (memory i64 1)"
+        );
+    }
+
+    #[test]
+    fn constexpr_offset_invalid() {
+        for (offset, msg) in [
+            (
+                "(f32.constexpr (f32.add (f32.const 10) (f32.const 2.5)))",
+                "Memory offset must be an integer, got f32",
+            ),
+            (
+                "(i32.constexpr (i32.sub (i32.const 4) (i32.const 8)))",
+                "Memory offset must not be negative, got -4",
+            ),
+        ] {
+            let mut linker = Linker::default();
+            linker.features.push(("constexpr", constexpr));
+            let err = linker
+                .link_raw(format!(
+                    "(module (i32.store offset={offset} (i32.const 0) (i32.const 4)))"
+                ))
+                .unwrap_err();
+            assert_eq!(
+                format!("{err}"),
+                format!("Something else went wrong: {msg}")
+            );
+        }
+    }
+
+    #[test]
+    fn constexpr_offset_hex_radix() {
+        run_radix_test(
+            "(module (i32.store offset=(i32.constexpr (i32.add (i32.const 8) (i32.const 8))) (i32.const 0) (i32.const 4)))",
+            "hex",
+            "(module (i32.store offset=0x10 (i32.const 0) (i32.const 4)))",
+        );
+
+        let mut linker = Linker::default();
+        linker.features.push(("constexpr", constexpr));
+        linker
+            .options
+            .insert(RADIX_OPTION.to_string(), "hex".to_string());
+        let err = linker
+            .link_raw("(module (i32.store offset=(i32.constexpr (i32.sub (i32.const 4) (i32.const 8))) (i32.const 0) (i32.const 4)))")
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Memory offset must not be negative, got -4"
+        );
+    }
+
+    #[test]
+    fn constexpr_offset_non_decimal_literals() {
+        run_test(
+            &[r#"
+                (module
+                    (i32.store
+                        offset=(i32.constexpr
+                                (i32.add
+                                    (i32.const 0x1_0)
+                                    (i32.const 0b100)))
+                        (i32.const 4))
+                )
+            "#],
+            r#"
+                (module (i32.store offset=20 (i32.const 4)))
+            "#,
+        );
+    }
+
+    #[test]
+    fn constexpr_radix_preserve() {
+        run_radix_test(
+            "(module (i32.constexpr (i32.add (i32.const 0x10) (i32.const 0x20))))",
+            "preserve",
+            "(module (i32.const 0x30))",
+        );
+        run_radix_test(
+            "(module (i32.constexpr (i32.add (i32.const 0x10) (i32.const 32))))",
+            "preserve",
+            "(module (i32.const 48))",
+        );
+    }
+
+    #[test]
+    fn constexpr_radix_decimal() {
+        run_radix_test(
+            "(module (i32.constexpr (i32.add (i32.const 0x10) (i32.const 0x20))))",
+            "decimal",
+            "(module (i32.const 48))",
+        );
+    }
+
+    #[test]
+    fn constexpr_crc32() {
+        run_test(
+            &[
+                r#"
+                    (module
+                        (global $CHECKSUM i32
+                            (i32.constexpr (swl.crc32 "1"))))
+                "#,
+                "123456789",
+            ],
+            r#"
+                (module (global $CHECKSUM i32 (i32.const -873187034)))
+            "#,
+        );
+    }
+
+    #[test]
+    fn constexpr_cache() {
+        let mut linker = Linker::default();
+        let mut module = crate::parser::Parser::new(
+            r#"
+                (module
+                    (data (i32.constexpr (i32.add (i32.const 1) (i32.const 2))) "a")
+                    (data (i32.constexpr (i32.add (i32.const 1) (i32.const 0x2))) "b")
+                    (i32.store
+                        offset=(i32.constexpr (i32.add (i32.const 1) (i32.const 2)))
+                        (i32.const 4)))
+            "#,
+        )
+        .parse()
+        .unwrap();
+        let mut evaluator = Evaluator::new("".to_string(), Radix::Decimal);
+        process_constexpr(&mut module, &mut evaluator, &mut linker).unwrap();
+        process_offset_constexpr(&mut module, &mut evaluator, &mut linker).unwrap();
+        assert_eq!(
+            format!("{module}"),
+            r#"(module (data (i32.const 3) "a") (data (i32.const 3) "b") (i32.store offset=3 (i32.const 4)))"#
+        );
+        assert_eq!(evaluator.evaluations, 1);
+    }
+
+    #[test]
+    fn constexpr_multi_value() {
+        for expr in [
+            "(i32.const 1) (i32.const 2)",
+            "(block (result i32 i32) (i32.const 1) (i32.const 2))",
+        ] {
+            let mut linker = Linker::default();
+            linker.features.push(("constexpr", constexpr));
+            let err = linker
+                .link_raw(format!("(module (i32.constexpr {expr}))"))
+                .unwrap_err();
+            assert_eq!(
+                format!("{err}"),
+                "Something went wrong: Constexpr must produce a single i32 value, but produces 2"
+            );
+        }
+    }
+
+    #[test]
+    fn constexpr_pure_function() {
+        run_test(
+            &[r#"
+                (module
+                    (global $PAGE i32 (i32.const 0x1_0000))
+                    (func $pages (result i32)
+                        (i32.const 3))
+                    (func $compute_size (export "compute_size") (result i32)
+                        (i32.mul (global.get $PAGE) (call $pages)))
+                    (data (i32.constexpr (call $compute_size)) "x"))
+            "#],
+            r#"
+                (module (global $PAGE i32 (i32.const 65536)) (func $pages (result i32) (i32.const 3)) (func $compute_size (export "compute_size") (result i32) (i32.mul (global.get $PAGE) (call $pages))) (data (i32.const 196608) "x"))
+            "#,
+        );
+    }
+
+    #[test]
+    fn constexpr_impure_function() {
+        for (func, reason) in [
+            (
+                "(func $f (result i32) (i32.load (i32.const 0)))",
+                "it accesses memory",
+            ),
+            (
+                "(func $f (result i32) global.get $counter)",
+                "it reads mutable global $counter",
+            ),
+            (
+                r#"(func $f (import "env" "f") (result i32))"#,
+                "it is imported",
+            ),
+        ] {
+            let mut linker = Linker::default();
+            linker.features.push(("constexpr", constexpr));
+            let err = linker
+                .link_raw(format!(
+                    "(module (global $counter (mut i32) (i32.const 0)) {func} (func $g (result i32) (call $f)) (i32.constexpr (call $g)))"
+                ))
+                .unwrap_err();
+            assert_eq!(
+                format!("{err}"),
+                format!(
+                    "Something else went wrong: Constexpr calls $f, which is not pure: {reason}"
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn constexpr_timeout() {
+        let mut linker = Linker::default();
+        linker.features.push(("constexpr", constexpr));
+        linker
+            .options
+            .insert(TIMEOUT_OPTION.to_string(), "100".to_string());
+        let err = linker
+            .link_raw(
+                "(module (global i32 (i32.constexpr (block (result i32) (loop $l (br $l)) (i32.const 0)))))",
+            )
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something went wrong: Constexpr (block (result i32) (loop $l (br $l)) (i32.const 0)) didn’t finish within 100ms"
+        );
+    }
+
+    #[test]
+    fn constexpr_float_literals() {
+        let mut linker = Linker::default();
+        linker.features.push(("constexpr", constexpr));
+        linker
+            .options
+            .insert(OFFLINE_OPTION.to_string(), "true".to_string());
+        let module = linker
+            .link_raw(
+                r#"
+                    (module
+                        (global f64 (f64.constexpr (f64.add (f64.const 0x1.8p1) (f64.const 1))))
+                        (global f32 (f32.constexpr (f32.mul (f32.const 0x1p-2) (f32.const 2))))
+                        (global f64 (f64.constexpr (f64.sub (f64.const inf) (f64.const 1))))
+                        (global f32 (f32.constexpr (f32.const nan)))
+                        (global f32 (f32.constexpr (f32.div (f32.const 1) (f32.const 10)))))
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            format!("{module}"),
+            r#"(module (global f64 (f64.const 4)) (global f32 (f32.const 0.5)) (global f64 (f64.const inf)) (global f32 (f32.const nan)) (global f32 (f32.const 0.1)))"#
+        );
+    }
+
+    #[test]
+    fn constexpr_offline() {
+        let mut linker = Linker::default();
+        linker.features.push(("constexpr", constexpr));
+        linker
+            .options
+            .insert(OFFLINE_OPTION.to_string(), "true".to_string());
+        let module = linker
+            .link_raw(
+                r#"
+                    (module
+                        (data (i32.constexpr (i32.add (i32.const 0x10) (i32.shl (i32.const 1) (i32.const 4)))) "a")
+                        (global i64 (i64.constexpr (i64.mul (i64.const -2) (i64.const 3))))
+                        (global f32 (f32.constexpr (f32.div (f32.const 1) (f32.const 4)))))
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            format!("{module}"),
+            r#"(module (data (i32.const 32) "a") (global i64 (i64.const -6)) (global f32 (f32.const 0.25)))"#
+        );
+
+        let err = linker
+            .link_raw("(module (global $G i32 (i32.const 1)) (i32.constexpr (global.get $G)))")
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something went wrong: Constexpr (global.get $G) requires the wasm3 runtime, which is disabled by --offline-constexpr"
+        );
+    }
 }
@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+
+use crate::ast::Node;
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils::{self, is_string_literal, unescape_wat_string};
+
+#[derive(Error, Debug)]
+pub enum DataDedupeError {
+    #[error("Data deduplicator can only be applied to top-level modules")]
+    NotAModule,
+}
+
+impl From<DataDedupeError> for SWLError {
+    fn from(val: DataDedupeError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+struct Segment {
+    id: Option<String>,
+    /// Memory and offset of active segments, `None` for passive segments.
+    target: Option<String>,
+    bytes: Vec<u8>,
+}
+
+impl Segment {
+    fn from_node(node: &Node) -> Result<Segment> {
+        let id = node
+            .immediate_attribute_iter()
+            .find(|attr| attr.starts_with('$'))
+            .map(|id| id.to_string());
+        let target: Vec<String> = node
+            .immediate_node_iter()
+            .map(|node| format!("{node}"))
+            .collect();
+        let mut bytes = vec![];
+        for attr in node
+            .immediate_attribute_iter()
+            .filter(|attr| is_string_literal(attr))
+        {
+            bytes.extend(unescape_wat_string(&attr[1..attr.len() - 1])?);
+        }
+        Ok(Segment {
+            id,
+            target: (!target.is_empty()).then(|| target.join(" ")),
+            bytes,
+        })
+    }
+
+    fn is_referenced_by(&self, idx: usize, refs: &HashSet<String>) -> bool {
+        refs.contains(&format!("{idx}"))
+            || self.id.as_ref().map(|id| refs.contains(id)) == Some(true)
+    }
+}
+
+fn is_label(attr: &str) -> bool {
+    attr.starts_with('$') || attr.parse::<u32>().is_ok()
+}
+
+/// Calls `f` with the data segment reference of every `memory.init` and `data.drop`, both folded and flat.
+pub(crate) fn for_each_data_ref(module: &mut Node, mut f: impl FnMut(&str, &mut String)) {
+    for node in module.node_iter_mut() {
+        let mut instr = Some(node.name.clone()).filter(|name| is_data_instr(name));
+        let mut last_label: Option<&mut String> = None;
+        for attr in node.immediate_attribute_iter_mut() {
+            if is_data_instr(attr) {
+                if let (Some(name), Some(label)) = (instr.take(), last_label.take()) {
+                    f(&name, label);
+                }
+                instr = Some(attr.clone());
+            } else if instr.is_some() && is_label(attr) {
+                // `memory.init` takes an optional memory before the data segment.
+                if instr.as_deref() == Some("data.drop") && last_label.is_some() {
+                    continue;
+                }
+                last_label = Some(attr);
+            } else if let (Some(name), Some(label)) = (instr.take(), last_label.take()) {
+                f(&name, label);
+            }
+        }
+        if let (Some(name), Some(label)) = (instr, last_label) {
+            f(&name, label);
+        }
+    }
+}
+
+fn is_data_instr(word: &str) -> bool {
+    word == "memory.init" || word == "data.drop"
+}
+
+pub fn data_dedupe(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(DataDedupeError::NotAModule.into());
+    }
+    let segments: Vec<Segment> = module
+        .immediate_node_iter()
+        .filter(|node| node.name == "data")
+        .map(Segment::from_node)
+        .collect::<Result<Vec<Segment>>>()?;
+
+    // Dropping a deduplicated passive segment would also drop it for all other users.
+    let mut dropped: HashSet<String> = HashSet::new();
+    for_each_data_ref(module, |instr, label| {
+        if instr == "data.drop" {
+            dropped.insert(label.clone());
+        }
+    });
+
+    let mut canonical: HashMap<(Option<&str>, &[u8]), usize> = HashMap::new();
+    let mut duplicate_of: Vec<Option<usize>> = vec![];
+    for (idx, segment) in segments.iter().enumerate() {
+        let is_passive = segment.target.is_none();
+        if is_passive && segment.is_referenced_by(idx, &dropped) {
+            duplicate_of.push(None);
+            continue;
+        }
+        let key = (segment.target.as_deref(), segment.bytes.as_slice());
+        match canonical.get(&key) {
+            Some(&original) => duplicate_of.push(Some(original)),
+            None => {
+                canonical.insert(key, idx);
+                duplicate_of.push(None);
+            }
+        }
+    }
+    if duplicate_of.iter().all(|dup| dup.is_none()) {
+        return Ok(());
+    }
+
+    let mut new_indices: Vec<usize> = vec![];
+    let mut num_kept = 0;
+    for dup in &duplicate_of {
+        new_indices.push(num_kept);
+        if dup.is_none() {
+            num_kept += 1;
+        }
+    }
+    let mut renames: HashMap<String, String> = HashMap::new();
+    for (idx, segment) in segments.iter().enumerate() {
+        let target = duplicate_of[idx].unwrap_or(idx);
+        let new_ref = segments[target]
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("{}", new_indices[target]));
+        renames.insert(format!("{idx}"), new_ref.clone());
+        if let Some(id) = &segment.id {
+            renames.insert(id.clone(), new_ref);
+        }
+    }
+    for_each_data_ref(module, |_, label| {
+        if let Some(new_ref) = renames.get(label.as_str()) {
+            *label = new_ref.clone();
+        }
+    });
+
+    let mut data_idx = 0;
+    module.items.retain(|item| {
+        if item.as_node().map(|node| node.name == "data") != Some(true) {
+            return true;
+        }
+        data_idx += 1;
+        duplicate_of[data_idx - 1].is_none()
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::assert_module_eq;
+
+    fn run_test(input: &str, expected: &str) {
+        let mut linker = Linker::default();
+        linker.features.push(("data_dedupe", data_dedupe));
+        let got = linker.link_raw(input).unwrap();
+        assert_module_eq(&got, expected);
+    }
+
+    #[test]
+    fn duplicate_active_segment() {
+        run_test(
+            r#"
+                (module
+                    (memory 1)
+                    (data (i32.const 0) "\01\02")
+                    (data (i32.const 16) "\01\02")
+                    (data (i32.const 0) "\01" "\02"))
+            "#,
+            r#"(module (memory 1) (data (i32.const 0) "\01\02") (data (i32.const 16) "\01\02"))"#,
+        );
+    }
+
+    #[test]
+    fn duplicate_passive_segment() {
+        run_test(
+            r#"
+                (module
+                    (data "abc")
+                    (data $b "abc")
+                    (data "xyz")
+                    (func
+                        (memory.init 1 (i32.const 0) (i32.const 0) (i32.const 3))
+                        (memory.init $b (i32.const 0) (i32.const 0) (i32.const 3))
+                        (memory.init 2 (i32.const 0) (i32.const 0) (i32.const 3))))
+            "#,
+            r#"(module (data "abc") (data "xyz") (func (memory.init 0 (i32.const 0) (i32.const 0) (i32.const 3)) (memory.init 0 (i32.const 0) (i32.const 0) (i32.const 3)) (memory.init 1 (i32.const 0) (i32.const 0) (i32.const 3))))"#,
+        );
+    }
+}
@@ -4,7 +4,9 @@ use crate::ast::{Item, Node};
 use crate::error::{Result, SWLError};
 use crate::linker::Linker;
 use crate::loader::Loader;
-use crate::utils::{self, find_child_node_item_mut, is_string_literal};
+use crate::utils::{
+    self, decode_base64, escape_wat_string, find_child_node_item_mut, is_string_literal,
+};
 
 #[derive(Error, Debug)]
 pub enum DataImportError {
@@ -12,8 +14,15 @@ pub enum DataImportError {
     NotAModule,
     #[error("Import directive expected a string literal")]
     InvalidImport,
+    #[error("{path} is not valid UTF-8: invalid byte at offset {offset}")]
+    InvalidUtf8 { path: String, offset: usize },
+    #[error("{0} is not valid base64")]
+    InvalidBase64(String),
 }
 
+/// Prefix of import paths that carry base64-encoded data instead of naming a file.
+static INLINE_PREFIX: &str = "inline:";
+
 impl From<DataImportError> for SWLError {
     fn from(val: DataImportError) -> Self {
         SWLError::Other(val.into())
@@ -21,10 +30,11 @@ impl From<DataImportError> for SWLError {
 }
 
 fn is_import_node(node: &Node) -> bool {
+    let items: Vec<&Item> = node.significant_items().collect();
     node.name == "import"
-        && node.items.len() == 2
-        && node.items[0].as_attribute().is_some()
-        && node.items[1]
+        && items.len() == 2
+        && items[0].as_attribute().is_some()
+        && items[1]
             .as_node()
             .map(|node| node.name == "raw")
             .unwrap_or(false)
@@ -44,18 +54,56 @@ pub fn data_import(module: &mut Node, linker: &mut Linker) -> Result<()> {
         };
         let import_node = import_item.as_node_mut().unwrap();
 
-        let file_path_attr = import_node.items[0].as_attribute().unwrap();
+        let file_path_attr = import_node.immediate_attribute_iter().next().unwrap();
         if !is_string_literal(file_path_attr) {
             return Err(DataImportError::InvalidImport.into());
         }
         let unquoted_file_path_attr = &file_path_attr[1..file_path_attr.len() - 1];
 
-        let raw_data = linker.load_raw(unquoted_file_path_attr)?;
-        let escaped_data: String = raw_data
-            .into_iter()
-            .map(|v| format!("\\{v:02x}"))
-            .collect::<Vec<String>>()
-            .join("");
+        let raw_options = |name: &str| {
+            import_node
+                .immediate_node_iter()
+                .find(|node| node.name == "raw")
+                .map(|raw| raw.immediate_node_iter().any(|node| node.name == name))
+                .unwrap_or(false)
+        };
+        let expects_utf8 = raw_options("utf8");
+        let is_base64 = raw_options("base64");
+
+        // Plain imports are escaped chunk by chunk, so only the escaped string is built up, without another copy of the raw file. The escaped string itself has to be held, as it becomes part of the module.
+        if !expects_utf8 && !is_base64 {
+            let mut escaped_data = String::new();
+            linker.load_raw_streaming(unquoted_file_path_attr, &mut |chunk| {
+                escaped_data += &escape_wat_string(chunk)
+            })?;
+            *import_item = Item::Attribute(format!(r#""{escaped_data}""#));
+            continue;
+        }
+
+        let raw_data = match unquoted_file_path_attr.strip_prefix(INLINE_PREFIX) {
+            Some(encoded) if is_base64 => encoded.as_bytes().to_vec(),
+            _ => linker.load_raw(unquoted_file_path_attr)?,
+        };
+        let raw_data = if is_base64 {
+            std::str::from_utf8(&raw_data)
+                .ok()
+                .and_then(decode_base64)
+                .ok_or_else(|| {
+                    DataImportError::InvalidBase64(unquoted_file_path_attr.to_string())
+                })?
+        } else {
+            raw_data
+        };
+        if expects_utf8 {
+            if let Err(err) = std::str::from_utf8(&raw_data) {
+                return Err(DataImportError::InvalidUtf8 {
+                    path: unquoted_file_path_attr.to_string(),
+                    offset: err.valid_up_to(),
+                }
+                .into());
+            }
+        }
+        let escaped_data = escape_wat_string(&raw_data);
         *import_item = Item::Attribute(format!(r#""{escaped_data}""#));
     }
     Ok(())
@@ -68,6 +116,7 @@ mod test {
     use super::*;
     use crate::linker;
     use crate::loader;
+    use crate::test_support::assert_module_eq;
 
     fn run_test<T: AsRef<str>>(inputs: &[T], expected: T) {
         let map: HashMap<String, Vec<u8>> = HashMap::from_iter(
@@ -77,10 +126,10 @@ mod test {
                 .map(|(idx, str)| (format!("{idx}"), str.as_ref().to_string().into_bytes())),
         );
         let mut linker = linker::Linker::new(Box::new(loader::MockLoader { map }));
-        linker.features.push(data_import);
+        linker.features.push(("data_import", data_import));
 
         let module = linker.link_file("0").unwrap();
-        assert_eq!(format!("{module}"), expected.as_ref().trim());
+        assert_module_eq(&module, expected.as_ref());
     }
 
     #[test]
@@ -99,4 +148,142 @@ mod test {
             "#,
         );
     }
+
+    #[test]
+    fn utf8_assertion() {
+        run_test(
+            &[
+                r#"
+                    (module
+                        (data (i32.const 0) (import "1" (raw (utf8))))
+                    )
+                "#,
+                "hï",
+            ],
+            r#"
+                (module (data (i32.const 0) "\68\c3\af"))
+            "#,
+        );
+
+        let map = HashMap::from([
+            (
+                "0".to_string(),
+                br#"(module (data (i32.const 0) (import "1" (raw (utf8)))))"#.to_vec(),
+            ),
+            ("1".to_string(), vec![0x41, 0xff, 0x42]),
+        ]);
+        let mut linker = linker::Linker::new(Box::new(loader::MockLoader { map }));
+        linker.features.push(("data_import", data_import));
+        let err = linker.link_file("0").unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: 1 is not valid UTF-8: invalid byte at offset 1"
+        );
+    }
+
+    #[test]
+    fn base64() {
+        run_test(
+            &[
+                r#"
+                    (module
+                        (data (i32.const 0) (import "inline:QUJD" (raw (base64))))
+                        (data (i32.const 3) (import "1" (raw (base64))))
+                    )
+                "#,
+                "aMOv\n",
+            ],
+            r#"
+                (module (data (i32.const 0) "\41\42\43") (data (i32.const 3) "\68\c3\af"))
+            "#,
+        );
+
+        let map = HashMap::from([(
+            "0".to_string(),
+            br#"(module (data (i32.const 0) (import "inline:QU!D" (raw (base64)))))"#.to_vec(),
+        )]);
+        let mut linker = linker::Linker::new(Box::new(loader::MockLoader { map }));
+        linker.features.push(("data_import", data_import));
+        let err = linker.link_file("0").unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: inline:QU!D is not valid base64"
+        );
+    }
+
+    /// Streams files in chunks of two bytes and counts the chunks.
+    struct ChunkingLoader {
+        inner: loader::MockLoader,
+        chunks: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl Loader for ChunkingLoader {
+        fn canonicalize(&mut self, path: &str) -> Result<String> {
+            self.inner.canonicalize(path)
+        }
+
+        fn load_raw(&mut self, path: &str) -> Result<Vec<u8>> {
+            self.inner.load_raw(path)
+        }
+
+        fn load_raw_streaming(&mut self, path: &str, f: &mut dyn FnMut(&[u8])) -> Result<()> {
+            for chunk in self.inner.load_raw(path)?.chunks(2) {
+                self.chunks.set(self.chunks.get() + 1);
+                f(chunk);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn streams_plain_imports() {
+        let map = HashMap::from([
+            (
+                "0".to_string(),
+                br#"(module (data (i32.const 0) (import "1" (raw))))"#.to_vec(),
+            ),
+            ("1".to_string(), b"ABCDE".to_vec()),
+        ]);
+        let chunks = std::rc::Rc::new(std::cell::Cell::new(0));
+        let mut linker = linker::Linker::new(Box::new(ChunkingLoader {
+            inner: loader::MockLoader { map },
+            chunks: chunks.clone(),
+        }));
+        linker.features.push(("data_import", data_import));
+        let module = linker.link_file("0").unwrap();
+        assert_module_eq(
+            &module,
+            r#"(module (data (i32.const 0) "\41\42\43\44\45"))"#,
+        );
+        assert_eq!(chunks.get(), 3);
+    }
+
+    #[test]
+    fn resolves_like_file_import() {
+        let root = std::env::temp_dir().join(format!("swl-data-import-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/data.bin"), "\x41\x42").unwrap();
+        std::fs::write(root.join("sub/other.wat"), "(module (func $other))").unwrap();
+
+        let mut linker =
+            linker::Linker::new(Box::new(loader::FileSystemLoader::new(vec![root.clone()])));
+        linker
+            .features
+            .push(("import", crate::features::import::import));
+        linker.features.push(("data_import", data_import));
+        let module = linker.link_raw(
+            r#"
+                (module
+                    (import "sub/other.wat" (file))
+                    (data (i32.const 0) (import "sub/data.bin" (raw)))
+                )
+            "#,
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            format!("{}", module.unwrap()),
+            r#"(module (data (i32.const 0) "\41\42") (func $other))"#
+        );
+    }
 }
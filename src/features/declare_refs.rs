@@ -0,0 +1,133 @@
+use thiserror::Error;
+
+use crate::ast::{DefKind, Item, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum DeclareRefsError {
+    #[error("Reference declarer can only be applied to top-level modules")]
+    NotAModule,
+}
+
+impl From<DeclareRefsError> for SWLError {
+    fn from(val: DeclareRefsError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+fn is_func_ref(attr: &str) -> bool {
+    attr.starts_with('$') || attr.parse::<u32>().is_ok()
+}
+
+/// Returns the targets of all `ref.func` instructions inside a node, both folded and flat.
+fn ref_func_targets(node: &Node) -> Vec<&str> {
+    let mut targets = vec![];
+    for node in node.node_iter() {
+        let mut attrs = node.immediate_attribute_iter();
+        if node.name == "ref.func" {
+            targets.extend(attrs.next());
+            continue;
+        }
+        while let Some(attr) = attrs.next() {
+            if attr == "ref.func" {
+                targets.extend(attrs.next());
+            }
+        }
+    }
+    targets
+}
+
+/// Returns all functions listed in an elem segment, either as function indices or as `ref.func` expressions.
+fn elem_funcs(elem: &Node) -> Vec<&str> {
+    let mut funcs: Vec<&str> = elem
+        .significant_items()
+        .enumerate()
+        .filter_map(|(idx, item)| match item {
+            // The segment’s own id comes first.
+            Item::Attribute(attr) if idx == 0 && attr.starts_with('$') => None,
+            Item::Attribute(attr) if is_func_ref(attr) => Some(attr.as_str()),
+            _ => None,
+        })
+        .collect();
+    funcs.extend(ref_func_targets(elem));
+    funcs
+}
+
+/// Adds all functions that are used with `ref.func` inside function bodies to an `(elem declare func ...)` segment, unless they are declared by an elem segment, an export or a global already.
+pub fn declare_refs(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(DeclareRefsError::NotAModule.into());
+    }
+    let exports = module.exports();
+    let mut declared: Vec<&str> = exports
+        .iter()
+        .filter(|export| export.kind == DefKind::Func)
+        .map(|export| export.target.as_str())
+        .collect();
+    let mut undeclared: Vec<String> = vec![];
+    for node in module.immediate_node_iter() {
+        match node.name.as_str() {
+            "elem" => declared.extend(elem_funcs(node)),
+            "global" => declared.extend(ref_func_targets(node)),
+            _ => {}
+        }
+    }
+    for node in module
+        .immediate_node_iter()
+        .filter(|node| node.name == "func")
+    {
+        for target in ref_func_targets(node) {
+            if !declared.contains(&target) && !undeclared.iter().any(|f| f == target) {
+                undeclared.push(target.to_string());
+            }
+        }
+    }
+    if undeclared.is_empty() {
+        return Ok(());
+    }
+
+    let items = ["declare", "func"]
+        .into_iter()
+        .map(|attr| attr.to_string())
+        .chain(undeclared)
+        .map(Item::Attribute)
+        .collect();
+    module.items.push(Item::Node(Node {
+        name: "elem".to_string(),
+        depth: module.depth + 1,
+        items,
+        span: None,
+        leading_comments: vec![],
+        trailing_comments: vec![],
+    }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::assert_module_eq;
+
+    fn run_test(input: &str, expected: &str) {
+        let mut linker = Linker::default();
+        linker.features.push(("declare_refs", declare_refs));
+        let got = linker.link_raw(input).unwrap();
+        assert_module_eq(&got, expected);
+    }
+
+    #[test]
+    fn declare_ref_func() {
+        run_test(
+            "(module (func $f) (func $g (drop (ref.func $f)) ref.func $f drop))",
+            "(module (func $f) (func $g (drop (ref.func $f)) ref.func $f drop) (elem declare func $f))",
+        );
+    }
+
+    #[test]
+    fn keep_declared_refs() {
+        let input = "(module (func $f) (func $g) (elem declare func $f) (global funcref (ref.func $g)) (func $h (drop (ref.func $f)) (drop (ref.func $g))))";
+        run_test(input, input);
+    }
+}
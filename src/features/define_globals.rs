@@ -0,0 +1,105 @@
+use thiserror::Error;
+
+use crate::ast::Node;
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::parser::Parser;
+use crate::utils::{self, parse_number_literal};
+
+#[derive(Error, Debug)]
+pub enum DefineGlobalsError {
+    #[error("Define globals can only be applied to top-level modules")]
+    NotAModule,
+}
+
+impl From<DefineGlobalsError> for SWLError {
+    fn from(val: DefineGlobalsError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+/// Returns true if the module already defines or imports a global with the given id.
+fn has_global(module: &Node, id: &str) -> bool {
+    module
+        .immediate_node_iter()
+        .flat_map(|node| match node.name.as_str() {
+            "import" => node.immediate_node_iter().next(),
+            _ => Some(node),
+        })
+        .filter(|node| node.name == "global")
+        .any(|node| node.immediate_attribute_iter().next() == Some(id))
+}
+
+/// Adds an immutable `(global $KEY i32 (i32.const VALUE))` for every define with a numeric value, so defines can be read with `global.get`. Values that don’t fit into an `i32` become `i64` globals. Defines whose global already exists are skipped. The globals are inserted after the leading imports, in alphabetical order.
+pub fn define_globals(module: &mut Node, linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(DefineGlobalsError::NotAModule.into());
+    }
+    let defines = match &linker.defines {
+        Some(defines) => defines,
+        None => return Ok(()),
+    };
+    let mut defines: Vec<(&String, &String)> = defines.iter().collect();
+    defines.sort();
+
+    let mut idx = module
+        .items
+        .iter()
+        .position(|item| item.as_node().map(|node| node.name != "import") == Some(true))
+        .unwrap_or(module.items.len());
+    for (key, value) in defines {
+        let value = match parse_number_literal(value.trim()) {
+            Ok(value) => value as i64,
+            Err(_) => continue,
+        };
+        let id = format!("${key}");
+        if has_global(module, &id) {
+            continue;
+        }
+        let typ = if i32::try_from(value).is_ok() {
+            "i32"
+        } else {
+            "i64"
+        };
+        let global = Parser::new(format!("(global {id} {typ} ({typ}.const {value}))")).parse()?;
+        module.insert_node(idx, global);
+        idx += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_support::assert_module_eq;
+
+    #[test]
+    fn numeric_defines() {
+        let mut linker = Linker::default();
+        linker.features.push(("define_globals", define_globals));
+        linker.defines = Some(HashMap::from_iter(
+            [
+                ("SIZE", "0x10"),
+                ("NAME", "hello"),
+                ("BIG", "5000000000"),
+                ("EXISTING", "1"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+        ));
+        let module = linker
+            .link_raw(
+                r#"
+                    (module
+                        (import "env" "existing" (global $EXISTING i32))
+                        (func (result i32) (global.get $SIZE)))
+                "#,
+            )
+            .unwrap();
+        assert_module_eq(
+            &module,
+            r#"(module (import "env" "existing" (global $EXISTING i32)) (global $BIG i64 (i64.const 5000000000)) (global $SIZE i32 (i32.const 16)) (func (result i32) (global.get $SIZE)))"#,
+        );
+    }
+}
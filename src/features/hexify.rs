@@ -0,0 +1,143 @@
+use thiserror::Error;
+
+use crate::ast::Node;
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum HexifyError {
+    #[error("Hexifier can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Invalid value for option {0}: {1}")]
+    InvalidOption(String, String),
+}
+
+impl From<HexifyError> for SWLError {
+    fn from(val: HexifyError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+static THRESHOLD_OPTION: &str = "hexify.threshold";
+static SKIP_OFFSETS_OPTION: &str = "hexify.skip_offsets";
+static DEFAULT_THRESHOLD: u64 = 4096;
+
+struct HexifyOptions {
+    threshold: u64,
+    skip_offsets: bool,
+}
+
+fn parse_option<T: std::str::FromStr>(linker: &Linker, key: &str, default: T) -> Result<T> {
+    match linker.option(key) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| HexifyError::InvalidOption(key.to_string(), value.to_string()).into()),
+        None => Ok(default),
+    }
+}
+
+/// Returns true if the value’s set bits form a single contiguous run of at least 8 bits, like `0xFF` or `0xFFFF_0000`.
+fn is_mask_like(v: u64) -> bool {
+    if v == 0 {
+        return false;
+    }
+    let shifted = v >> v.trailing_zeros();
+    shifted & (shifted + 1) == 0 && shifted.count_ones() >= 8
+}
+
+/// Formats a value as hexadecimal, grouping every 4 digits with an underscore.
+fn to_grouped_hex(v: u64) -> String {
+    let digits: Vec<char> = format!("{v:X}").chars().collect();
+    let groups: Vec<String> = digits
+        .rchunks(4)
+        .rev()
+        .map(|chunk| chunk.iter().collect())
+        .collect();
+    format!("0x{}", groups.join("_"))
+}
+
+fn is_const_node(node: &Node) -> bool {
+    node.name == "i32.const" || node.name == "i64.const"
+}
+
+fn is_segment(node: &Node) -> bool {
+    node.name == "data" || node.name == "elem"
+}
+
+fn hexify_node(node: &mut Node, options: &HexifyOptions, is_offset: bool) {
+    if is_const_node(node) && !(is_offset && options.skip_offsets) {
+        for attr in node.immediate_attribute_iter_mut() {
+            let v = match attr.parse::<u64>() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if v >= options.threshold || is_mask_like(v) {
+                *attr = to_grouped_hex(v);
+            }
+        }
+    }
+    let is_segment = is_segment(node);
+    let is_offset_node = is_offset && node.name == "offset";
+    for child in node.immediate_node_iter_mut() {
+        let child_is_offset =
+            (is_segment && (is_const_node(child) || child.name == "offset")) || is_offset_node;
+        hexify_node(child, options, child_is_offset);
+    }
+}
+
+pub fn hexify(module: &mut Node, linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(HexifyError::NotAModule.into());
+    }
+    let options = HexifyOptions {
+        threshold: parse_option(linker, THRESHOLD_OPTION, DEFAULT_THRESHOLD)?,
+        skip_offsets: parse_option(linker, SKIP_OFFSETS_OPTION, false)?,
+    };
+    hexify_node(module, &options, false);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::assert_module_eq;
+
+    fn run_test(input: &str, options: &[(&str, &str)], expected: &str) {
+        let mut linker = Linker::default();
+        linker.features.push(("hexify", hexify));
+        for (key, value) in options {
+            linker.options.insert(key.to_string(), value.to_string());
+        }
+        let got = linker.link_raw(input).unwrap();
+        assert_module_eq(&got, expected);
+    }
+
+    #[test]
+    fn large_constants() {
+        run_test(
+            r#"
+                (module
+                    (func
+                        (i32.and (i32.const 4026531840) (i32.const 12))
+                        (i64.const 255)))
+            "#,
+            &[],
+            "(module (func (i32.and (i32.const 0xF000_0000) (i32.const 12)) (i64.const 0xFF)))",
+        );
+    }
+
+    #[test]
+    fn skip_offsets() {
+        run_test(
+            r#"
+                (module
+                    (data (i32.const 65536) "x")
+                    (data (offset (i32.const 65536)) "x")
+                    (global i32 (i32.const 65536)))
+            "#,
+            &[(SKIP_OFFSETS_OPTION, "true"), (THRESHOLD_OPTION, "1000")],
+            r#"(module (data (i32.const 65536) "x") (data (offset (i32.const 65536)) "x") (global i32 (i32.const 0x1_0000)))"#,
+        );
+    }
+}
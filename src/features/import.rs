@@ -1,6 +1,9 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+
 use thiserror::Error;
 
-use crate::ast::{Item, Node};
+use crate::ast::{DefKind, IdRef, Item, Node};
 use crate::error::{Result, SWLError};
 use crate::linker::Linker;
 use crate::loader::Loader;
@@ -12,6 +15,12 @@ pub enum ImportError {
     NotAModule,
     #[error("Import directive expected a string literal")]
     InvalidImport,
+    #[error("Import alias expected a string literal")]
+    InvalidAlias,
+    #[error("Import of {0} looks like a file import, which is written as (import {0} (file))")]
+    MisformedFileImport(String),
+    #[error("File imports take exactly one path, like (import \"util.wat\" (file)), but {import} has {count} names")]
+    FileImportArity { import: String, count: usize },
 }
 
 impl From<ImportError> for SWLError {
@@ -21,15 +30,109 @@ impl From<ImportError> for SWLError {
 }
 
 fn is_file_import_node(node: &Node) -> bool {
+    let items: Vec<&Item> = node.significant_items().collect();
     node.name == "import"
-        && node.items.len() == 2
-        && node.items[0].as_attribute().is_some()
-        && node.items[1]
+        && items.len() == 2
+        && items[0].as_attribute().is_some()
+        && items[1]
             .as_node()
             .map(|node| node.name == "file")
             .unwrap_or(false)
 }
 
+/// Returns the path of an import like `(import "util.wat" (func ...))`, which is neither a file import nor a valid Wasm import, as the field name is missing.
+fn misformed_file_import(node: &Node) -> Option<&str> {
+    if node.name != "import" {
+        return None;
+    }
+    let items: Vec<&Item> = node.significant_items().collect();
+    match items.as_slice() {
+        [Item::Attribute(path), Item::Node(_), ..]
+            if is_string_literal(path) && path.ends_with(".wat\"") =>
+        {
+            Some(path)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the number of names of an import that ends in `(file)` but isn’t a file import, like the two-level `(import "a" "b" (file))`.
+fn file_import_arity(node: &Node) -> Option<usize> {
+    if node.name != "import" {
+        return None;
+    }
+    let items: Vec<&Item> = node.significant_items().collect();
+    match items.split_last() {
+        Some((Item::Node(last), names)) if last.name == "file" && names.len() != 1 => {
+            Some(names.len())
+        }
+        _ => None,
+    }
+}
+
+/// Returns the alias of an `(import "..." (file (as "alias")))` directive, if any.
+fn import_alias(import_node: &Node) -> Result<Option<String>> {
+    let as_node = import_node
+        .significant_items()
+        .nth(1)
+        .and_then(|item| item.as_node())
+        .and_then(|file_node| {
+            file_node
+                .immediate_node_iter()
+                .find(|node| node.name == "as")
+        });
+    let as_node = match as_node {
+        Some(node) => node,
+        None => return Ok(None),
+    };
+    let alias = as_node
+        .immediate_attribute_iter()
+        .next()
+        .filter(|attr| is_string_literal(attr))
+        .ok_or::<SWLError>(ImportError::InvalidAlias.into())?;
+    Ok(Some(alias[1..alias.len() - 1].to_string()))
+}
+
+/// Returns the id of a definition, which directly follows its keyword.
+fn definition_id_mut(def: &mut Node) -> Option<&mut String> {
+    match def.items.iter_mut().find(|item| !item.is_comment()) {
+        Some(Item::Attribute(attr)) if attr.starts_with('$') => Some(attr),
+        _ => None,
+    }
+}
+
+/// Prefixes all ids defined at the top level of `module` with `alias.` and rewrites all references to them. References are matched per index space, so locals, params and labels keep their names even if a top-level definition has the same id.
+fn prefix_ids(module: &mut Node, alias: &str) {
+    let prefixed = |id: &str| format!("${alias}.{}", &id[1..]);
+    let mut defined_ids: HashSet<(DefKind, String)> = HashSet::new();
+    for node in module.immediate_node_iter_mut() {
+        let def = if node.name == "import" {
+            node.immediate_node_iter_mut().last()
+        } else {
+            Some(node)
+        };
+        let (def, kind) = match def.and_then(|def| def.definition_kind().map(|kind| (def, kind))) {
+            Some(found) => found,
+            None => continue,
+        };
+        if let Some(id) = definition_id_mut(def) {
+            defined_ids.insert((kind, id.clone()));
+            *id = prefixed(id);
+        }
+    }
+
+    let result: std::result::Result<(), Infallible> =
+        module.visit_references_mut(&mut |id_ref, attr| {
+            if let IdRef::Def(kind) = id_ref {
+                if defined_ids.contains(&(kind, attr.clone())) {
+                    *attr = prefixed(attr);
+                }
+            }
+            Ok(())
+        });
+    result.unwrap();
+}
+
 pub fn import(module: &mut Node, linker: &mut Linker) -> Result<()> {
     if !utils::is_module(module) {
         return Err(ImportError::NotAModule.into());
@@ -43,21 +146,32 @@ pub fn import(module: &mut Node, linker: &mut Linker) -> Result<()> {
             _ => continue,
         };
         if !is_file_import_node(import_node) {
+            if let Some(path) = misformed_file_import(import_node) {
+                return Err(ImportError::MisformedFileImport(path.to_string()).into());
+            }
+            if let Some(count) = file_import_arity(import_node) {
+                return Err(ImportError::FileImportArity {
+                    import: format!("{import_node}"),
+                    count,
+                }
+                .into());
+            }
             continue;
         }
 
         // `into_node` guaranteed to not throw by `is_file_import_node`
         let import_node = std::mem::replace(&mut module.items[i - 1], Item::Nothing).into_node();
         // Guaranteed to not throw by `is_file_import_node`
-        let file_path = import_node.items[0].as_attribute().unwrap();
+        let file_path = import_node.immediate_attribute_iter().next().unwrap();
         if !is_string_literal(file_path) {
             return Err(ImportError::InvalidImport.into());
         }
         let unquoted_file_path = &file_path[1..file_path.len() - 1];
-        let imported_module = linker.load_module(unquoted_file_path)?;
-        for item in imported_module.items.into_iter() {
-            module.items.push(item);
+        let mut imported_module = linker.load_module(unquoted_file_path)?;
+        if let Some(alias) = import_alias(&import_node)? {
+            prefix_ids(&mut imported_module, &alias);
         }
+        utils::merge_module_into(module, imported_module)?;
     }
     Ok(())
 }
@@ -69,6 +183,7 @@ mod test {
     use super::*;
     use crate::linker;
     use crate::loader;
+    use crate::test_support::assert_module_eq;
 
     fn run_test<T: AsRef<str>>(inputs: &[T], expected: T) {
         let map: HashMap<String, Vec<u8>> = HashMap::from_iter(
@@ -78,10 +193,10 @@ mod test {
                 .map(|(idx, str)| (format!("{idx}"), str.as_ref().to_string().into_bytes())),
         );
         let mut linker = linker::Linker::new(Box::new(loader::MockLoader { map }));
-        linker.features.push(import);
+        linker.features.push(("import", import));
 
         let module = linker.link_file("0").unwrap();
-        assert_eq!(format!("{module}"), expected.as_ref().trim());
+        assert_module_eq(&module, expected.as_ref());
     }
 
     #[test]
@@ -105,6 +220,28 @@ mod test {
             "#,
         );
     }
+    #[test]
+    fn annotations() {
+        run_test(
+            &[
+                r#"
+                    (module
+                        (@custom "note" "main")
+                        (import "1" (file))
+                        (func $a))
+                "#,
+                r#"
+                    (module
+                        (@name "util")
+                        (func $b (@name "b") (nop)))
+                "#,
+            ],
+            r#"
+                (module (@custom "note" "main") (func $a) (@name "util") (func $b (@name "b") (nop)))
+            "#,
+        );
+    }
+
     #[test]
     fn dedupe_imports() {
         run_test(
@@ -154,4 +291,123 @@ mod test {
             "#,
         );
     }
+
+    #[test]
+    fn spliced_depths() {
+        let map = HashMap::from_iter([(
+            "1".to_string(),
+            "(module (func $b (block (nop))))".to_string().into_bytes(),
+        )]);
+        let mut linker = linker::Linker::new(Box::new(loader::MockLoader { map }));
+        linker.features.push(("import", import));
+
+        let module = linker
+            .link_raw(r#"(module (import "1" (file)) (func $a (block (nop))))"#)
+            .unwrap();
+        let depths: Vec<Vec<usize>> = module
+            .immediate_node_iter()
+            .map(|func| func.node_iter().map(|node| node.depth).collect())
+            .collect();
+        assert_eq!(depths, [[1, 2, 3], [1, 2, 3]]);
+    }
+
+    #[test]
+    fn aliased_import() {
+        run_test(
+            &[
+                r#"
+                    (module
+                        (import "1" (file (as "u")))
+                        (func $helper)
+                        (func $main
+                            (call $u.helper)))
+                "#,
+                r#"
+                    (module
+                        (global $counter (mut i32) (i32.const 0))
+                        (func $helper (export "helper")
+                            (global.set $counter (i32.const 1))
+                            (call $main)))
+                "#,
+            ],
+            r#"
+                (module (func $helper) (func $main (call $u.helper)) (global $u.counter (mut i32) (i32.const 0)) (func $u.helper (export "helper") (global.set $u.counter (i32.const 1)) (call $main)))
+            "#,
+        );
+    }
+
+    #[test]
+    fn aliased_import_keeps_locals() {
+        run_test(
+            &[
+                r#"
+                    (module
+                        (import "1" (file (as "u"))))
+                "#,
+                r#"
+                    (module
+                        (global $x i32 (i32.const 0))
+                        (func $x (param $x i32) (local $f i32)
+                            (block $x
+                                (local.set $f (local.get $x))
+                                (br $x))
+                            local.get $x
+                            global.get $x
+                            call $x
+                            drop
+                            drop)
+                        (export "x" (func $x)))
+                "#,
+            ],
+            r#"
+                (module (global $u.x i32 (i32.const 0)) (func $u.x (param $x i32) (local $f i32) (block $x (local.set $f (local.get $x)) (br $x)) local.get $x global.get $u.x call $u.x drop drop) (export "x" (func $u.x)))
+            "#,
+        );
+    }
+
+    #[test]
+    fn import_with_nothing_item() {
+        let map = HashMap::from_iter([(
+            "1".to_string(),
+            "(module (func $b))".to_string().into_bytes(),
+        )]);
+        let mut linker = linker::Linker::new(Box::new(loader::MockLoader { map }));
+        linker.features.push(("import", import));
+
+        let mut module = crate::parser::Parser::new(r#"(module (import "1" (file)) (func $a))"#)
+            .parse()
+            .unwrap();
+        let import_node = module.items[0].as_node_mut().unwrap();
+        import_node.items.insert(1, Item::Nothing);
+        import_node.items.push(Item::Nothing);
+
+        let module = linker.link_module(module).unwrap();
+        assert_eq!(format!("{module}"), "(module (func $a) (func $b))");
+    }
+
+    #[test]
+    fn two_level_file_import() {
+        let mut linker = linker::Linker::default();
+        linker.features.push(("import", import));
+        let err = linker
+            .link_raw(r#"(module (import "a" "b" (file)))"#)
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            r#"Something else went wrong: File imports take exactly one path, like (import "util.wat" (file)), but (import "a" "b" (file)) has 2 names"#
+        );
+    }
+
+    #[test]
+    fn misformed_file_import() {
+        let mut linker = linker::Linker::default();
+        linker.features.push(("import", import));
+        let err = linker
+            .link_raw(r#"(module (import "util.wat" (func $helper)))"#)
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            r#"Something else went wrong: Import of "util.wat" looks like a file import, which is written as (import "util.wat" (file))"#
+        );
+    }
 }
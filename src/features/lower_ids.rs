@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::ast::{DefKind, IdRef, Item, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum LowerIdsError {
+    #[error("ID lowering can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Unknown {kind} id {id}")]
+    UnknownId { kind: &'static str, id: String },
+}
+
+impl From<LowerIdsError> for SWLError {
+    fn from(val: LowerIdsError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+/// Maps ids to indices for a single index space.
+#[derive(Default)]
+struct IndexSpace {
+    kind: &'static str,
+    imports: Vec<Option<String>>,
+    definitions: Vec<Option<String>>,
+}
+
+impl IndexSpace {
+    fn new(kind: &'static str) -> IndexSpace {
+        IndexSpace {
+            kind,
+            ..Default::default()
+        }
+    }
+
+    fn index_of(&self, id: &str) -> Option<usize> {
+        self.imports
+            .iter()
+            .chain(self.definitions.iter())
+            .position(|entry| entry.as_deref() == Some(id))
+    }
+
+    fn lower(&self, attr: &mut String) -> Result<()> {
+        if !attr.starts_with('$') {
+            return Ok(());
+        }
+        let idx = self.index_of(attr).ok_or(LowerIdsError::UnknownId {
+            kind: self.kind,
+            id: attr.clone(),
+        })?;
+        *attr = format!("{idx}");
+        Ok(())
+    }
+}
+
+struct IndexSpaces {
+    spaces: HashMap<&'static str, IndexSpace>,
+    /// Number of parameters declared by each type, needed to number the locals of functions that only reference a type.
+    type_params: HashMap<usize, usize>,
+}
+
+impl IndexSpaces {
+    fn get(&self, kind: &str) -> &IndexSpace {
+        &self.spaces[kind]
+    }
+
+    fn lower(&self, kind: &str, attr: &mut String) -> Result<()> {
+        self.get(kind).lower(attr)
+    }
+}
+
+/// Returns the id of a definition, `param` or `local`, which has to be its first item.
+fn id_attribute(node: &Node) -> Option<String> {
+    match node.significant_items().next() {
+        Some(Item::Attribute(attr)) if attr.starts_with('$') => Some(attr.clone()),
+        _ => None,
+    }
+}
+
+fn remove_id_attribute(node: &mut Node) {
+    let pos = node
+        .items
+        .iter()
+        .position(|item| !item.is_nothing() && !item.is_comment());
+    if let Some(pos) = pos {
+        if node.items[pos]
+            .as_attribute()
+            .map(|attr| attr.starts_with('$'))
+            == Some(true)
+        {
+            node.items.remove(pos);
+        }
+    }
+}
+
+fn is_importable(node: &Node) -> bool {
+    node.definition_kind()
+        .map(|kind| kind.is_importable())
+        .unwrap_or(false)
+}
+
+fn is_inline_import(node: &Node) -> bool {
+    node.immediate_node_iter().any(|node| node.name == "import")
+}
+
+/// Returns the kind node of an `(import "mod" "field" (kind ...))` directive.
+fn import_descriptor(node: &Node) -> Option<&Node> {
+    node.immediate_node_iter().find(|node| is_importable(node))
+}
+
+fn import_descriptor_mut(node: &mut Node) -> Option<&mut Node> {
+    node.immediate_node_iter_mut()
+        .find(|node| is_importable(node))
+}
+
+/// Counts the values declared by `param` or `local` nodes. `(param $p i32)` declares one value, `(param i32 i64)` declares two.
+fn declared_values(node: &Node) -> Vec<Option<String>> {
+    match id_attribute(node) {
+        Some(id) => vec![Some(id)],
+        None => node.immediate_attribute_iter().map(|_| None).collect(),
+    }
+}
+
+fn collect_index_spaces(module: &Node) -> IndexSpaces {
+    let mut spaces: HashMap<&'static str, IndexSpace> = DefKind::ALL
+        .iter()
+        .map(|kind| (kind.name(), IndexSpace::new(kind.name())))
+        .collect();
+    let mut type_params = HashMap::new();
+    for node in module.immediate_node_iter() {
+        if node.name == "import" {
+            if let Some(descriptor) = import_descriptor(node) {
+                spaces
+                    .get_mut(descriptor.name.as_str())
+                    .unwrap()
+                    .imports
+                    .push(id_attribute(descriptor));
+            }
+            continue;
+        }
+        let space = match spaces.get_mut(node.name.as_str()) {
+            Some(space) => space,
+            None => continue,
+        };
+        if node.name == "type" {
+            let num_params = node
+                .immediate_node_iter()
+                .flat_map(|func| func.immediate_node_iter())
+                .filter(|node| node.name == "param")
+                .map(|node| declared_values(node).len())
+                .sum();
+            type_params.insert(space.definitions.len(), num_params);
+        }
+        if is_importable(node) && is_inline_import(node) {
+            space.imports.push(id_attribute(node));
+        } else {
+            space.definitions.push(id_attribute(node));
+        }
+    }
+    IndexSpaces {
+        spaces,
+        type_params,
+    }
+}
+
+fn collect_locals(func: &Node, spaces: &IndexSpaces) -> Result<IndexSpace> {
+    let mut locals = IndexSpace::new("local");
+    for node in func.immediate_node_iter() {
+        match node.name.as_str() {
+            "param" | "local" => locals.definitions.extend(declared_values(node)),
+            _ => {}
+        }
+    }
+    let has_inline_params = func.immediate_node_iter().any(|node| node.name == "param");
+    let type_use = func
+        .immediate_node_iter()
+        .find(|node| node.name == "type")
+        .and_then(|node| node.immediate_attribute_iter().next());
+    if let (false, Some(type_use)) = (has_inline_params, type_use) {
+        let mut type_idx = type_use.to_string();
+        spaces.lower("type", &mut type_idx)?;
+        let num_params = type_idx
+            .parse::<usize>()
+            .ok()
+            .and_then(|idx| spaces.type_params.get(&idx))
+            .cloned()
+            .unwrap_or(0);
+        locals.imports = vec![None; num_params];
+    }
+    Ok(locals)
+}
+
+fn is_label_keyword(keyword: &str) -> bool {
+    matches!(keyword, "block" | "loop" | "if" | "try")
+}
+
+/// Returns the relative depth of the innermost label `id`, skipping the `skip` innermost labels.
+fn resolve_label(labels: &[Option<String>], id: &str, skip: usize) -> Result<usize> {
+    labels
+        .iter()
+        .rev()
+        .skip(skip)
+        .position(|label| label.as_deref() == Some(id))
+        .map(|depth| depth + skip)
+        .ok_or_else(|| {
+            LowerIdsError::UnknownId {
+                kind: "label",
+                id: id.to_string(),
+            }
+            .into()
+        })
+}
+
+/// Removes the id following the item at `idx`, if there is one, and returns it.
+fn take_label(node: &mut Node, idx: usize) -> Option<String> {
+    let pos = (idx..node.items.len())
+        .find(|&pos| !node.items[pos].is_nothing() && !node.items[pos].is_comment())?;
+    match &node.items[pos] {
+        Item::Attribute(attr) if attr.starts_with('$') => {
+            let label = attr.clone();
+            node.items.remove(pos);
+            Some(label)
+        }
+        _ => None,
+    }
+}
+
+/// Removes the labels bound by blocks and rewrites branches to them to relative depths, in both folded and flat instructions. `labels` holds the labels of the enclosing blocks, innermost last.
+fn lower_labels(node: &mut Node, labels: &mut Vec<Option<String>>) -> Result<()> {
+    let is_label_node = is_label_keyword(&node.name);
+    if is_label_node {
+        let label = take_label(node, 0);
+        labels.push(label);
+    }
+    let mut keyword = Some(node.name.clone());
+    let mut idx = 0;
+    while idx < node.items.len() {
+        match &mut node.items[idx] {
+            Item::Attribute(attr) if attr.starts_with('$') => match keyword.as_deref() {
+                Some("br" | "br_if" | "br_table") => {
+                    *attr = format!("{}", resolve_label(labels, attr, 0)?);
+                }
+                // `delegate` targets a label outside of the `try` it ends, which is still open in the folded form.
+                Some("delegate") => {
+                    let skip = usize::from(node.name == "delegate");
+                    *attr = format!("{}", resolve_label(labels, attr, skip)?);
+                }
+                _ => {}
+            },
+            // Numeric targets of a `br_table` can be mixed with labels.
+            Item::Attribute(attr)
+                if keyword.as_deref() == Some("br_table") && attr.parse::<usize>().is_ok() => {}
+            Item::Attribute(attr) => {
+                let attr = attr.clone();
+                if is_label_keyword(&attr) {
+                    let label = take_label(node, idx + 1);
+                    labels.push(label);
+                } else if attr == "end" || attr == "delegate" {
+                    labels.pop();
+                }
+                // Labels repeated after `else` or `end` only have to match their block.
+                if attr == "end" || attr == "else" {
+                    take_label(node, idx + 1);
+                }
+                keyword = Some(attr);
+            }
+            Item::Node(child) => {
+                lower_labels(child, labels)?;
+                keyword = None;
+            }
+            _ => {}
+        }
+        idx += 1;
+    }
+    if is_label_node {
+        labels.pop();
+    }
+    Ok(())
+}
+
+fn lower_top_level_node(node: &mut Node, spaces: &IndexSpaces) -> Result<()> {
+    let locals = match node.name.as_str() {
+        "func" => collect_locals(node, spaces)?,
+        _ => IndexSpace::new("local"),
+    };
+    node.visit_references_mut(&mut |id_ref, attr| match id_ref {
+        IdRef::Def(kind) => spaces.lower(kind.name(), attr),
+        IdRef::Local => locals.lower(attr),
+    })?;
+    if node.name == "func" {
+        lower_labels(node, &mut vec![])?;
+    }
+    let def = match node.name.as_str() {
+        "import" => import_descriptor_mut(node),
+        _ if node.is_definition() => Some(node),
+        _ => None,
+    };
+    if let Some(def) = def {
+        remove_id_attribute(def);
+        for child in def.node_iter_mut() {
+            if child.name == "param" || child.name == "local" {
+                remove_id_attribute(child);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn lower_ids(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(LowerIdsError::NotAModule.into());
+    }
+    let spaces = collect_index_spaces(module);
+    for node in module.immediate_node_iter_mut() {
+        lower_top_level_node(node, &spaces)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::linker;
+    use crate::loader;
+    use crate::test_support::assert_module_eq;
+
+    fn run_test<T: AsRef<str>>(inputs: &[T], expected: T) {
+        let map: HashMap<String, Vec<u8>> = HashMap::from_iter(
+            inputs
+                .iter()
+                .enumerate()
+                .map(|(idx, str)| (format!("{idx}"), str.as_ref().to_string().into_bytes())),
+        );
+        let mut linker = linker::Linker::new(Box::new(loader::MockLoader { map }));
+        linker.features.push(("lower_ids", lower_ids));
+
+        let module = linker.link_file("0").unwrap();
+        assert_module_eq(&module, expected.as_ref());
+    }
+
+    #[test]
+    fn simple_call() {
+        run_test(
+            &[r#"
+                (module
+                    (func $main (export "main")
+                        (call $f))
+                    (func $f))
+            "#],
+            r#"
+                (module (func (export "main") (call 1)) (func))
+            "#,
+        );
+    }
+
+    #[test]
+    fn imports_come_first() {
+        run_test(
+            &[r#"
+                (module
+                    (func $main
+                        (call $log (global.get $g)))
+                    (global $g i32 (i32.const 0))
+                    (import "env" "log" (func $log (param i32)))
+                    (import "env" "g" (global $imported i32))
+                    (export "main" (func $main)))
+            "#],
+            r#"
+                (module (func (call 0 (global.get 1))) (global i32 (i32.const 0)) (import "env" "log" (func (param i32))) (import "env" "g" (global i32)) (export "main" (func 1)))
+            "#,
+        );
+    }
+
+    #[test]
+    fn locals_and_types() {
+        run_test(
+            &[r#"
+                (module
+                    (type $sig (func (param i32 i32)))
+                    (func $f (type $sig) (local $tmp i32)
+                        (local.set $tmp (local.get 1)))
+                    (func $g (param $a i32) (local $b i32)
+                        (call_indirect (type $sig) (local.get $a) (local.get $b) (i32.const 0))))
+            "#],
+            r#"
+                (module (type (func (param i32 i32))) (func (type 0) (local i32) (local.set 2 (local.get 1))) (func (param i32) (local i32) (call_indirect (type 0) (local.get 0) (local.get 1) (i32.const 0))))
+            "#,
+        );
+    }
+
+    #[test]
+    fn flat_instructions() {
+        run_test(
+            &[r#"
+                (module
+                    (global $g (mut i32) (i32.const 0))
+                    (func $main (param $x i32)
+                        local.get $x
+                        global.set $g
+                        call $f)
+                    (func $f))
+            "#],
+            r#"
+                (module (global (mut i32) (i32.const 0)) (func (param i32) local.get 0 global.set 0 call 1) (func))
+            "#,
+        );
+    }
+
+    #[test]
+    fn folded_labels() {
+        run_test(
+            &[r#"
+                (module
+                    (func
+                        (block $outer
+                            (loop $inner
+                                (br_if $inner (i32.const 1))
+                                (br $outer)))))
+            "#],
+            r#"
+                (module (func (block (loop (br_if 0 (i32.const 1)) (br 1)))))
+            "#,
+        );
+    }
+
+    #[test]
+    fn flat_labels() {
+        run_test(
+            &[r#"
+                (module
+                    (func
+                        block $outer (result i32)
+                            i32.const 0
+                            if $cond
+                                br $outer
+                            else $cond
+                                block
+                                    br_table $cond 0 $outer
+                                end
+                            end $cond
+                            i32.const 1
+                        end $outer
+                        drop))
+            "#],
+            r#"
+                (module (func block (result i32) i32.const 0 if br 1 else block br_table 1 0 2 end end i32.const 1 end drop))
+            "#,
+        );
+    }
+
+    #[test]
+    fn unknown_label() {
+        let mut linker = Linker::default();
+        linker.features.push(("lower_ids", lower_ids));
+        let err = linker
+            .link_raw("(module (func (block $l) (br $l)))")
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Unknown label id $l"
+        );
+    }
+
+    #[test]
+    fn unknown_id() {
+        let mut linker = Linker::default();
+        linker.features.push(("lower_ids", lower_ids));
+        assert!(linker.link_raw("(module (func (call $missing)))").is_err());
+    }
+}
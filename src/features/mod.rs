@@ -2,12 +2,205 @@ use crate::ast::Node;
 use crate::error::Result;
 use crate::linker::Linker;
 
+pub mod align_data;
+pub mod assert_memory;
+pub mod check_exports;
+pub mod check_import_sigs;
+pub mod check_indirect;
+pub mod check_known_sections;
+pub mod check_unused_imports;
 pub mod constexpr;
+pub mod data_dedupe;
 pub mod data_import;
+pub mod declare_refs;
+pub mod define_globals;
+pub mod hexify;
 pub mod import;
+pub mod lower_ids;
+pub mod modernize;
 pub mod numerals;
+pub mod passive_data;
+pub mod producers;
+pub mod repeat;
+pub mod simplify_control;
 pub mod size_adjust;
 pub mod sort;
+pub mod split_data;
 pub mod start_merge;
+pub mod table_adjust;
+pub mod template;
+pub mod tree_shake;
 
 pub type Feature = fn(&mut Node, &mut Linker) -> Result<()>;
+
+/// All features, addressable by name.
+pub static FEATURES: &[(&str, Feature)] = &[
+    ("import", import::import),
+    ("check_import_sigs", check_import_sigs::check_import_sigs),
+    ("check_indirect", check_indirect::check_indirect),
+    (
+        "check_known_sections",
+        check_known_sections::check_known_sections,
+    ),
+    (
+        "check_unused_imports",
+        check_unused_imports::check_unused_imports,
+    ),
+    ("sort", sort::sort),
+    ("align_data", align_data::align_data),
+    ("size_adjust", size_adjust::size_adjust),
+    ("assert_memory", assert_memory::assert_memory),
+    ("table_adjust", table_adjust::table_adjust),
+    ("passive_data", passive_data::passive_data),
+    ("start_merge", start_merge::start_merge),
+    ("data_import", data_import::data_import),
+    ("define_globals", define_globals::define_globals),
+    ("template", template::template),
+    ("constexpr", constexpr::constexpr),
+    ("data_dedupe", data_dedupe::data_dedupe),
+    ("declare_refs", declare_refs::declare_refs),
+    ("split_data", split_data::split_data),
+    ("tree_shake", tree_shake::tree_shake),
+    ("check_exports", check_exports::check_exports),
+    ("numerals", numerals::numerals),
+    ("repeat", repeat::repeat),
+    ("lower_ids", lower_ids::lower_ids),
+    ("simplify_control", simplify_control::simplify_control),
+    ("hexify", hexify::hexify),
+    ("producers", producers::producers),
+    ("modernize", modernize::modernize),
+];
+
+/// How a feature depends on the features it has to run after.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dependency {
+    /// The prerequisite is enabled automatically if it wasn’t requested.
+    Requires,
+    /// The prerequisite only has to run first if it is enabled as well.
+    After,
+}
+
+/// Features that have to run after other features for them to produce correct results.
+pub static DEPENDENCIES: &[(&str, Dependency, &[&str])] = &[
+    ("constexpr", Dependency::Requires, &["numerals"]),
+    ("check_exports", Dependency::After, &["tree_shake"]),
+    ("check_known_sections", Dependency::After, &["import"]),
+];
+
+fn runs_after(name: &str, dep: &str) -> bool {
+    DEPENDENCIES
+        .iter()
+        .any(|(key, _, deps)| *key == name && deps.contains(&dep))
+}
+
+/// Inserts missing `Dependency::Requires` prerequisites right before the features that need them. Prerequisites the user listed anywhere are left where they are. Returns the resolved feature list and, for every inserted feature, the feature that required it.
+pub fn resolve_dependencies<'a>(names: &[&'a str]) -> (Vec<&'a str>, Vec<(&'a str, &'a str)>) {
+    fn add<'a>(
+        name: &'a str,
+        names: &[&str],
+        resolved: &mut Vec<&'a str>,
+        inserted: &mut Vec<(&'a str, &'a str)>,
+    ) {
+        let deps = DEPENDENCIES
+            .iter()
+            .filter(|(key, kind, _)| *key == name && *kind == Dependency::Requires)
+            .flat_map(|(_, _, deps)| deps.iter());
+        for &dep in deps {
+            if !names.contains(&dep) && !resolved.contains(&dep) {
+                add(dep, names, resolved, inserted);
+                inserted.push((dep, name));
+            }
+        }
+        resolved.push(name);
+    }
+
+    let mut resolved = vec![];
+    let mut inserted = vec![];
+    for &name in names {
+        if !resolved.contains(&name) {
+            add(name, names, &mut resolved, &mut inserted);
+        }
+    }
+    (resolved, inserted)
+}
+
+/// Adds a feature that was enabled by a flag rather than listed by the user. It goes right after the last listed feature it has to run after, or else right before the first listed feature that has to run after it, or else at the end.
+pub fn insert_feature<'a>(names: &mut Vec<&'a str>, name: &'a str) {
+    if names.contains(&name) {
+        return;
+    }
+    let idx = names
+        .iter()
+        .rposition(|other| runs_after(name, other))
+        .map(|idx| idx + 1)
+        .or_else(|| names.iter().position(|other| runs_after(other, name)))
+        .unwrap_or(names.len());
+    names.insert(idx, name);
+}
+
+/// Renders `DEPENDENCIES` as a DOT graph, with an edge from every feature to the features that have to run after it. Edges that don’t enable the prerequisite are dashed.
+pub fn dependency_graph() -> String {
+    let mut graph = "digraph features {\n".to_string();
+    for (feature, kind, deps) in DEPENDENCIES {
+        let style = match kind {
+            Dependency::Requires => "",
+            Dependency::After => " [style=dashed]",
+        };
+        for dep in deps.iter() {
+            graph += &format!("    {dep} -> {feature}{style};\n");
+        }
+    }
+    graph += "}\n";
+    graph
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn inserts_missing_dependencies() {
+        let (resolved, inserted) = resolve_dependencies(&["import", "constexpr"]);
+        assert_eq!(resolved, vec!["import", "numerals", "constexpr"]);
+        assert_eq!(inserted, vec![("numerals", "constexpr")]);
+
+        let (resolved, inserted) = resolve_dependencies(&["numerals", "constexpr"]);
+        assert_eq!(resolved, vec!["numerals", "constexpr"]);
+        assert!(inserted.is_empty());
+    }
+
+    #[test]
+    fn keeps_listed_dependencies_in_place() {
+        let (resolved, inserted) = resolve_dependencies(&["constexpr", "numerals"]);
+        assert_eq!(resolved, vec!["constexpr", "numerals"]);
+        assert!(inserted.is_empty());
+    }
+
+    #[test]
+    fn ordering_dependencies_are_not_enabled() {
+        let (resolved, inserted) = resolve_dependencies(&["import", "check_exports"]);
+        assert_eq!(resolved, vec!["import", "check_exports"]);
+        assert!(inserted.is_empty());
+    }
+
+    #[test]
+    fn inserts_flag_features_in_order() {
+        let mut names = vec!["import", "check_exports", "sort"];
+        insert_feature(&mut names, "tree_shake");
+        insert_feature(&mut names, "check_known_sections");
+        assert_eq!(
+            names,
+            vec![
+                "import",
+                "check_known_sections",
+                "tree_shake",
+                "check_exports",
+                "sort"
+            ]
+        );
+
+        let mut names = vec!["import", "sort"];
+        insert_feature(&mut names, "tree_shake");
+        assert_eq!(names, vec!["import", "sort", "tree_shake"]);
+    }
+}
@@ -0,0 +1,109 @@
+use thiserror::Error;
+
+use crate::ast::Node;
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum ModernizeError {
+    #[error("Modernizer can only be applied to top-level modules")]
+    NotAModule,
+}
+
+impl From<ModernizeError> for SWLError {
+    fn from(val: ModernizeError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+static RENAMES: &[(&str, &str)] = &[
+    ("get_local", "local.get"),
+    ("set_local", "local.set"),
+    ("tee_local", "local.tee"),
+    ("get_global", "global.get"),
+    ("set_global", "global.set"),
+    ("current_memory", "memory.size"),
+    ("grow_memory", "memory.grow"),
+];
+
+/// Returns the current name of a legacy instruction, if it is one.
+///
+/// Besides the fixed renames, this handles conversions like `i32.trunc_s/f32`, where the source type moved before the signedness (`i32.trunc_f32_s`), and their saturating variants like `i32.trunc_s:sat/f32` (`i32.trunc_sat_f32_s`).
+fn modern_name(name: &str) -> Option<String> {
+    if let Some(&(_, modern)) = RENAMES.iter().find(|&&(legacy, _)| legacy == name) {
+        return Some(modern.to_string());
+    }
+    let (op, source) = name.split_once('/')?;
+    // Leave anything that isn’t a numeric instruction alone, like strings containing a slash.
+    let (typ, _) = op.split_once('.')?;
+    if !["i32", "i64", "f32", "f64"].contains(&typ) {
+        return None;
+    }
+    let (op, saturating) = match op.strip_suffix(":sat") {
+        Some(op) => (op, true),
+        None => (op, false),
+    };
+    let (op, sign) = match op.rsplit_once('_') {
+        Some((op, sign)) if sign == "s" || sign == "u" => (op, Some(sign)),
+        _ => (op, None),
+    };
+    let mut modern = op.to_string();
+    if saturating {
+        modern += "_sat";
+    }
+    modern += "_";
+    modern += source;
+    if let Some(sign) = sign {
+        modern += "_";
+        modern += sign;
+    }
+    Some(modern)
+}
+
+pub fn modernize(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(ModernizeError::NotAModule.into());
+    }
+    for node in module.node_iter_mut() {
+        if let Some(name) = modern_name(&node.name) {
+            node.name = name;
+        }
+    }
+    // Instructions in non-folded form are attributes.
+    module.for_each_attribute_mut(|attr| {
+        if let Some(name) = modern_name(attr) {
+            *attr = name;
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::assert_module_eq;
+
+    fn run_test(input: &str, expected: &str) {
+        let mut linker = Linker::default();
+        linker.features.push(("modernize", modernize));
+        let module = linker.link_raw(input).unwrap();
+        assert_module_eq(&module, expected);
+    }
+
+    #[test]
+    fn locals_and_globals() {
+        run_test(
+            r#"(module (import "a/b" "c" (func)) (func (set_global $g (get_local $x)) get_local 0 tee_local 1 drop))"#,
+            r#"(module (import "a/b" "c" (func)) (func (global.set $g (local.get $x)) local.get 0 local.tee 1 drop))"#,
+        );
+    }
+
+    #[test]
+    fn conversions() {
+        run_test(
+            "(module (func (i32.trunc_s/f32 (f32.convert_u/i64 (i64.extend_s/i32 (i32.wrap/i64 (i64.trunc_u:sat/f64 (f64.promote/f32 (get_local 0)))))))))",
+            "(module (func (i32.trunc_f32_s (f32.convert_i64_u (i64.extend_i32_s (i32.wrap_i64 (i64.trunc_sat_f64_u (f64.promote_f32 (local.get 0)))))))))",
+        );
+    }
+}
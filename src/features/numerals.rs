@@ -17,23 +17,38 @@ impl From<NumeralsError> for SWLError {
 }
 
 pub fn numerals(module: &mut Node, _linker: &mut Linker) -> Result<()> {
-    for attr in module
-        .node_iter_mut()
-        .flat_map(|node| node.immediate_attribute_iter_mut())
-    {
-        if attr.starts_with("0x") {
-            let v = i64::from_str_radix(&attr.replace('_', "")[2..], 16).map_err(|_| {
-                SWLError::Other(NumeralsError::InvalidNumericLiteral(attr.to_string()).into())
-            })?;
-            *attr = format!("{v}");
-        }
-        if attr.starts_with("0b") {
-            let v = i64::from_str_radix(&attr.replace('_', "")[2..], 2).map_err(|_| {
-                SWLError::Other(NumeralsError::InvalidNumericLiteral(attr.to_string()).into())
-            })?;
-            *attr = format!("{v}");
+    normalize_numerals(module)
+}
+
+/// Rewrites all hexadecimal and binary numeric literals in the given subtree to decimal.
+pub fn normalize_numerals(node: &mut Node) -> Result<()> {
+    let mut result = Ok(());
+    node.for_each_attribute_mut(|attr| {
+        if result.is_err() {
+            return;
         }
-    }
+        result = normalize_numeral(attr);
+    });
+    result
+}
+
+/// Returns true for hexadecimal float literals like `0x1.8p3`, which have no integer representation.
+fn is_hex_float(attr: &str) -> bool {
+    attr.starts_with("0x") && attr.contains(['.', 'p', 'P'])
+}
+
+fn normalize_numeral(attr: &mut String) -> Result<()> {
+    let radix = if attr.starts_with("0x") && !is_hex_float(attr) {
+        16
+    } else if attr.starts_with("0b") {
+        2
+    } else {
+        return Ok(());
+    };
+    let v = i64::from_str_radix(&attr.replace('_', "")[2..], radix).map_err(|_| {
+        SWLError::Other(NumeralsError::InvalidNumericLiteral(attr.to_string()).into())
+    })?;
+    *attr = format!("{v}");
     Ok(())
 }
 
@@ -44,6 +59,7 @@ mod test {
     use super::*;
     use crate::linker;
     use crate::loader;
+    use crate::test_support::assert_module_eq;
 
     fn run_test<T: AsRef<str>>(inputs: &[T], expected: T) {
         let map: HashMap<String, Vec<u8>> = HashMap::from_iter(
@@ -53,10 +69,10 @@ mod test {
                 .map(|(idx, str)| (format!("{idx}"), str.as_ref().to_string().into_bytes())),
         );
         let mut linker = linker::Linker::new(Box::new(loader::MockLoader { map }));
-        linker.features.push(numerals);
+        linker.features.push(("numerals", numerals));
 
         let module = linker.link_file("0").unwrap();
-        assert_eq!(format!("{module}"), expected.as_ref().trim());
+        assert_module_eq(&module, expected.as_ref());
     }
 
     #[test]
@@ -86,4 +102,20 @@ mod test {
             "#,
         );
     }
+
+    #[test]
+    fn hex_floats_untouched() {
+        run_test(
+            &[r#"
+                (module
+                    (global f64 (f64.const 0x1.8p3))
+                    (global f32 (f32.const 0x1p-2))
+                    (global f32 (f32.const 0x10))
+                )
+            "#],
+            r#"
+                (module (global f64 (f64.const 0x1.8p3)) (global f32 (f32.const 0x1p-2)) (global f32 (f32.const 16)))
+            "#,
+        );
+    }
 }
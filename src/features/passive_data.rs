@@ -0,0 +1,225 @@
+use thiserror::Error;
+
+use crate::ast::{Item, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils::{self, find_id_attribute, interpreted_string_length, is_string_literal};
+
+#[derive(Error, Debug)]
+pub enum PassiveDataError {
+    #[error("Passive data converter can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Offset is missing expression argument")]
+    InvalidOffset,
+    #[error("Start directive needs a function id or index")]
+    InvalidStartDirective,
+}
+
+impl From<PassiveDataError> for SWLError {
+    fn from(val: PassiveDataError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+static SWL_INIT_FUNC_ID: &str = "$_swl_passive_data_init";
+static SWL_SEGMENT_ID_PREFIX: &str = "$_swl_passive_data_";
+
+fn data_size(data_seg: &Node) -> Result<usize> {
+    let data_sizes: Vec<usize> = Result::from_iter(
+        data_seg
+            .immediate_attribute_iter()
+            .filter(|&attr| is_string_literal(attr))
+            .map(|s| interpreted_string_length(&s[1..s.len() - 1])),
+    )?;
+    Ok(data_sizes.into_iter().sum())
+}
+
+fn instr(name: &str, depth: usize, items: Vec<Item>) -> Node {
+    Node {
+        name: name.to_string(),
+        depth,
+        items,
+        span: None,
+        leading_comments: vec![],
+        trailing_comments: vec![],
+    }
+}
+
+/// Turns an active data segment into a passive one and returns the instructions that initialize it. Returns nothing for segments that are passive already.
+fn convert_segment(data_seg: &mut Node, idx: usize) -> Result<Vec<Node>> {
+    let is_offset_item = |item: &Item| {
+        item.as_node()
+            .map(|node| node.name != "memory")
+            .unwrap_or(false)
+    };
+    if !data_seg.items.iter().any(is_offset_item) {
+        return Ok(vec![]);
+    }
+
+    let depth = data_seg.depth + 1;
+    let len = data_size(data_seg)?;
+    let memory_id = match utils::find_child_node_item_mut(data_seg, |node| node.name == "memory") {
+        Some(item) => std::mem::replace(item, Item::Nothing)
+            .into_node()
+            .immediate_attribute_iter()
+            .next()
+            .map(|attr| attr.to_string()),
+        None => None,
+    };
+    let offset_pos = data_seg.items.iter().position(is_offset_item).unwrap();
+    let mut offset_node = data_seg.items.remove(offset_pos).into_node();
+    if offset_node.name == "offset" {
+        offset_node = offset_node
+            .items
+            .into_iter()
+            .find_map(|item| item.as_node().cloned())
+            .ok_or::<SWLError>(PassiveDataError::InvalidOffset.into())?;
+    }
+    data_seg.items.retain(|item| !item.is_nothing());
+
+    let segment_id = match find_id_attribute(data_seg) {
+        Some(id) => id.to_string(),
+        None => {
+            let id = format!("{SWL_SEGMENT_ID_PREFIX}{idx}");
+            data_seg.items.insert(0, Item::Attribute(id.clone()));
+            id
+        }
+    };
+
+    offset_node.depth = depth + 1;
+    let init = instr(
+        "memory.init",
+        depth,
+        memory_id
+            .into_iter()
+            .chain([segment_id.clone()])
+            .map(Item::Attribute)
+            .chain([
+                Item::Node(offset_node),
+                Item::Node(instr(
+                    "i32.const",
+                    depth + 1,
+                    vec![Item::Attribute("0".to_string())],
+                )),
+                Item::Node(instr(
+                    "i32.const",
+                    depth + 1,
+                    vec![Item::Attribute(format!("{len}"))],
+                )),
+            ])
+            .collect(),
+    );
+    let drop = instr("data.drop", depth, vec![Item::Attribute(segment_id)]);
+    Ok(vec![init, drop])
+}
+
+pub fn passive_data(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(PassiveDataError::NotAModule.into());
+    }
+    let mut body = vec![];
+    for (idx, data_seg) in module
+        .immediate_node_iter_mut()
+        .filter(|node| node.name == "data")
+        .enumerate()
+    {
+        body.extend(convert_segment(data_seg, idx)?);
+    }
+    if body.is_empty() {
+        return Ok(());
+    }
+
+    // The initializer has to run before the start function, so it takes over
+    // an existing start directive and calls the previous start function last.
+    let depth = module.depth + 1;
+    let start = module
+        .immediate_node_iter_mut()
+        .find(|node| node.name == "start");
+    let has_start = start.is_some();
+    if let Some(start) = start {
+        let previous = find_id_attribute(start)
+            .ok_or::<SWLError>(PassiveDataError::InvalidStartDirective.into())?
+            .to_string();
+        start.items = vec![Item::Attribute(SWL_INIT_FUNC_ID.to_string())];
+        body.push(instr("call", depth + 1, vec![Item::Attribute(previous)]));
+    }
+    module.append_node(instr(
+        "func",
+        depth,
+        [Item::Attribute(SWL_INIT_FUNC_ID.to_string())]
+            .into_iter()
+            .chain(body.into_iter().map(Item::Node))
+            .collect(),
+    ));
+    if !has_start {
+        module.append_node(instr(
+            "start",
+            depth,
+            vec![Item::Attribute(SWL_INIT_FUNC_ID.to_string())],
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_test(input: &str, expected: &str) {
+        let mut linker = Linker::default();
+        linker.features.push(("passive_data", passive_data));
+        let got = linker.link_raw(input).unwrap();
+        let expected = Linker::default().link_raw(expected).unwrap();
+        assert_eq!(format!("{got}"), format!("{expected}"));
+    }
+
+    #[test]
+    fn active_to_passive() {
+        run_test(
+            r#"
+                (module
+                    (memory 1)
+                    (data (i32.const 16) "abc\00")
+                    (data $named "x")
+                    (func $main)
+                    (start $main))
+            "#,
+            &format!(
+                r#"
+                    (module
+                        (memory 1)
+                        (data {SWL_SEGMENT_ID_PREFIX}0 "abc\00")
+                        (data $named "x")
+                        (func $main)
+                        (start {SWL_INIT_FUNC_ID})
+                        (func {SWL_INIT_FUNC_ID}
+                            (memory.init {SWL_SEGMENT_ID_PREFIX}0 (i32.const 16) (i32.const 0) (i32.const 4))
+                            (data.drop {SWL_SEGMENT_ID_PREFIX}0)
+                            (call $main)))
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn adds_start_directive() {
+        run_test(
+            r#"
+                (module
+                    (memory 1)
+                    (data (i32.const 0) "ab"))
+            "#,
+            &format!(
+                r#"
+                    (module
+                        (memory 1)
+                        (data {SWL_SEGMENT_ID_PREFIX}0 "ab")
+                        (func {SWL_INIT_FUNC_ID}
+                            (memory.init {SWL_SEGMENT_ID_PREFIX}0 (i32.const 0) (i32.const 0) (i32.const 2))
+                            (data.drop {SWL_SEGMENT_ID_PREFIX}0))
+                        (start {SWL_INIT_FUNC_ID}))
+                "#
+            ),
+        );
+    }
+}
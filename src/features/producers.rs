@@ -0,0 +1,129 @@
+use thiserror::Error;
+
+use crate::ast::{Item, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::parser::Parser;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum ProducersError {
+    #[error("Producers can only be applied to top-level modules")]
+    NotAModule,
+}
+
+impl From<ProducersError> for SWLError {
+    fn from(val: ProducersError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+static PRODUCERS_NAME: &str = "@producers";
+static PROCESSED_BY_NAME: &str = "processed-by";
+
+fn our_name() -> String {
+    format!("\"{}\"", env!("CARGO_PKG_NAME"))
+}
+
+fn is_our_entry(node: &Node) -> bool {
+    node.name == PROCESSED_BY_NAME
+        && node.immediate_attribute_iter().next() == Some(our_name().as_str())
+}
+
+fn processed_by_node(depth: usize) -> Result<Node> {
+    let mut node = Parser::new(format!(
+        "({PROCESSED_BY_NAME} {} \"{}\")",
+        our_name(),
+        env!("CARGO_PKG_VERSION")
+    ))
+    .parse()?;
+    node.depth = depth;
+    Ok(node)
+}
+
+/// Adds this linker to the `(@producers ...)` annotation of a module, creating the annotation if necessary. Existing entries for other tools are kept.
+pub fn producers(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(ProducersError::NotAModule.into());
+    }
+    let depth = module.depth + 1;
+    let producers_node = module
+        .immediate_node_iter_mut()
+        .find(|node| node.name == PRODUCERS_NAME);
+    match producers_node {
+        Some(producers_node) => {
+            producers_node.items.retain(|item| {
+                item.as_node()
+                    .map(|node| !is_our_entry(node))
+                    .unwrap_or(true)
+            });
+            producers_node
+                .items
+                .push(Item::Node(processed_by_node(depth + 1)?));
+        }
+        None => {
+            module.items.push(Item::Node(Node {
+                name: PRODUCERS_NAME.to_string(),
+                depth,
+                items: vec![Item::Node(processed_by_node(depth + 1)?)],
+                span: None,
+                leading_comments: vec![],
+                trailing_comments: vec![],
+            }));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_test(input: &str) -> Node {
+        let mut linker = Linker::default();
+        linker.features.push(("producers", producers));
+        linker.link_raw(input).unwrap()
+    }
+
+    fn expected_entry() -> String {
+        format!(
+            r#"(processed-by "silly-wat-linker" "{}")"#,
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+
+    #[test]
+    fn adds_producers() {
+        let module = run_test("(module (func $a))");
+        assert_eq!(
+            format!("{module}"),
+            format!("(module (func $a) (@producers {}))", expected_entry())
+        );
+    }
+
+    #[test]
+    fn replaces_existing_entry() {
+        let mut linker = Linker::default();
+        // Running twice must not duplicate the entry either.
+        linker.features.push(("producers", producers));
+        linker.features.push(("producers", producers));
+        let module = linker
+            .link_raw(
+                r#"
+                    (module
+                        (@producers
+                            (language "wat" "1.0")
+                            (processed-by "silly-wat-linker" "0.0.1"))
+                        (func $a))
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            format!("{module}"),
+            format!(
+                r#"(module (@producers (language "wat" "1.0") {}) (func $a))"#,
+                expected_entry()
+            )
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use thiserror::Error;
+
+use crate::ast::{Item, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum RepeatError {
+    #[error("Repeater can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Repeat directive expected a literal integer count, got {0}")]
+    InvalidCount(String),
+    #[error("Repeat directive is missing a count")]
+    MissingCount,
+}
+
+impl From<RepeatError> for SWLError {
+    fn from(val: RepeatError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+static SWL_REPEAT_NAME: &str = "swl.repeat";
+static SWL_INDEX_ID: &str = "$swl.index";
+
+fn is_repeat_item(item: &Item) -> bool {
+    item.as_node()
+        .map(|node| node.name == SWL_REPEAT_NAME)
+        .unwrap_or(false)
+}
+
+fn substitute_index(item: &mut Item, index: usize) {
+    match item {
+        Item::Attribute(attr) if attr == SWL_INDEX_ID => *attr = format!("{index}"),
+        Item::Node(node) => {
+            for attr in node
+                .node_iter_mut()
+                .flat_map(|node| node.immediate_attribute_iter_mut())
+                .filter(|attr| *attr == SWL_INDEX_ID)
+            {
+                *attr = format!("{index}");
+            }
+        }
+        _ => {}
+    }
+}
+
+fn expand_repeat(repeat_node: Node) -> Result<Vec<Item>> {
+    let mut items = repeat_node.items.into_iter();
+    let count_attr = items
+        .next()
+        .and_then(|item| item.as_attribute().map(|attr| attr.to_string()))
+        .ok_or::<SWLError>(RepeatError::MissingCount.into())?;
+    let count = count_attr
+        .parse::<usize>()
+        .map_err(|_| RepeatError::InvalidCount(count_attr.clone()))?;
+
+    // The body gets spliced into the parent of the repeat directive, so it moves up one level.
+    let body: Vec<Item> = items
+        .map(|mut item| {
+            if let Item::Node(node) = &mut item {
+                node.node_iter_mut().for_each(|node| node.depth -= 1);
+            }
+            item
+        })
+        .collect();
+
+    let mut result = vec![];
+    for index in 0..count {
+        for item in &body {
+            let mut item = item.clone();
+            substitute_index(&mut item, index);
+            result.push(item);
+        }
+    }
+    Ok(result)
+}
+
+fn expand_repeats(node: &mut Node) -> Result<()> {
+    // Innermost repeats are expanded first so that `$swl.index` binds to the closest directive.
+    for child in node.immediate_node_iter_mut() {
+        expand_repeats(child)?;
+    }
+    if !node.items.iter().any(is_repeat_item) {
+        return Ok(());
+    }
+    let items = std::mem::take(&mut node.items);
+    for item in items {
+        if is_repeat_item(&item) {
+            node.items.extend(expand_repeat(item.into_node())?);
+        } else {
+            node.items.push(item);
+        }
+    }
+    Ok(())
+}
+
+pub fn repeat(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(RepeatError::NotAModule.into());
+    }
+    expand_repeats(module)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::linker;
+    use crate::loader;
+    use crate::test_support::assert_module_eq;
+
+    fn run_test<T: AsRef<str>>(inputs: &[T], expected: T) -> Node {
+        let map: HashMap<String, Vec<u8>> = HashMap::from_iter(
+            inputs
+                .iter()
+                .enumerate()
+                .map(|(idx, str)| (format!("{idx}"), str.as_ref().to_string().into_bytes())),
+        );
+        let mut linker = linker::Linker::new(Box::new(loader::MockLoader { map }));
+        linker.features.push(("repeat", repeat));
+
+        let module = linker.link_file("0").unwrap();
+        assert_module_eq(&module, expected.as_ref());
+        module
+    }
+
+    #[test]
+    fn simple_repeat() {
+        let module = run_test(
+            &[r#"
+                (module
+                    (func $main
+                        (swl.repeat 3
+                            (call $f (i32.const $swl.index))))
+                )
+            "#],
+            r#"
+                (module (func $main (call $f (i32.const 0)) (call $f (i32.const 1)) (call $f (i32.const 2))))
+            "#,
+        );
+        let depths: Vec<usize> = module.node_iter().map(|node| node.depth).collect();
+        assert_eq!(depths, [0, 1, 2, 3, 2, 3, 2, 3]);
+    }
+
+    #[test]
+    fn nested_repeat() {
+        run_test(
+            &[r#"
+                (module
+                    (swl.repeat 2
+                        (swl.repeat 2 (elem $swl.index)))
+                )
+            "#],
+            r#"
+                (module (elem 0) (elem 1) (elem 0) (elem 1))
+            "#,
+        );
+    }
+}
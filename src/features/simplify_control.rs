@@ -0,0 +1,152 @@
+use thiserror::Error;
+
+use crate::ast::{Item, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum SimplifyControlError {
+    #[error("Control flow simplifier can only be applied to top-level modules")]
+    NotAModule,
+}
+
+impl From<SimplifyControlError> for SWLError {
+    fn from(val: SimplifyControlError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+fn is_branch(word: &str) -> bool {
+    matches!(word, "br" | "br_if" | "br_table")
+}
+
+fn is_label(attr: &str) -> bool {
+    attr.starts_with('$') || attr.parse::<u32>().is_ok()
+}
+
+/// Returns all labels targeted by branches inside a node, covering both folded (`(br $l)`) and flat (`br $l`) branches.
+fn branch_targets(node: &Node) -> Vec<&str> {
+    let mut targets = vec![];
+    for node in node.node_iter() {
+        let mut in_branch = is_branch(&node.name);
+        for attr in node.immediate_attribute_iter() {
+            if is_branch(attr) {
+                in_branch = true;
+            } else if in_branch && is_label(attr) {
+                targets.push(attr);
+            } else {
+                in_branch = false;
+            }
+        }
+    }
+    targets
+}
+
+/// Returns true if `block` only wraps a single node and no branch can target it.
+fn is_redundant_block(block: &Node) -> bool {
+    if block.name != "block" {
+        return false;
+    }
+    let items: Vec<&Item> = block.significant_items().collect();
+    let (label, inner) = match items.as_slice() {
+        [Item::Node(inner)] => (None, inner),
+        [Item::Attribute(label), Item::Node(inner)] if label.starts_with('$') => {
+            (Some(label.as_str()), inner)
+        }
+        _ => return false,
+    };
+    if matches!(inner.name.as_str(), "result" | "param" | "type") {
+        return false;
+    }
+    if inner
+        .node_iter()
+        .flat_map(|node| std::iter::once(node.name.as_str()).chain(node.immediate_attribute_iter()))
+        .any(|word| word == "delegate" || word == "rethrow")
+    {
+        return false;
+    }
+    // Numeric branches that cross the block are adjusted, but those targeting it can't be.
+    let mut targets_block = false;
+    inner.clone().adjust_relative_branches(|target| {
+        targets_block |= target == 0;
+        target
+    });
+    !targets_block
+        && branch_targets(inner)
+            .into_iter()
+            .all(|target| Some(target) != label)
+}
+
+fn simplify_control_node(node: &mut Node) {
+    for item in node.items.iter_mut() {
+        let child = match item {
+            Item::Node(child) => child,
+            _ => continue,
+        };
+        simplify_control_node(child);
+        while is_redundant_block(child) {
+            let mut inner = std::mem::take(&mut child.items)
+                .into_iter()
+                .find_map(|item| match item {
+                    Item::Node(inner) => Some(inner),
+                    _ => None,
+                })
+                .unwrap();
+            inner.node_iter_mut().for_each(|node| node.depth -= 1);
+            inner.adjust_relative_branches(|target| target - 1);
+            *child = inner;
+        }
+    }
+}
+
+pub fn simplify_control(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(SimplifyControlError::NotAModule.into());
+    }
+    simplify_control_node(module);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::assert_module_eq;
+
+    fn run_test(input: &str, expected: &str) {
+        let mut linker = Linker::default();
+        linker.features.push(("simplify_control", simplify_control));
+        let got = linker.link_raw(input).unwrap();
+        assert_module_eq(&got, expected);
+    }
+
+    #[test]
+    fn collapse_redundant_blocks() {
+        run_test(
+            r#"
+                (module
+                    (func $f
+                        (block
+                            (block $outer
+                                (block $inner
+                                    (br_if $inner (i32.const 1))
+                                    (nop))))))
+            "#,
+            "(module (func $f (block $inner (br_if $inner (i32.const 1)) (nop))))",
+        );
+    }
+
+    #[test]
+    fn adjust_crossing_branches() {
+        run_test(
+            "(module (func $f (block (block (loop (br_if 2 (i32.const 1)) (br 0))))))",
+            "(module (func $f (block (loop (br_if 1 (i32.const 1)) (br 0)))))",
+        );
+    }
+
+    #[test]
+    fn keep_branch_targets() {
+        let input = "(module (func $f (block $outer (loop (br $outer))) (block (loop (br 1))) (block (result i32) (i32.const 1))))";
+        run_test(input, input);
+    }
+}
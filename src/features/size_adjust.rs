@@ -2,7 +2,9 @@ use thiserror::Error;
 
 use crate::ast::{Item, Node};
 use crate::error::{Result, SWLError};
+use crate::features::align_data;
 use crate::linker::Linker;
+use crate::parser::Parser;
 use crate::utils::{self, interpreted_string_length, is_string_literal, parse_number_literal};
 
 #[derive(Error, Debug)]
@@ -11,6 +13,10 @@ pub enum SizeAdjustError {
     NotAModule,
     #[error("Offset is missing expression argument")]
     InvalidOffset,
+    #[error("Data needs {required} pages, but the imported memory only guarantees {min}")]
+    ImportedMemoryTooSmall { required: u64, min: u64 },
+    #[error("Data needs {0} pages, but a 32-bit memory can have at most 65536. Use a 64-bit memory like (memory i64 1)")]
+    MemoryTooLarge(u64),
 }
 
 impl From<SizeAdjustError> for SWLError {
@@ -31,14 +37,73 @@ fn is_active_data_segment(data_seg: &Node) -> Result<bool> {
         .any(|node| node.name == "memory");
     let has_offset_node = data_seg
         .immediate_node_iter()
-        .any(|node| node.name == "offset" || node.name == "i32.const");
+        .any(|node| node.name == "offset" || is_offset_const(node));
     Ok(has_memory_node || has_offset_node)
 }
 
-pub fn size_adjust(module: &mut Node, _linker: &mut Linker) -> Result<()> {
-    if !utils::is_module(module) {
-        return Err(SizeAdjustError::NotAModule.into());
+/// Offsets are `i32.const` for 32-bit memories and `i64.const` for 64-bit memories.
+fn is_offset_const(node: &Node) -> bool {
+    node.name == "i32.const" || node.name == "i64.const"
+}
+
+/// Returns true if the module’s memory is a 64-bit memory, declared like `(memory i64 1)`.
+pub fn is_memory64(module: &Node) -> bool {
+    module
+        .immediate_node_iter()
+        .find_map(|node| match node.name.as_str() {
+            "memory" => Some(node),
+            "import" => node
+                .immediate_node_iter()
+                .find(|descriptor| descriptor.name == "memory"),
+            _ => None,
+        })
+        .map(|memory| memory.immediate_attribute_iter().any(|attr| attr == "i64"))
+        .unwrap_or(false)
+}
+
+fn data_size(data_seg: &Node) -> Result<usize> {
+    let data_sizes: Vec<usize> = Result::from_iter(
+        data_seg
+            .immediate_attribute_iter()
+            .filter(|&attr| is_string_literal(attr))
+            .map(|s| interpreted_string_length(&s[1..s.len() - 1])),
+    )?;
+    Ok(data_sizes.into_iter().sum())
+}
+
+/// Returns the declared minimum size of the module’s memory if it is imported, either as `(memory (import "env" "mem") 1)` or as `(import "env" "mem" (memory 1))`. Returns `None` if the memory isn’t imported.
+fn imported_memory_min(module: &Node) -> Option<Option<u64>> {
+    let (memory_node, is_imported) =
+        module
+            .immediate_node_iter()
+            .find_map(|node| match node.name.as_str() {
+                "memory" => Some((
+                    node,
+                    node.immediate_node_iter()
+                        .any(|child| child.name == "import"),
+                )),
+                "import" => node
+                    .immediate_node_iter()
+                    .find(|descriptor| descriptor.name == "memory")
+                    .map(|descriptor| (descriptor, true)),
+                _ => None,
+            })?;
+    if !is_imported {
+        return None;
     }
+    Some(
+        memory_node
+            .immediate_attribute_iter()
+            .find_map(|attr| attr.parse::<u64>().ok()),
+    )
+}
+
+fn num_pages_for(num_bytes: u64) -> u64 {
+    num_bytes.div_ceil(64 * 1024)
+}
+
+/// Returns the first address after all active data segments, i.e. the highest address any of them writes to plus one.
+pub fn data_extent(module: &Node) -> Result<u64> {
     let mut max_addr = 0;
     for node in module.immediate_node_iter() {
         if node.name != "data" {
@@ -50,16 +115,22 @@ pub fn size_adjust(module: &mut Node, _linker: &mut Linker) -> Result<()> {
 
         let offset_node = node
             .immediate_node_iter()
-            .find(|node| node.name == "offset" || node.name == "i32.const");
+            .find(|node| node.name == "offset" || is_offset_const(node));
         let offset = offset_node
             .map(|mut node| {
                 if node.name == "offset" {
-                    node = node.items[0]
-                        .as_node()
+                    node = node
+                        .significant_items()
+                        .next()
+                        .and_then(|item| item.as_node())
                         .ok_or::<SWLError>(SizeAdjustError::InvalidOffset.into())?;
                 }
-                let offset: usize = if node.name == "i32.const" {
-                    parse_number_literal(node.items[0].as_attribute().unwrap_or("0"))
+                let offset: u64 = if is_offset_const(node) {
+                    let value = node
+                        .significant_items()
+                        .next()
+                        .and_then(|item| item.as_attribute());
+                    parse_number_literal(value.unwrap_or("0"))
                         .map_err(|err| SWLError::Other(err.into()))?
                         .try_into()
                         .map_err(|err: std::num::TryFromIntError| SWLError::Other(err.into()))?
@@ -69,30 +140,92 @@ pub fn size_adjust(module: &mut Node, _linker: &mut Linker) -> Result<()> {
                 Ok(offset)
             })
             .unwrap_or(Ok(0))?;
+        // Segments that `align_data` hasn’t processed yet will be moved up.
+        let offset = match align_data::requested_alignment(node)? {
+            Some(alignment) => offset.next_multiple_of(alignment as u64),
+            None => offset,
+        };
 
-        let data_sizes: Vec<usize> = Result::from_iter(
-            node.immediate_attribute_iter()
-                .filter(|&attr| is_string_literal(attr))
-                .map(|s| interpreted_string_length(&s[1..s.len() - 1])),
-        )?;
-        let data_size = data_sizes.into_iter().reduce(|acc, i| acc + i).unwrap_or(0);
-        max_addr = max_addr.max(offset + data_size);
+        max_addr = max_addr.max(offset + data_size(node)? as u64);
     }
+    Ok(max_addr)
+}
 
-    let memory_node = module
-        .immediate_node_iter_mut()
-        .find(|node| node.name == "memory");
-    let memory_node = match memory_node {
-        Some(m) => m,
+pub fn size_adjust(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(SizeAdjustError::NotAModule.into());
+    }
+    let max_addr = data_extent(module)?;
+    let memory64 = is_memory64(module);
+    if !memory64 && num_pages_for(max_addr) > 65536 {
+        return Err(SizeAdjustError::MemoryTooLarge(num_pages_for(max_addr)).into());
+    }
+
+    // Imported memories can’t be resized, but the data has to fit their declared minimum.
+    if let Some(min) = imported_memory_min(module) {
+        let required = num_pages_for(max_addr);
+        let min = min.unwrap_or(0);
+        if required > min {
+            return Err(SizeAdjustError::ImportedMemoryTooSmall { required, min }.into());
+        }
+        return Ok(());
+    }
+
+    let memory_idx = module
+        .items
+        .iter()
+        .position(|item| item.as_node().map(|node| node.name == "memory") == Some(true));
+    let memory_idx = match memory_idx {
+        Some(idx) => idx,
         None => return Ok(()),
     };
+    let memory_node = module.items[memory_idx].as_node_mut().unwrap();
+
+    // `(memory (data ...))` implicitly sizes the memory to fit the inline data, placed at offset 0.
+    if let Some(inline_data_idx) = memory_node
+        .items
+        .iter()
+        .position(|item| item.as_node().map(|node| node.name == "data") == Some(true))
+    {
+        let inline_data_size =
+            data_size(memory_node.items[inline_data_idx].as_node().unwrap())? as u64;
+        let implicit_pages = num_pages_for(inline_data_size);
+        if num_pages_for(max_addr.max(inline_data_size)) <= implicit_pages {
+            return Ok(());
+        }
+        // The other data segments don’t fit, so the abbreviation has to be expanded into an explicit memory size and a separate data segment.
+        let num_pages = num_pages_for(max_addr);
+        let mut inline_data = std::mem::replace(
+            &mut memory_node.items[inline_data_idx],
+            Item::Attribute(format!("{num_pages}")),
+        )
+        .into_node();
+        memory_node
+            .items
+            .insert(inline_data_idx + 1, Item::Attribute(format!("{num_pages}")));
+        let memory_ref = match memory_node
+            .immediate_attribute_iter()
+            .find(|attr| attr.starts_with('$'))
+        {
+            Some(id) => format!("(memory {id}) "),
+            None => "".to_string(),
+        };
+        let offset_type = if memory64 { "i64" } else { "i32" };
+        let mut data_node =
+            Parser::new(format!("(data {memory_ref}({offset_type}.const 0))")).parse()?;
+        data_node.items.append(&mut inline_data.items);
+        let depth = module.depth + 1;
+        data_node
+            .node_iter_mut()
+            .for_each(|node| node.depth += depth);
+        module.items.insert(memory_idx + 1, Item::Node(data_node));
+        return Ok(());
+    }
+
     let memory_size_attribute = memory_node
         .immediate_attribute_iter_mut()
-        .find(|attr| attr.parse::<usize>().is_ok());
-    let mut num_pages: usize = ((max_addr as f32) / (64.0 * 1024.0)).ceil() as usize;
-    if num_pages < 1 {
-        num_pages = 1;
-    }
+        .find(|attr| attr.parse::<u64>().is_ok());
+    let num_pages = num_pages_for(max_addr).max(1);
 
     if let Some(memory_size_attribute) = memory_size_attribute {
         *memory_size_attribute = format!("{num_pages}")
@@ -116,7 +249,7 @@ mod test {
 
     fn run_test<T: AsRef<str>>(input: T, expected_memory_size: usize) {
         let mut linker = Linker::default();
-        linker.features.push(size_adjust);
+        linker.features.push(("size_adjust", size_adjust));
         let got = linker.link_raw(input).unwrap();
         let memory_node = got
             .immediate_node_iter()
@@ -205,6 +338,62 @@ mod test {
         run_test(input, 2);
     }
 
+    #[test]
+    fn memory64_test() {
+        let input = r#"
+            (module
+                (memory i64 1)
+                (data (i64.const 0x100000000) "1")
+            )
+        "#;
+        run_test(input, 65537);
+
+        let mut linker = Linker::default();
+        linker.features.push(("size_adjust", size_adjust));
+        let err = linker
+            .link_raw(r#"(module (memory 1) (data (i32.const 0xffffffff) "12"))"#)
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Data needs 65537 pages, but a 32-bit memory can have at most 65536. Use a 64-bit memory like (memory i64 1)"
+        );
+    }
+
+    #[test]
+    fn inline_data_test() {
+        let input = format!(
+            r#"
+            (module
+                (memory (data "{}"))
+            )
+        "#,
+            string_of_length(1, 1)
+        );
+        let mut linker = Linker::default();
+        linker.features.push(("size_adjust", size_adjust));
+        let got = linker.link_raw(&input).unwrap();
+        assert_eq!(got, Parser::new(input).parse().unwrap());
+    }
+
+    #[test]
+    fn inline_data_expansion_test() {
+        let input = r#"
+            (module
+                (memory $x (data "xxxx"))
+                (data (i32.const 0x10000) "y")
+            )
+        "#;
+        let mut linker = Linker::default();
+        linker.features.push(("size_adjust", size_adjust));
+        let got = linker.link_raw(input).unwrap();
+        assert_eq!(
+            format!("{got}"),
+            r#"(module (memory $x 2 2) (data (memory $x) (i32.const 0) "xxxx") (data (i32.const 0x10000) "y"))"#
+        );
+        let depths: Vec<usize> = got.node_iter().map(|node| node.depth).collect();
+        assert_eq!(depths, [0, 1, 1, 2, 2, 1, 2]);
+    }
+
     #[test]
     fn hex_data_offset() {
         let input = r#"
@@ -216,4 +405,30 @@ mod test {
         .to_string();
         run_test(input, 2);
     }
+
+    #[test]
+    fn imported_memory() {
+        let mut linker = Linker::default();
+        linker.features.push(("size_adjust", size_adjust));
+        let input = format!(
+            r#"(module (memory $m (import "env" "mem") 2) (data (i32.const 0) "{}"))"#,
+            string_of_length(1, 1)
+        );
+        let got = linker.link_raw(&input).unwrap();
+        assert_eq!(
+            format!("{}", got.items[0]),
+            r#"(memory $m (import "env" "mem") 2)"#
+        );
+
+        let err = linker
+            .link_raw(format!(
+                r#"(module (import "env" "mem" (memory 1)) (data (i32.const 0) "{}"))"#,
+                string_of_length(1, 1)
+            ))
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Data needs 2 pages, but the imported memory only guarantees 1"
+        );
+    }
 }
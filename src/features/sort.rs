@@ -1,5 +1,3 @@
-use std::cmp::Ordering;
-
 use thiserror::Error;
 
 use crate::ast::{Item, Node};
@@ -32,18 +30,24 @@ pub fn frontload_imports(module: &mut Node) -> Result<()> {
         return Err(SortError::NotAModule.into());
     }
 
-    module.items.sort_by(|a, b| match (a, b) {
-        (Item::Node(a), Item::Node(b)) => {
-            if has_import_node(a) && !has_import_node(b) {
-                Ordering::Less
-            } else if !has_import_node(a) && has_import_node(b) {
-                Ordering::Greater
-            } else {
-                Ordering::Equal
-            }
+    // Comments move together with the node that follows them.
+    let mut groups: Vec<Vec<Item>> = vec![];
+    let mut group = vec![];
+    for item in std::mem::take(&mut module.items) {
+        let ends_group = !item.is_comment();
+        group.push(item);
+        if ends_group {
+            groups.push(std::mem::take(&mut group));
         }
-        _ => Ordering::Equal,
+    }
+    groups.push(group);
+    // The module id has to stay in front of the imports.
+    groups.sort_by_key(|group| match group.last() {
+        Some(Item::Attribute(_)) => 0,
+        Some(Item::Node(node)) if has_import_node(node) => 1,
+        _ => 2,
     });
+    module.items = groups.into_iter().flatten().collect();
 
     Ok(())
 }
@@ -61,6 +65,25 @@ mod test {
         assert_eq!(&got, expected)
     }
 
+    #[test]
+    fn comments_move_with_nodes() {
+        let mut parser = Parser::new(
+            r#"
+                (module $m
+                    ;; Main function
+                    (func $main)
+                    (; Logging ;) (import "env" "log" (func $log)))
+            "#,
+        );
+        parser.keep_comments = true;
+        let mut ast = parser.parse().unwrap();
+        frontload_imports(&mut ast).unwrap();
+        assert_eq!(
+            format!("{ast}"),
+            "(module $m (; Logging ;) (import \"env\" \"log\" (func $log)) ;; Main function\n (func $main))"
+        );
+    }
+
     #[test]
     fn simple() {
         run_test(
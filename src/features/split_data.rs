@@ -0,0 +1,238 @@
+use thiserror::Error;
+
+use crate::ast::{Item, Node};
+use crate::error::{Result, SWLError};
+use crate::features::data_dedupe::for_each_data_ref;
+use crate::linker::Linker;
+use crate::utils::{self, is_string_literal, parse_number_literal, unescape_wat_string};
+
+#[derive(Error, Debug)]
+pub enum SplitDataError {
+    #[error("Data splitter can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Invalid value for option {0}: {1}, expected a positive integer")]
+    InvalidOption(String, String),
+    #[error("Data segment of {0} bytes needs to be split, but has no constant i32 offset")]
+    NonConstantOffset(usize),
+}
+
+impl From<SplitDataError> for SWLError {
+    fn from(val: SplitDataError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+static MAX_SIZE_OPTION: &str = "split_data.max_size";
+const DEFAULT_MAX_SIZE: usize = 65536;
+
+/// Splits the contents of a WAT string literal (without quotes) into its characters and escape sequences, each paired with the bytes it represents.
+fn tokenize(s: &str) -> Result<Vec<(&str, Vec<u8>)>> {
+    let mut tokens = vec![];
+    let mut it = s.char_indices().peekable();
+    while let Some((start, char)) = it.next() {
+        if char == '\\' {
+            match it.next() {
+                Some((_, 'u')) => {
+                    for (_, c) in it.by_ref() {
+                        if c == '}' {
+                            break;
+                        }
+                    }
+                }
+                Some((_, c)) if c.is_ascii_hexdigit() => {
+                    it.next();
+                }
+                _ => {}
+            }
+        }
+        let end = it.peek().map(|&(idx, _)| idx).unwrap_or(s.len());
+        let token = &s[start..end];
+        tokens.push((token, unescape_wat_string(token)?));
+    }
+    Ok(tokens)
+}
+
+/// Splits the string literals of a data segment into chunks of at most `max_size` bytes. Escape sequences are kept intact, unless a chunk boundary falls inside the bytes they represent, in which case those bytes are re-escaped individually.
+fn split_strings<'a>(
+    strings: impl Iterator<Item = &'a str>,
+    max_size: usize,
+) -> Result<Vec<String>> {
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+    let mut chunk_size = 0;
+    for string in strings {
+        for (token, bytes) in tokenize(string)? {
+            if chunk_size + bytes.len() <= max_size {
+                chunk.push_str(token);
+                chunk_size += bytes.len();
+            } else {
+                for byte in bytes {
+                    if chunk_size == max_size {
+                        chunks.push(std::mem::take(&mut chunk));
+                        chunk_size = 0;
+                    }
+                    chunk.push_str(&utils::escape_wat_string(&[byte]));
+                    chunk_size += 1;
+                }
+            }
+            if chunk_size == max_size {
+                chunks.push(std::mem::take(&mut chunk));
+                chunk_size = 0;
+            }
+        }
+    }
+    if chunk_size > 0 {
+        chunks.push(chunk);
+    }
+    Ok(chunks)
+}
+
+/// Returns the `i32.const` node holding the offset of an active data segment. Handles both `(i32.const N)` and `(offset (i32.const N))`.
+fn offset_node(data_seg: &mut Node) -> Option<&mut Node> {
+    let offset_node = data_seg
+        .immediate_node_iter_mut()
+        .find(|node| node.name == "offset" || node.name == "i32.const")?;
+    if offset_node.name == "offset" {
+        offset_node
+            .immediate_node_iter_mut()
+            .find(|node| node.name == "i32.const")
+    } else {
+        Some(offset_node)
+    }
+}
+
+fn is_active(data_seg: &Node) -> bool {
+    data_seg
+        .immediate_node_iter()
+        .any(|node| node.name != "memory")
+}
+
+/// Returns the segments replacing `data_seg`, or `None` if it doesn’t need to be split.
+fn split_segment(data_seg: &Node, max_size: usize) -> Result<Option<Vec<Node>>> {
+    let strings: Vec<&str> = data_seg
+        .immediate_attribute_iter()
+        .filter(|attr| is_string_literal(attr))
+        .map(|attr| &attr[1..attr.len() - 1])
+        .collect();
+    let size = strings
+        .iter()
+        .map(|s| utils::interpreted_string_length(s))
+        .sum::<Result<usize>>()?;
+    if size <= max_size || !is_active(data_seg) {
+        return Ok(None);
+    }
+
+    let mut template = data_seg.clone();
+    let offset = offset_node(&mut template)
+        .and_then(|node| node.immediate_attribute_iter().next())
+        .and_then(|attr| parse_number_literal(attr).ok())
+        .and_then(|v| usize::try_from(v).ok())
+        .ok_or(SplitDataError::NonConstantOffset(size))?;
+    template
+        .items
+        .retain(|item| !matches!(item, Item::Attribute(attr) if is_string_literal(attr)));
+
+    let chunks = split_strings(strings.into_iter(), max_size)?;
+    let mut segments = vec![];
+    for (idx, chunk) in chunks.into_iter().enumerate() {
+        let mut segment = template.clone();
+        if idx > 0 {
+            // Only the first segment keeps the id.
+            segment
+                .items
+                .retain(|item| !matches!(item, Item::Attribute(attr) if attr.starts_with('$')));
+        }
+        let offset_attr = offset_node(&mut segment)
+            .and_then(|node| node.immediate_attribute_iter_mut().next())
+            .unwrap();
+        *offset_attr = format!("{}", offset + idx * max_size);
+        segment.items.push(Item::Attribute(format!(r#""{chunk}""#)));
+        segments.push(segment);
+    }
+    Ok(Some(segments))
+}
+
+/// Splits active data segments that are bigger than `split_data.max_size` bytes into multiple contiguous segments.
+pub fn split_data(module: &mut Node, linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(SplitDataError::NotAModule.into());
+    }
+    let max_size = match linker.option(MAX_SIZE_OPTION) {
+        None => DEFAULT_MAX_SIZE,
+        Some(value) => value
+            .parse::<usize>()
+            .ok()
+            .filter(|&v| v > 0)
+            .ok_or_else(|| {
+                SplitDataError::InvalidOption(MAX_SIZE_OPTION.to_string(), value.to_string())
+            })?,
+    };
+
+    let mut items = vec![];
+    // For each original data segment, the number of segments added before it.
+    let mut shifts = vec![];
+    let mut added = 0;
+    for item in std::mem::take(&mut module.items) {
+        let segments = match item.as_node() {
+            Some(node) if node.name == "data" => {
+                shifts.push(added);
+                split_segment(node, max_size)?
+            }
+            _ => None,
+        };
+        match segments {
+            Some(segments) => {
+                added += segments.len() - 1;
+                items.extend(segments.into_iter().map(Item::Node));
+            }
+            None => items.push(item),
+        }
+    }
+    module.items = items;
+
+    if added > 0 {
+        for_each_data_ref(module, |_, label| {
+            if let Some(&shift) = label.parse::<usize>().ok().and_then(|idx| shifts.get(idx)) {
+                *label = format!("{}", label.parse::<usize>().unwrap() + shift);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::assert_module_eq;
+
+    fn run_test(input: &str, max_size: &str, expected: &str) {
+        let mut linker = Linker::default();
+        linker.features.push(("split_data", split_data));
+        linker
+            .options
+            .insert(MAX_SIZE_OPTION.to_string(), max_size.to_string());
+        let got = linker.link_raw(input).unwrap();
+        assert_module_eq(&got, expected);
+    }
+
+    #[test]
+    fn split_mid_escape() {
+        run_test(
+            r#"(module (data $d (i32.const 0x10) "ab\u{ef}\0a"))"#,
+            "3",
+            r#"(module (data $d (i32.const 16) "ab\c3") (data (i32.const 19) "\af\0a"))"#,
+        );
+    }
+
+    #[test]
+    fn keeps_small_and_passive_segments() {
+        let input =
+            r#"(module (data (i32.const 0) "abc") (data $p "abcdef") (func (data.drop 1)))"#;
+        run_test(input, "3", input);
+        run_test(
+            r#"(module (data (memory 0) (offset (i32.const 0)) "abcd") (data $p "x") (func (data.drop 1)))"#,
+            "3",
+            r#"(module (data (memory 0) (offset (i32.const 0)) "abc") (data (memory 0) (offset (i32.const 3)) "d") (data $p "x") (func (data.drop 2)))"#,
+        );
+    }
+}
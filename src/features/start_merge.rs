@@ -11,6 +11,8 @@ pub enum StartMergeError {
     NotAModule,
     #[error("Start directive is invalid")]
     InvalidStartDirective,
+    #[error("Start function {0} must not have params or results, but has {1}")]
+    InvalidStartSignature(String, String),
 }
 
 impl From<StartMergeError> for SWLError {
@@ -25,6 +27,26 @@ pub fn start_merge(module: &mut Node, _linker: &mut Linker) -> Result<()> {
     if !utils::is_module(module) {
         return Err(StartMergeError::NotAModule.into());
     }
+    for start in module
+        .immediate_node_iter()
+        .filter(|node| node.name == "start")
+    {
+        let id = find_id_attribute(start)
+            .ok_or::<SWLError>(StartMergeError::InvalidStartDirective.into())?;
+        let signature = find_func(module, id).map(|func| signature(module, func));
+        if let Some(signature) = signature.filter(|signature| !signature.is_empty()) {
+            return Err(StartMergeError::InvalidStartSignature(id.to_string(), signature).into());
+        }
+    }
+    // A single start directive is left where the author put it.
+    if module
+        .immediate_node_iter()
+        .filter(|node| node.name == "start")
+        .count()
+        <= 1
+    {
+        return Ok(());
+    }
     let start_directives: Vec<Node> = module
         .items
         .iter_mut()
@@ -37,13 +59,7 @@ pub fn start_merge(module: &mut Node, _linker: &mut Linker) -> Result<()> {
             Some(std::mem::replace(item, Item::Nothing).into_node())
         })
         .collect();
-
-    if start_directives.len() <= 1 {
-        start_directives
-            .into_iter()
-            .for_each(|node| module.append_node(node));
-        return Ok(());
-    }
+    module.items.retain(|item| !item.is_nothing());
 
     let start_function_ids: Vec<String> = Result::from_iter(
         start_directives
@@ -66,19 +82,117 @@ pub fn start_merge(module: &mut Node, _linker: &mut Linker) -> Result<()> {
                     name: "call".to_string(),
                     depth: module.depth + 2,
                     items: vec![Item::Attribute(id)],
+                    span: None,
+                    leading_comments: vec![],
+                    trailing_comments: vec![],
                 })
             })
             .collect::<Vec<Item>>(),
     );
-    module.append_node(new_start_function);
-    module.append_node(Node {
-        name: "start".to_string(),
-        depth: 0,
-        items: vec![Item::Attribute(SWL_START_FUNC_ID.to_string())],
-    });
+    let func_idx = module
+        .items
+        .iter()
+        .rposition(|item| item.as_node().map(|node| node.name == "func") == Some(true))
+        .map(|idx| idx + 1)
+        .unwrap_or(module.items.len());
+    module.insert_node(func_idx, new_start_function);
+    let start_idx = start_directive_position(module, func_idx + 1);
+    module.insert_node(
+        start_idx,
+        Node {
+            name: "start".to_string(),
+            depth: 0,
+            items: vec![Item::Attribute(SWL_START_FUNC_ID.to_string())],
+            span: None,
+            leading_comments: vec![],
+            trailing_comments: vec![],
+        },
+    );
     Ok(())
 }
 
+/// Finds a function by id or index. Imported functions come first in the index space.
+pub fn find_func<'a>(module: &'a Node, id: &str) -> Option<&'a Node> {
+    let is_imported = |node: &Node| {
+        node.immediate_node_iter()
+            .any(|child| child.name == "import")
+    };
+    let imported = module
+        .immediate_node_iter()
+        .filter_map(|node| match node.name.as_str() {
+            "import" => node
+                .immediate_node_iter()
+                .find(|child| child.name == "func"),
+            "func" if is_imported(node) => Some(node),
+            _ => None,
+        });
+    let defined = module
+        .immediate_node_iter()
+        .filter(|node| node.name == "func" && !is_imported(node));
+    let mut funcs = imported.chain(defined);
+    match id.parse::<usize>() {
+        Ok(idx) => funcs.nth(idx),
+        Err(_) => funcs.find(|func| find_id_attribute(func) == Some(id)),
+    }
+}
+
+/// Finds a type definition by id or index.
+pub fn find_type<'a>(module: &'a Node, id: &str) -> Option<&'a Node> {
+    let mut types = module
+        .immediate_node_iter()
+        .filter(|node| node.name == "type");
+    match id.parse::<usize>() {
+        Ok(idx) => types.nth(idx),
+        Err(_) => types.find(|typ| find_id_attribute(typ) == Some(id)),
+    }
+}
+
+/// Returns the params and results of a function, like `(param i32) (result i32)`, resolving type uses. Empty for functions of type `[] -> []`.
+/// Returns the `param` and `result` nodes of a function or any other node with a type use, like `call_indirect`. A `(type ...)` reference is resolved if there are no inline `param`s or `result`s.
+pub fn signature_nodes<'a>(module: &'a Node, func: &'a Node) -> Vec<&'a Node> {
+    let is_signature = |node: &&Node| node.name == "param" || node.name == "result";
+    let parts: Vec<&Node> = func.immediate_node_iter().filter(is_signature).collect();
+    let type_id = func
+        .immediate_node_iter()
+        .find(|node| node.name == "type")
+        .and_then(|node| node.immediate_attribute_iter().next());
+    if let (true, Some(type_id)) = (parts.is_empty(), type_id) {
+        if let Some(func_type) =
+            find_type(module, type_id).and_then(|typ| typ.immediate_node_iter().next())
+        {
+            return func_type
+                .immediate_node_iter()
+                .filter(is_signature)
+                .collect();
+        }
+    }
+    parts
+}
+
+fn signature(module: &Node, func: &Node) -> String {
+    signature_nodes(module, func)
+        .into_iter()
+        .map(|node| format!("{node}"))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Returns where the start directive conventionally goes: before the first `elem` or `data` segment after `min_idx`, or at the end.
+fn start_directive_position(module: &Node, min_idx: usize) -> usize {
+    module
+        .items
+        .iter()
+        .enumerate()
+        .skip(min_idx)
+        .find(|(_, item)| {
+            item.as_node()
+                .map(|node| node.name == "elem" || node.name == "data")
+                .unwrap_or(false)
+        })
+        .map(|(idx, _)| idx)
+        .unwrap_or(module.items.len())
+}
+
 fn create_start_func(id: &str, body: Vec<Item>) -> Node {
     Node {
         name: "func".to_string(),
@@ -87,6 +201,9 @@ fn create_start_func(id: &str, body: Vec<Item>) -> Node {
             .into_iter()
             .chain(body.into_iter())
             .collect(),
+        span: None,
+        leading_comments: vec![],
+        trailing_comments: vec![],
     }
 }
 
@@ -134,9 +251,74 @@ mod test {
             ),
         };
         let mut linker = Linker::new(Box::new(loader));
-        linker.features.push(start_merge);
+        linker.features.push(("start_merge", start_merge));
         let got = linker.link_file("0").unwrap();
         let expected = linker.link_file("1").unwrap();
         assert_eq!(format!("{got}"), format!("{expected}"),)
     }
+
+    #[test]
+    fn single_start_is_stable() {
+        let mut linker = Linker::default();
+        linker.features.push(("start_merge", start_merge));
+        let once = linker
+            .link_raw("(module (start $a) (start $b) (func $a) (func $b))")
+            .unwrap();
+        assert!(once.items.iter().all(|item| !item.is_nothing()));
+        let mut twice = once.clone();
+        start_merge(&mut twice, &mut linker).unwrap();
+        assert_eq!(once, twice);
+    }
+
+    #[test]
+    fn start_precedes_segments() {
+        let mut linker = Linker::default();
+        linker.features.push(("start_merge", start_merge));
+        let got = linker
+            .link_raw(
+                r#"
+                    (module
+                        (func $t1)
+                        (start $t1)
+                        (func $t2)
+                        (start $t2)
+                        (data (i32.const 0) "x")
+                        (global $g i32 (i32.const 0)))
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            format!("{got}"),
+            format!(
+                r#"(module (func $t1) (func $t2) (func {SWL_START_FUNC_ID} (call $t1) (call $t2)) (start {SWL_START_FUNC_ID}) (data (i32.const 0) "x") (global $g i32 (i32.const 0)))"#
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_start_signature() {
+        for (funcs, start, signature) in [
+            ("(func $f (param i32))", "$f", "(param i32)"),
+            (
+                "(type $t (func (result i32))) (func $f (type $t) (i32.const 0))",
+                "$f",
+                "(result i32)",
+            ),
+            (
+                r#"(import "env" "f" (func (param f32))) (func $g)"#,
+                "0",
+                "(param f32)",
+            ),
+        ] {
+            let mut linker = Linker::default();
+            linker.features.push(("start_merge", start_merge));
+            let err = linker
+                .link_raw(format!("(module {funcs} (start {start}))"))
+                .unwrap_err();
+            assert_eq!(
+                format!("{err}"),
+                format!("Something else went wrong: Start function {start} must not have params or results, but has {signature}")
+            );
+        }
+    }
 }
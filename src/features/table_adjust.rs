@@ -0,0 +1,326 @@
+use thiserror::Error;
+
+use crate::ast::{Item, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils::{self, parse_number_literal};
+
+#[derive(Error, Debug)]
+pub enum TableAdjustError {
+    #[error("Table Adjuster can only be applied to top-level modules")]
+    NotAModule,
+    #[error("Offset is missing expression argument")]
+    InvalidOffset,
+    #[error("Elem segments need a table size of {needed}, but the table’s maximum is {max}")]
+    ExceedsMaximum { needed: usize, max: usize },
+}
+
+impl From<TableAdjustError> for SWLError {
+    fn from(val: TableAdjustError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+static ELEM_KEYWORDS: &[&str] = &["func", "funcref", "externref", "declare"];
+
+fn is_offset_node(node: &Node) -> bool {
+    node.name == "offset" || node.name == "i32.const"
+}
+
+fn is_active_elem_segment(elem_seg: &Node) -> bool {
+    elem_seg
+        .immediate_node_iter()
+        .any(|node| node.name == "table" || is_offset_node(node))
+}
+
+fn elem_offset(elem_seg: &Node) -> Result<usize> {
+    let mut node = match elem_seg
+        .immediate_node_iter()
+        .find(|node| is_offset_node(node))
+    {
+        Some(node) => node,
+        None => return Ok(0),
+    };
+    if node.name == "offset" {
+        node = node
+            .significant_items()
+            .next()
+            .and_then(|item| item.as_node())
+            .ok_or::<SWLError>(TableAdjustError::InvalidOffset.into())?;
+    }
+    if node.name != "i32.const" {
+        return Err(TableAdjustError::InvalidOffset.into());
+    }
+    let value = node
+        .significant_items()
+        .next()
+        .and_then(|item| item.as_attribute());
+    parse_number_literal(value.unwrap_or("0"))
+        .map_err(|err| SWLError::Other(err.into()))?
+        .try_into()
+        .map_err(|err: std::num::TryFromIntError| SWLError::Other(err.into()))
+}
+
+/// Counts the entries of an element segment. Entries are either function references (`$f` or an index) or element expressions, which all come after the offset.
+fn elem_count(elem_seg: &Node) -> usize {
+    elem_seg
+        .items
+        .iter()
+        .skip_while(|item| !item.as_node().map(is_offset_node).unwrap_or(false))
+        .skip(1)
+        .filter(|item| match item.as_attribute() {
+            Some(attr) => !ELEM_KEYWORDS.contains(&attr),
+            None => item
+                .as_node()
+                .map(|node| node.name == "item" || node.name.starts_with("ref."))
+                .unwrap_or(false),
+        })
+        .count()
+}
+
+fn is_funcref_table(node: &Node) -> bool {
+    node.name == "table"
+        && node
+            .immediate_attribute_iter()
+            .any(|attr| attr == "funcref")
+}
+
+/// Tables with inline `(elem ...)` get their size from the element count and can’t have limits.
+fn has_inline_elem(table: &Node) -> bool {
+    table.immediate_node_iter().any(|node| node.name == "elem")
+}
+
+fn id_of(node: &Node) -> Option<&str> {
+    node.significant_items()
+        .next()
+        .and_then(|item| item.as_attribute())
+        .filter(|attr| attr.starts_with('$'))
+}
+
+/// Returns the position among the module’s child nodes and the id of every table, in index order. Imported tables come first.
+fn table_index_space(module: &Node) -> Vec<(usize, Option<&str>)> {
+    let mut imports = vec![];
+    let mut definitions = vec![];
+    for (pos, node) in module.immediate_node_iter().enumerate() {
+        if node.name == "import" {
+            if let Some(table) = node.immediate_node_iter().find(|node| node.name == "table") {
+                imports.push((pos, id_of(table)));
+            }
+        } else if node.name == "table" {
+            if node.immediate_node_iter().any(|node| node.name == "import") {
+                imports.push((pos, id_of(node)));
+            } else {
+                definitions.push((pos, id_of(node)));
+            }
+        }
+    }
+    imports.extend(definitions);
+    imports
+}
+
+/// Returns true if an elem segment writes to the table with the given index and id. Segments without a `(table ...)` write to table 0.
+fn targets_table(elem_seg: &Node, index: usize, id: Option<&str>) -> bool {
+    let target = elem_seg
+        .immediate_node_iter()
+        .find(|node| node.name == "table")
+        .and_then(|node| node.immediate_attribute_iter().next());
+    match target {
+        None => index == 0,
+        Some(target) if target.starts_with('$') => Some(target) == id,
+        Some(target) => target.parse::<usize>() == Ok(index),
+    }
+}
+
+pub fn table_adjust(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(TableAdjustError::NotAModule.into());
+    }
+    let table_pos = match module.immediate_node_iter().position(is_funcref_table) {
+        Some(pos) => pos,
+        None => return Ok(()),
+    };
+    let (table_index, table_id) = match table_index_space(module)
+        .into_iter()
+        .enumerate()
+        .find(|(_, (pos, _))| *pos == table_pos)
+    {
+        Some((index, (_, id))) => (index, id.map(|id| id.to_string())),
+        None => return Ok(()),
+    };
+
+    let mut max_index = 0;
+    for node in module.immediate_node_iter() {
+        if node.name != "elem" || !is_active_elem_segment(node) {
+            continue;
+        }
+        if !targets_table(node, table_index, table_id.as_deref()) {
+            continue;
+        }
+        max_index = max_index.max(elem_offset(node)? + elem_count(node));
+    }
+
+    let table_node = match module.immediate_node_iter_mut().nth(table_pos) {
+        Some(t) if !has_inline_elem(t) => t,
+        _ => return Ok(()),
+    };
+    let max = table_node
+        .immediate_attribute_iter()
+        .filter_map(|attr| attr.parse::<usize>().ok())
+        .nth(1);
+    if let Some(max) = max.filter(|&max| max < max_index) {
+        return Err(TableAdjustError::ExceedsMaximum {
+            needed: max_index,
+            max,
+        }
+        .into());
+    }
+    let table_size_attribute = table_node
+        .immediate_attribute_iter_mut()
+        .find(|attr| attr.parse::<usize>().is_ok());
+
+    if let Some(table_size_attribute) = table_size_attribute {
+        let current_size: usize = table_size_attribute.parse().unwrap();
+        *table_size_attribute = format!("{}", current_size.max(max_index));
+    } else {
+        let reftype_idx = table_node
+            .items
+            .iter()
+            .position(|item| item.as_attribute() == Some("funcref"))
+            .unwrap();
+        table_node
+            .items
+            .insert(reftype_idx, Item::Attribute(format!("{max_index}")));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::linker::Linker;
+
+    fn run_test<T: AsRef<str>>(input: T, expected_table_size: usize) {
+        let mut linker = Linker::default();
+        linker.features.push(("table_adjust", table_adjust));
+        let got = linker.link_raw(input).unwrap();
+        let table_node = got
+            .immediate_node_iter()
+            .find(|node| node.name == "table")
+            .unwrap();
+        let table_size = table_node
+            .immediate_attribute_iter()
+            .find(|attr| attr.parse::<usize>().is_ok())
+            .unwrap()
+            .parse::<usize>()
+            .unwrap();
+        assert_eq!(table_size, expected_table_size);
+    }
+
+    #[test]
+    fn offset_elem_test() {
+        let input = r#"
+            (module
+                (table $t 1 funcref)
+                (elem (i32.const 3) $f $g)
+            )
+        "#;
+        run_test(input, 5);
+    }
+
+    #[test]
+    fn missing_size_test() {
+        let input = r#"
+            (module
+                (table $t funcref)
+                (elem (table $t) (offset (i32.const 1)) func $f $g $h)
+            )
+        "#;
+        run_test(input, 4);
+    }
+
+    #[test]
+    fn comments_in_offset() {
+        let mut linker = Linker::default();
+        linker.keep_comments = true;
+        linker.features.push(("table_adjust", table_adjust));
+        let got = linker
+            .link_raw(
+                "(module (table 1 funcref) (elem (offset (; start ;) (i32.const (; at ;) 3)) $f $g))",
+            )
+            .unwrap();
+        assert_eq!(
+            format!("{got}"),
+            "(module (table 5 funcref) (elem (offset (; start ;) (i32.const (; at ;) 3)) $f $g))"
+        );
+    }
+
+    #[test]
+    fn multiple_tables() {
+        let mut linker = Linker::default();
+        linker.features.push(("table_adjust", table_adjust));
+        let got = linker
+            .link_raw(
+                r#"
+                    (module
+                        (import "env" "t" (table $imported 1 externref))
+                        (table $funcs 1 2 funcref)
+                        (table $other 1 funcref)
+                        (elem (table $funcs) (i32.const 0) func $a $b)
+                        (elem (table $other) (i32.const 4) func $a)
+                        (elem (table 2) (i32.const 8) func $a)
+                        (elem (i32.const 16) externref (ref.null extern)))
+                "#,
+            )
+            .unwrap();
+        let sizes: Vec<String> = got
+            .immediate_node_iter()
+            .filter(|node| node.name == "table")
+            .map(|node| format!("{node}"))
+            .collect();
+        assert_eq!(
+            sizes,
+            ["(table $funcs 2 2 funcref)", "(table $other 1 funcref)"]
+        );
+    }
+
+    #[test]
+    fn inline_elem_table() {
+        let mut linker = Linker::default();
+        linker.features.push(("table_adjust", table_adjust));
+        let got = linker
+            .link_raw("(module (table funcref (elem $a $b)) (elem (i32.const 1) $a))")
+            .unwrap();
+        assert_eq!(
+            format!("{got}"),
+            "(module (table funcref (elem $a $b)) (elem (i32.const 1) $a))"
+        );
+    }
+
+    #[test]
+    fn exceeds_maximum() {
+        run_test("(module (table 1 4 funcref) (elem (i32.const 2) $f $g))", 4);
+
+        let mut linker = Linker::default();
+        linker.features.push(("table_adjust", table_adjust));
+        let err = linker
+            .link_raw("(module (table 1 4 funcref) (elem (i32.const 3) $f $g))")
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Elem segments need a table size of 5, but the table’s maximum is 4"
+        );
+    }
+
+    #[test]
+    fn passive_elem_test() {
+        let input = r#"
+            (module
+                (table $t 2 funcref)
+                (elem func $f $g $h)
+                (elem declare func $f)
+            )
+        "#;
+        run_test(input, 2);
+    }
+}
@@ -0,0 +1,68 @@
+use thiserror::Error;
+
+use crate::ast::Node;
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("Template can only be applied to top-level modules")]
+    NotAModule,
+}
+
+impl From<TemplateError> for SWLError {
+    fn from(val: TemplateError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+/// Marks `${KEY}` substitution as enabled. The substitution itself is textual, so the linker applies it to every file before parsing it, once this feature is enabled. See `Linker::preprocess`.
+pub fn template(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(TemplateError::NotAModule.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn linker_with_name() -> Linker {
+        let mut linker = Linker::default();
+        linker.defines = Some(HashMap::from([("NAME".to_string(), "main".to_string())]));
+        linker
+    }
+
+    #[test]
+    fn substitutes_defines() {
+        let mut linker = linker_with_name();
+        linker.enable_feature("template").unwrap();
+        let module = linker
+            .link_raw(r#"(module (func (export "${NAME}")))"#)
+            .unwrap();
+        assert_eq!(format!("{module}"), r#"(module (func (export "main")))"#);
+
+        let err = linker.link_raw(r#"(module (func (export "${OTHER}")))"#);
+        assert_eq!(
+            format!("{}", err.unwrap_err()),
+            "Something went wrong: Undefined template variable OTHER"
+        );
+    }
+
+    #[test]
+    fn defines_alone_leave_input_alone() {
+        let mut linker = linker_with_name();
+        linker.enable_feature("define_globals").unwrap();
+        let module = linker
+            .link_raw(r#"(module (data (i32.const 0) "${NAME}"))"#)
+            .unwrap();
+        assert_eq!(
+            format!("{module}"),
+            r#"(module (data (i32.const 0) "${NAME}"))"#
+        );
+    }
+}
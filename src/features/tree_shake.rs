@@ -0,0 +1,261 @@
+use std::collections::HashSet;
+
+use thiserror::Error;
+
+use crate::ast::{DefKind, IdRef, Item, Node};
+use crate::error::{Result, SWLError};
+use crate::linker::Linker;
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum TreeShakeError {
+    #[error("Tree shaker can only be applied to top-level modules")]
+    NotAModule,
+    #[error("There is no export named {0}")]
+    UnknownExport(String),
+    #[error("Tree shaker needs ids, but {0} refers to an index")]
+    IndexReference(String),
+}
+
+impl From<TreeShakeError> for SWLError {
+    fn from(val: TreeShakeError) -> Self {
+        SWLError::Other(val.into())
+    }
+}
+
+pub static ONLY_EXPORT_OPTION: &str = "tree_shake.only_export";
+
+/// Instructions whose immediate refers to a function, global or type.
+static REFERENCING_INSTRUCTIONS: &[&str] = &[
+    "call",
+    "return_call",
+    "ref.func",
+    "global.get",
+    "global.set",
+    "type",
+];
+
+fn is_index(attr: &str) -> bool {
+    attr.parse::<u32>().is_ok()
+}
+
+/// Returns the kind and id of a definition that can be removed if nothing refers to it: a function, global or type with an id, or an import of a function or global with an id.
+fn removable_id(node: &Node) -> Option<(DefKind, &str)> {
+    let def = if node.name == "import" {
+        node.immediate_node_iter().next()?
+    } else {
+        node
+    };
+    match def.definition_kind()? {
+        kind @ (DefKind::Func | DefKind::Global | DefKind::Type) => def
+            .immediate_attribute_iter()
+            .find(|attr| attr.starts_with('$'))
+            .map(|id| (kind, id)),
+        _ => None,
+    }
+}
+
+fn is_export_node(item: &Item) -> bool {
+    item.as_node().map(|node| node.name == "export") == Some(true)
+}
+
+/// Returns the name of an `(export "name" ...)` node, without quotes.
+fn export_name(export: &Node) -> Option<&str> {
+    export
+        .immediate_attribute_iter()
+        .next()
+        .map(|name| name.trim_matches('"'))
+}
+
+/// Finds the first reference by index to a function, global or type. Removing definitions would shift those indices.
+fn find_index_reference(module: &Node) -> Option<String> {
+    for node in module.node_iter() {
+        let words: Vec<&str> = std::iter::once(node.name.as_str())
+            .chain(node.immediate_attribute_iter())
+            .collect();
+        if let Some(pair) = words
+            .windows(2)
+            .find(|pair| REFERENCING_INSTRUCTIONS.contains(&pair[0]) && is_index(pair[1]))
+        {
+            return Some(pair.join(" "));
+        }
+        let is_descriptor = matches!(node.name.as_str(), "func" | "global") && node.depth > 1;
+        let lists_indices = node.name == "elem" || node.name == "start" || is_descriptor;
+        if lists_indices && node.immediate_attribute_iter().any(is_index) {
+            return Some(format!("{node}"));
+        }
+    }
+    None
+}
+
+/// Removes all functions, globals and types that aren’t reachable from the module’s exports, its start function or its other definitions, like memories, tables and segments. With `tree_shake.only_export`, the named export is the only one that is kept and everything that only other exports use is removed.
+pub fn tree_shake(module: &mut Node, linker: &mut Linker) -> Result<()> {
+    if !utils::is_module(module) {
+        return Err(TreeShakeError::NotAModule.into());
+    }
+
+    if let Some(name) = linker.option(ONLY_EXPORT_OPTION) {
+        if !module.exports().iter().any(|export| export.name == name) {
+            return Err(TreeShakeError::UnknownExport(name.to_string()).into());
+        }
+        module.items.retain(|item| {
+            !is_export_node(item) || item.as_node().and_then(export_name) == Some(name)
+        });
+        for node in module.immediate_node_iter_mut() {
+            node.items.retain(|item| {
+                !is_export_node(item) || item.as_node().and_then(export_name) == Some(name)
+            });
+        }
+    }
+
+    let nodes: Vec<&Node> = module.immediate_node_iter().collect();
+    let ids: Vec<Option<(DefKind, &str)>> = nodes
+        .iter()
+        .map(|node| {
+            let is_exported = node
+                .immediate_node_iter()
+                .any(|child| child.name == "export");
+            removable_id(node).filter(|_| !is_exported)
+        })
+        .collect();
+
+    // Ids are only unique per index space, so a function and a global can share one.
+    let mut reachable: HashSet<(DefKind, String)> = HashSet::new();
+    let mut queue: Vec<&Node> = nodes
+        .iter()
+        .zip(&ids)
+        .filter(|(_, id)| id.is_none())
+        .map(|(node, _)| *node)
+        .collect();
+    while let Some(node) = queue.pop() {
+        for (id_ref, id) in node.references() {
+            let kind = match id_ref {
+                IdRef::Def(kind) => kind,
+                IdRef::Local => continue,
+            };
+            if reachable.contains(&(kind, id.clone())) {
+                continue;
+            }
+            if let Some(idx) = ids.iter().position(|def| *def == Some((kind, id.as_str()))) {
+                reachable.insert((kind, id));
+                queue.push(nodes[idx]);
+            }
+        }
+    }
+
+    let unreachable: HashSet<(DefKind, String)> = ids
+        .iter()
+        .flatten()
+        .map(|&(kind, id)| (kind, id.to_string()))
+        .filter(|def| !reachable.contains(def))
+        .collect();
+    if unreachable.is_empty() {
+        return Ok(());
+    }
+    if let Some(reference) = find_index_reference(module) {
+        return Err(TreeShakeError::IndexReference(reference).into());
+    }
+    module.items.retain(|item| {
+        item.as_node()
+            .and_then(removable_id)
+            .map(|(kind, id)| !unreachable.contains(&(kind, id.to_string())))
+            .unwrap_or(true)
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::assert_module_eq;
+
+    fn run_test(input: &str, only_export: Option<&str>) -> Result<Node> {
+        let mut linker = Linker::default();
+        linker.features.push(("tree_shake", tree_shake));
+        if let Some(name) = only_export {
+            linker
+                .options
+                .insert(ONLY_EXPORT_OPTION.to_string(), name.to_string());
+        }
+        linker.link_raw(input)
+    }
+
+    #[test]
+    fn removes_unreachable() {
+        let module = run_test(
+            r#"
+                (module
+                    (import "env" "log" (func $log (param i32)))
+                    (import "env" "unused" (func $unused))
+                    (type $sig (func (param i32)))
+                    (global $counter (mut i32) (i32.const 0))
+                    (global $dead i32 (i32.const 1))
+                    (func $main (export "main") (call $helper (global.get $counter)))
+                    (func $helper (type $sig) (param $value i32) (call $log (local.get $value)))
+                    (func $orphan (call $helper (global.get $dead))))
+            "#,
+            None,
+        )
+        .unwrap();
+        assert_module_eq(
+            &module,
+            r#"(module (import "env" "log" (func $log (param i32))) (type $sig (func (param i32))) (global $counter (mut i32) (i32.const 0)) (func $main (export "main") (call $helper (global.get $counter))) (func $helper (type $sig) (param $value i32) (call $log (local.get $value))))"#,
+        );
+    }
+
+    #[test]
+    fn shared_ids() {
+        let module = run_test(
+            r#"
+                (module
+                    (global $x i32 (i32.const 0))
+                    (func $helper)
+                    (func $x (call $helper))
+                    (func $main (export "main") (call $x)))
+            "#,
+            None,
+        )
+        .unwrap();
+        assert_module_eq(
+            &module,
+            r#"(module (func $helper) (func $x (call $helper)) (func $main (export "main") (call $x)))"#,
+        );
+    }
+
+    #[test]
+    fn only_export() {
+        let input = r#"
+            (module
+                (memory (export "memory") 1)
+                (func $main (call $a))
+                (func $a)
+                (func $other (export "other") (call $b))
+                (func $b)
+                (export "main" (func $main)))
+        "#;
+        let module = run_test(input, Some("main")).unwrap();
+        assert_module_eq(
+            &module,
+            r#"(module (memory 1) (func $main (call $a)) (func $a) (export "main" (func $main)))"#,
+        );
+
+        let err = run_test(input, Some("missing")).unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: There is no export named missing"
+        );
+    }
+
+    #[test]
+    fn index_reference() {
+        let err = run_test(
+            r#"(module (func $main (export "main") (call 0)) (func $unused))"#,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something else went wrong: Tree shaker needs ids, but call 0 refers to an index"
+        );
+    }
+}
@@ -0,0 +1,23 @@
+//! The parser, AST and pretty-printer of silly-wat-linker. These only depend on `thiserror`, so they can be used without the loader, the `wasm3`-based constexpr evaluation and the CLI, which are part of the default `std` feature:
+//!
+//! ```toml
+//! silly-wat-linker = { version = "0.7", default-features = false }
+//! ```
+//!
+//! With `std`, the [`linker`], [`loader`] and [`features`] are available as well, so the linker can be embedded in other tools.
+
+pub mod ast;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod eval;
+#[cfg(feature = "std")]
+pub mod features;
+#[cfg(feature = "std")]
+pub mod linker;
+#[cfg(feature = "std")]
+pub mod loader;
+pub mod parser;
+pub mod pretty;
+#[cfg(all(test, feature = "std"))]
+mod test_support;
+pub mod utils;
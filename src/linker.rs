@@ -1,15 +1,42 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use crate::ast::Node;
+use crate::ast::{Item, Node};
 use crate::error::{Result, SWLError};
-use crate::features::Feature;
+use crate::features::{Feature, FEATURES};
 use crate::loader::{FileSystemLoader, Loader};
 use crate::parser;
+use crate::utils;
+
+static SWL_REQUIRES_NAME: &str = "swl.requires";
+
+/// The result of `Linker::link_module_reported`.
+#[derive(Debug)]
+pub struct LinkReport {
+    pub module: Node,
+    /// Names of the features that changed the module, in the order they ran.
+    pub changed_features: Vec<String>,
+}
+
+/// Receives the canonical path and the parsed module of a loaded file.
+pub type ModuleLoadedCallback = Box<dyn FnMut(&str, &Node)>;
 
 pub struct Linker {
     loader: Box<dyn Loader>,
     pub(crate) loaded_modules: HashSet<String>,
-    pub features: Vec<Feature>,
+    module_cache: HashMap<String, Node>,
+    touched_files: Vec<String>,
+    /// The features to run, in order, each with the name it is reported under.
+    pub features: Vec<(&'static str, Feature)>,
+    /// Names of the features enabled via `enable_feature`, checked against `(swl.requires ...)` directives.
+    enabled_features: HashSet<String>,
+    /// Options to configure features, keyed by `<feature>.<option>`.
+    pub options: HashMap<String, String>,
+    /// Values of `--define`s, used by `define_globals` and, if the `template` feature is enabled, for `${KEY}` tokens.
+    pub defines: Option<HashMap<String, String>>,
+    /// If set, comments are kept in the linked module instead of being discarded while parsing.
+    pub keep_comments: bool,
+    /// Called with the canonical path and the parsed module of every file the first time it is loaded, before any feature runs.
+    module_loaded: Option<ModuleLoadedCallback>,
 }
 
 impl Linker {
@@ -17,33 +44,192 @@ impl Linker {
         Linker {
             loader,
             loaded_modules: HashSet::new(),
+            module_cache: HashMap::new(),
+            touched_files: vec![],
             features: vec![],
+            enabled_features: HashSet::new(),
+            options: HashMap::new(),
+            defines: None,
+            keep_comments: false,
+            module_loaded: None,
         }
     }
 
+    /// Sets a callback that observes every loaded file, for example for linters or coverage tools. It fires once per distinct file, right after parsing.
+    pub fn on_module_loaded(&mut self, callback: ModuleLoadedCallback) {
+        self.module_loaded = Some(callback);
+    }
+
+    /// Looks up a feature by name and appends it to the list of features.
+    pub fn enable_feature(&mut self, name: &str) -> Result<()> {
+        let entry = FEATURES
+            .iter()
+            .find(|&&(key, _)| key == name)
+            .ok_or_else(|| SWLError::Simple(format!("Unknown pass name {name}")))?;
+        self.features.push(*entry);
+        self.enabled_features.insert(name.to_string());
+        Ok(())
+    }
+
+    /// Returns the canonical paths of all files that were loaded so far, in the order they were first loaded.
+    pub fn touched_files(&self) -> &[String] {
+        &self.touched_files
+    }
+
+    /// Records a loaded file for `touched_files`.
+    fn touch(&mut self, path: &str) -> Result<()> {
+        let canonical_path = self.canonicalize(path)?;
+        if !self.touched_files.contains(&canonical_path) {
+            self.touched_files.push(canonical_path);
+        }
+        Ok(())
+    }
+
+    pub fn option(&self, key: &str) -> Option<&str> {
+        self.options.get(key).map(|value| value.as_str())
+    }
+
+    /// Substitutes `${KEY}` tokens if the `template` feature is enabled. This has to happen before parsing, so it can’t be done by the feature itself.
+    fn preprocess(&self, content: &str) -> Result<String> {
+        match &self.defines {
+            Some(defines) if self.enabled_features.contains("template") => {
+                utils::substitute_template(content, defines)
+            }
+            _ => Ok(content.to_string()),
+        }
+    }
+
+    fn parse(&self, content: &str) -> Result<Node> {
+        let mut parser = parser::Parser::new(content);
+        parser.keep_comments = self.keep_comments;
+        parser.parse()
+    }
+
     pub fn link_raw<T: AsRef<str>>(&mut self, content: T) -> Result<Node> {
-        let module = parser::Parser::new(content).parse()?;
+        let content = self.preprocess(content.as_ref())?;
+        let module = self.parse(&content)?;
         self.link_module(module)
     }
 
+    /// Links a bare expression like `(i32.add (i32.const 1) (i32.const 2))` by wrapping it in a throwaway module. Numeric expressions are turned into constexprs, so they get folded if `constexpr` is enabled.
+    pub fn link_expression<T: AsRef<str>>(&mut self, content: T) -> Result<Node> {
+        let expr = parser::Parser::new(content).parse()?;
+        let typ = expr.name.split('.').next().unwrap_or("");
+        let wrapped =
+            if matches!(typ, "i32" | "i64" | "f32" | "f64") && !expr.name.ends_with(".constexpr") {
+                format!("(module ({typ}.constexpr {expr}))")
+            } else {
+                format!("(module {expr})")
+            };
+        let module = self.link_raw(wrapped)?;
+        module
+            .items
+            .into_iter()
+            .find_map(|item| match item {
+                Item::Node(node) => Some(node),
+                _ => None,
+            })
+            .ok_or_else(|| SWLError::Simple("Expression linked to an empty module".to_string()))
+    }
+
     pub fn link_file(&mut self, path: &str) -> Result<Node> {
         let module = self.load_module(path)?;
         self.link_module(module)
     }
 
+    /// Links multiple entry points. Imports are deduped per entry point, but every file is only loaded and parsed once.
+    pub fn link_all(&mut self, paths: &[&str]) -> Result<Vec<Node>> {
+        paths
+            .iter()
+            .map(|path| {
+                self.loaded_modules.clear();
+                self.link_file(path)
+            })
+            .collect()
+    }
+
+    /// Links multiple modules independently of each other, like `link_all` does for files.
+    pub fn link_modules(&mut self, modules: Vec<Node>) -> Result<Vec<Node>> {
+        modules
+            .into_iter()
+            .map(|module| {
+                self.loaded_modules.clear();
+                self.link_module(module)
+            })
+            .collect()
+    }
+
     pub fn link_module(&mut self, mut module: Node) -> Result<Node> {
-        for feature in self.features.clone() {
+        for (_, feature) in self.features.clone() {
             feature(&mut module, self)?;
         }
+        self.check_required_features(&mut module)?;
         Ok(module)
     }
+
+    /// Like `link_module`, but also reports which features changed the module.
+    pub fn link_module_reported(&mut self, mut module: Node) -> Result<LinkReport> {
+        let mut changed_features = vec![];
+        for (name, feature) in self.features.clone() {
+            let before = module.clone();
+            feature(&mut module, self)?;
+            if module != before {
+                changed_features.push(name.to_string());
+            }
+        }
+        self.check_required_features(&mut module)?;
+        Ok(LinkReport {
+            module,
+            changed_features,
+        })
+    }
+
+    /// Removes all `(swl.requires ...)` directives, including those pulled in by imports, and fails if any of the listed features isn’t enabled.
+    fn check_required_features(&self, module: &mut Node) -> Result<()> {
+        let mut missing: Vec<String> = vec![];
+        for directive in module
+            .immediate_node_iter()
+            .filter(|node| node.name == SWL_REQUIRES_NAME)
+        {
+            for name in directive.immediate_attribute_iter() {
+                if !self.enabled_features.contains(name) && !missing.iter().any(|m| m == name) {
+                    missing.push(name.to_string());
+                }
+            }
+        }
+        module.items.retain(|item| {
+            item.as_node()
+                .map(|node| node.name != SWL_REQUIRES_NAME)
+                .unwrap_or(true)
+        });
+        if !missing.is_empty() {
+            return Err(SWLError::Simple(format!(
+                "Module requires features that are not enabled: {}",
+                missing.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Runs all features a second time on a copy of an already linked module. Returns the name of the first feature that changed the module, if any.
+    pub fn find_non_idempotent_feature(&mut self, module: &Node) -> Result<Option<&'static str>> {
+        let mut module = module.clone();
+        for (name, feature) in self.features.clone() {
+            let before = module.clone();
+            feature(&mut module, self)?;
+            if module != before {
+                return Ok(Some(name));
+            }
+        }
+        Ok(None)
+    }
 }
 
 impl Default for Linker {
     fn default() -> Self {
-        Linker::new(Box::new(FileSystemLoader::new(
+        Linker::new(Box::new(FileSystemLoader::new(vec![
             std::env::current_dir().unwrap(),
-        )))
+        ])))
     }
 }
 
@@ -52,8 +238,16 @@ impl Loader for Linker {
         self.loader.canonicalize(path)
     }
 
+    // Raw data and modules are both loaded through here so that relative paths resolve against the same base.
     fn load_raw(&mut self, path: &str) -> Result<Vec<u8>> {
-        self.loader.load_raw(path)
+        let contents = self.loader.load_raw(path)?;
+        self.touch(path)?;
+        Ok(contents)
+    }
+
+    fn load_raw_streaming(&mut self, path: &str, f: &mut dyn FnMut(&[u8])) -> Result<()> {
+        self.loader.load_raw_streaming(path, f)?;
+        self.touch(path)
     }
 
     // Linker dedupes by returning an empty module when a module is loaded the second time.
@@ -61,16 +255,294 @@ impl Loader for Linker {
     fn load_module(&mut self, path: &str) -> Result<Node> {
         let canonical_path = self.canonicalize(path)?;
 
-        let contents = if self.loaded_modules.contains(&canonical_path) {
-            "(module)".to_string().into_bytes()
-        } else {
-            let contents = self.loader.load_raw(path)?;
+        if self.loaded_modules.contains(&canonical_path) {
+            return parser::Parser::new("(module)").parse();
+        }
+        if let Some(module) = self.module_cache.get(&canonical_path) {
             self.loaded_modules.insert(canonical_path);
-            contents
-        };
+            return Ok(module.clone());
+        }
 
+        let contents = self.load_raw(path)?;
         let contents = String::from_utf8(contents).map_err(|err| SWLError::Other(err.into()))?;
-        let module = parser::Parser::new(contents).parse()?;
+        // Executable scripts start with a shebang, which isn’t WAT.
+        let (_, contents) = utils::split_shebang(&contents);
+        let contents = self.preprocess(contents)?;
+        let module = self.parse(&contents)?;
+        if let Some(callback) = &mut self.module_loaded {
+            callback(&canonical_path, &module);
+        }
+        self.module_cache
+            .insert(canonical_path.clone(), module.clone());
+        self.loaded_modules.insert(canonical_path);
         Ok(module)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::features::import::import;
+    use crate::loader::MockLoader;
+
+    struct CountingLoader {
+        inner: MockLoader,
+        counts: Rc<RefCell<HashMap<String, usize>>>,
+    }
+
+    impl Loader for CountingLoader {
+        fn canonicalize(&mut self, path: &str) -> Result<String> {
+            self.inner.canonicalize(path)
+        }
+
+        fn load_raw(&mut self, path: &str) -> Result<Vec<u8>> {
+            *self
+                .counts
+                .borrow_mut()
+                .entry(path.to_string())
+                .or_insert(0) += 1;
+            self.inner.load_raw(path)
+        }
+    }
+
+    #[test]
+    fn link_all_shares_cache() {
+        let map = HashMap::from_iter(
+            [
+                ("a", r#"(module (import "common" (file)) (func $a))"#),
+                ("b", r#"(module (import "common" (file)) (func $b))"#),
+                ("common", r#"(module (func $common))"#),
+            ]
+            .into_iter()
+            .map(|(path, code)| (path.to_string(), code.to_string().into_bytes())),
+        );
+        let counts = Rc::new(RefCell::new(HashMap::new()));
+        let loader = CountingLoader {
+            inner: MockLoader { map },
+            counts: counts.clone(),
+        };
+        let mut linker = Linker::new(Box::new(loader));
+        linker.features.push(("import", import));
+
+        let modules = linker.link_all(&["a", "b"]).unwrap();
+        let modules: Vec<String> = modules.iter().map(|m| format!("{m}")).collect();
+        assert_eq!(
+            modules,
+            [
+                "(module (func $a) (func $common))",
+                "(module (func $b) (func $common))"
+            ]
+        );
+        assert_eq!(counts.borrow().get("common"), Some(&1));
+        assert_eq!(linker.touched_files(), ["a", "common", "b"]);
+    }
+
+    #[test]
+    fn module_loaded_callback() {
+        let map = HashMap::from_iter(
+            [
+                ("a", r#"(module (import "common" (file)) (func $a))"#),
+                ("b", r#"(module (import "common" (file)) (func $b))"#),
+                ("common", r#"(module (func $common))"#),
+            ]
+            .into_iter()
+            .map(|(path, code)| (path.to_string(), code.to_string().into_bytes())),
+        );
+        let mut linker = Linker::new(Box::new(MockLoader { map }));
+        linker.features.push(("import", import));
+        let loaded = Rc::new(RefCell::new(vec![]));
+        let loaded_clone = loaded.clone();
+        linker.on_module_loaded(Box::new(move |path, module| {
+            loaded_clone.borrow_mut().push(format!("{path}: {module}"));
+        }));
+
+        linker.link_all(&["a", "b", "a"]).unwrap();
+        assert_eq!(
+            *loaded.borrow(),
+            [
+                r#"a: (module (import "common" (file)) (func $a))"#,
+                "common: (module (func $common))",
+                r#"b: (module (import "common" (file)) (func $b))"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn link_modules_independently() {
+        let map = HashMap::from([("common".to_string(), b"(module (func $common))".to_vec())]);
+        let mut linker = Linker::new(Box::new(MockLoader { map }));
+        linker.features.push(("import", import));
+
+        let modules = parser::parse_modules(
+            r#"
+                (module (import "common" (file)) (func $a))
+                (module (import "common" (file)) (func $b))
+            "#,
+        )
+        .unwrap();
+        let modules = linker.link_modules(modules).unwrap();
+        let modules: Vec<String> = modules.iter().map(|m| format!("{m}")).collect();
+        assert_eq!(
+            modules,
+            [
+                "(module (func $a) (func $common))",
+                "(module (func $b) (func $common))"
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_survive_default_features() {
+        let map = HashMap::from([(
+            "util.wat".to_string(),
+            b";; Helpers\n(module (func $helper (; no-op ;) (nop)))".to_vec(),
+        )]);
+        let mut linker = Linker::new(Box::new(MockLoader { map }));
+        linker.keep_comments = true;
+        // The default features of the CLI.
+        for name in [
+            "import",
+            "repeat",
+            "numerals",
+            "data_import",
+            "constexpr",
+            "align_data",
+            "size_adjust",
+            "assert_memory",
+            "start_merge",
+            "sort",
+        ] {
+            linker.enable_feature(name).unwrap();
+        }
+        let module = linker
+            .link_raw(
+                r#"
+                    ;; Header
+                    (module
+                        (import "util.wat" (file))
+                        ;; Entry point
+                        (func $main
+                            (call $helper) ;; Calls the helper
+                            (i32.const 0x10) drop)
+                        (memory 1)
+                        (data (offset (; base ;) (i32.const (; at ;) 0x10000)) "x")
+                        (start $main)
+                        (; Host imports ;)
+                        (import "env" "log" (func $log (param i32))))
+                    ;; Footer
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            format!("{module}"),
+            ";; Header\n(module (; Host imports ;) (import \"env\" \"log\" (func $log (param i32))) ;; Entry point\n (func $main (call $helper) ;; Calls the helper\n (i32.const 16) drop) (memory 2) (data (offset (; base ;) (i32.const (; at ;) 65536)) \"x\") (start $main) (func $helper (; no-op ;) (nop))) ;; Footer\n"
+        );
+    }
+
+    #[test]
+    fn report_changed_features() {
+        let mut linker = Linker::default();
+        linker.enable_feature("numerals").unwrap();
+        linker.enable_feature("sort").unwrap();
+
+        let module = parser::Parser::new("(module (func (i32.const 0x10) drop))")
+            .parse()
+            .unwrap();
+        let report = linker.link_module_reported(module).unwrap();
+        assert_eq!(report.changed_features, ["numerals"]);
+        assert_eq!(
+            format!("{}", report.module),
+            "(module (func (i32.const 16) drop))"
+        );
+
+        let module = parser::Parser::new("(module (func (i32.const 16) drop))")
+            .parse()
+            .unwrap();
+        let report = linker.link_module_reported(module).unwrap();
+        assert!(report.changed_features.is_empty());
+    }
+
+    #[test]
+    fn default_features_are_idempotent() {
+        let mut linker = Linker::default();
+        for name in [
+            "import",
+            "repeat",
+            "numerals",
+            "data_import",
+            "constexpr",
+            "size_adjust",
+            "table_adjust",
+            "start_merge",
+            "sort",
+        ] {
+            linker.enable_feature(name).unwrap();
+        }
+        let module = linker
+            .link_raw(
+                r#"
+                    (module
+                        (memory $mem 1)
+                        (table $t funcref)
+                        (elem (i32.const 0x1) $a $b)
+                        (data (i32.const 0) "hello")
+                        (func $a (swl.repeat 2 (call $b)))
+                        (func $b)
+                        (start $a)
+                        (start $b)
+                        (import "env" "log" (func $log (param i32))))
+                "#,
+            )
+            .unwrap();
+        assert_eq!(linker.find_non_idempotent_feature(&module).unwrap(), None);
+    }
+
+    #[test]
+    fn non_idempotent_feature() {
+        fn append_func(module: &mut Node, _linker: &mut Linker) -> Result<()> {
+            module.append_node(parser::Parser::new("(func)").parse()?);
+            Ok(())
+        }
+        let mut linker = Linker::default();
+        linker.enable_feature("sort").unwrap();
+        linker.features.push(("append_func", append_func));
+        let module = linker.link_raw("(module)").unwrap();
+        assert_eq!(
+            linker.find_non_idempotent_feature(&module).unwrap(),
+            Some("append_func")
+        );
+    }
+
+    #[test]
+    fn required_features() {
+        let mut linker = Linker::default();
+        linker.enable_feature("import").unwrap();
+        linker.enable_feature("sort").unwrap();
+        let module = linker
+            .link_raw("(module (swl.requires import sort) (func $a))")
+            .unwrap();
+        assert_eq!(format!("{module}"), "(module (func $a))");
+
+        let err = linker
+            .link_raw("(module (swl.requires constexpr import size_adjust) (func $a))")
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Something went wrong: Module requires features that are not enabled: constexpr, size_adjust"
+        );
+    }
+
+    #[test]
+    fn bare_expression() {
+        let mut linker = Linker::default();
+        linker.enable_feature("constexpr").unwrap();
+        let expr = linker
+            .link_expression("(i32.add (i32.const 1) (i32.mul (i32.const 2) (i32.const 3)))")
+            .unwrap();
+        assert_eq!(format!("{expr}"), "(i32.const 7)");
+    }
+}
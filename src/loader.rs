@@ -1,38 +1,91 @@
 use std::collections::HashMap;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
 
 use crate::ast::Node;
 use crate::error::{Result, SWLError};
 use crate::parser::Parser;
+use crate::utils::split_shebang;
+
+/// Size of the chunks `FileSystemLoader::load_raw_streaming` reads.
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
 
 pub trait Loader {
     fn canonicalize(&mut self, path: &str) -> Result<String>;
     fn load_raw(&mut self, path: &str) -> Result<Vec<u8>>;
+    /// Feeds the contents of a file to `f` in chunks, so callers that transform the data don’t need the raw file in memory at once. By default, the whole file is loaded and passed as a single chunk.
+    fn load_raw_streaming(&mut self, path: &str, f: &mut dyn FnMut(&[u8])) -> Result<()> {
+        f(&self.load_raw(path)?);
+        Ok(())
+    }
     fn load_module(&mut self, path: &str) -> Result<Node> {
         let contents = self.load_raw(path)?;
         let contents = String::from_utf8(contents).map_err(|err| SWLError::Other(err.into()))?;
+        let (_, contents) = split_shebang(&contents);
         let module = Parser::new(contents).parse()?;
         Ok(module)
     }
 }
 
+/// Turns a path into a stable, platform-independent form. `.` and `..` are resolved lexically and both `/` and `\` are accepted as separators, but only `/` is emitted.
+pub fn normalize_path<T: AsRef<Path>>(path: T) -> String {
+    let path = path.as_ref().to_str().unwrap().replace('\\', "/");
+    let mut prefix = String::new();
+    let mut parts: Vec<String> = vec![];
+    for component in Path::new(&path).components() {
+        match component {
+            Component::Prefix(p) => prefix += p.as_os_str().to_str().unwrap(),
+            Component::RootDir => prefix += "/",
+            Component::CurDir => {}
+            Component::ParentDir => match parts.last() {
+                Some(last) if last != ".." => {
+                    parts.pop();
+                }
+                // `..` can’t go above the root.
+                _ if !prefix.is_empty() => {}
+                _ => parts.push("..".to_string()),
+            },
+            Component::Normal(part) => parts.push(part.to_str().unwrap().to_string()),
+        }
+    }
+    format!("{prefix}{}", parts.join("/"))
+}
+
 pub struct FileSystemLoader {
-    root: PathBuf,
+    roots: Vec<PathBuf>,
+    /// If set, paths that don’t exist in any root are retried with `.wat` appended.
+    pub infer_extension: bool,
 }
 
 impl FileSystemLoader {
-    pub fn new<T: AsRef<Path>>(root: T) -> FileSystemLoader {
+    /// Creates a loader that resolves paths against each of `roots` in order. The first root containing the file wins.
+    pub fn new(roots: Vec<PathBuf>) -> FileSystemLoader {
+        assert!(
+            !roots.is_empty(),
+            "FileSystemLoader needs at least one root"
+        );
         FileSystemLoader {
-            root: root.as_ref().to_path_buf(),
+            roots,
+            infer_extension: false,
         }
     }
 }
 
 impl Loader for FileSystemLoader {
     fn canonicalize(&mut self, path: &str) -> Result<String> {
-        let file_path = self.root.join(path);
-        Ok(file_path.to_str().unwrap().to_string())
+        let find = |path: &str| {
+            self.roots
+                .iter()
+                .map(|root| normalize_path(root.join(path)))
+                .find(|file_path| Path::new(file_path).exists())
+        };
+        let mut file_path = find(path);
+        if file_path.is_none() && self.infer_extension {
+            file_path = find(&format!("{path}.wat"));
+        }
+        // If no root has the file, report it relative to the first root.
+        Ok(file_path.unwrap_or_else(|| normalize_path(self.roots[0].join(path))))
     }
 
     fn load_raw(&mut self, path: &str) -> Result<Vec<u8>> {
@@ -40,6 +93,20 @@ impl Loader for FileSystemLoader {
         let contents = fs::read(canonical_path).map_err(|err| SWLError::Other(err.into()))?;
         Ok(contents)
     }
+
+    fn load_raw_streaming(&mut self, path: &str, f: &mut dyn FnMut(&[u8])) -> Result<()> {
+        let canonical_path = self.canonicalize(path)?;
+        let io_error = |err: std::io::Error| SWLError::Other(err.into());
+        let mut file = fs::File::open(canonical_path).map_err(io_error)?;
+        let mut buf = vec![0u8; STREAM_CHUNK_SIZE];
+        loop {
+            let n = file.read(&mut buf).map_err(io_error)?;
+            if n == 0 {
+                return Ok(());
+            }
+            f(&buf[..n]);
+        }
+    }
 }
 
 pub struct MockLoader {
@@ -60,3 +127,96 @@ impl Loader for MockLoader {
         Ok(contents)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::features::import::import;
+    use crate::linker::Linker;
+
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize_path("a/b/../c"), normalize_path("a/c"));
+        assert_eq!(normalize_path("a/b/../c"), "a/c");
+        assert_eq!(normalize_path("a\\.\\c"), "a/c");
+        assert_eq!(normalize_path("../a/../../b"), "../../b");
+        assert_eq!(normalize_path("/x/../../y"), "/y");
+    }
+
+    #[test]
+    fn canonicalize_dedupes_equivalent_paths() {
+        let mut loader = FileSystemLoader::new(vec![PathBuf::from("/root/dir")]);
+        assert_eq!(
+            loader.canonicalize("a/b/../c").unwrap(),
+            loader.canonicalize("./a/c").unwrap()
+        );
+    }
+
+    #[test]
+    fn multiple_roots() {
+        let base = std::env::temp_dir().join(format!("swl-roots-{}", std::process::id()));
+        let first = base.join("first");
+        let second = base.join("second");
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        fs::write(first.join("both.wat"), "(module (func $first))").unwrap();
+        fs::write(second.join("both.wat"), "(module (func $second))").unwrap();
+        fs::write(second.join("lib.wat"), "(module (func $lib))").unwrap();
+
+        let mut loader = FileSystemLoader::new(vec![first, second]);
+        let lib = loader.load_module("lib.wat");
+        let both = loader.load_module("both.wat");
+        fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(format!("{}", lib.unwrap()), "(module (func $lib))");
+        assert_eq!(format!("{}", both.unwrap()), "(module (func $first))");
+    }
+
+    #[test]
+    fn streaming() {
+        let root = std::env::temp_dir().join(format!("swl-streaming-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let contents: Vec<u8> = (0..3 * STREAM_CHUNK_SIZE + 5).map(|v| v as u8).collect();
+        fs::write(root.join("large.bin"), &contents).unwrap();
+
+        let mut loader = FileSystemLoader::new(vec![root.clone()]);
+        let mut chunks = 0;
+        let mut largest_chunk = 0;
+        let mut streamed = vec![];
+        let result = loader.load_raw_streaming("large.bin", &mut |chunk| {
+            chunks += 1;
+            largest_chunk = largest_chunk.max(chunk.len());
+            streamed.extend_from_slice(chunk);
+        });
+        fs::remove_dir_all(&root).unwrap();
+
+        result.unwrap();
+        assert!(chunks >= 4);
+        assert!(largest_chunk <= STREAM_CHUNK_SIZE);
+        assert_eq!(streamed, contents);
+    }
+
+    #[test]
+    fn infer_extension() {
+        let root = std::env::temp_dir().join(format!("swl-infer-ext-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("util.wat"), "(module (func $util))").unwrap();
+
+        let link = |infer_extension| {
+            let mut loader = FileSystemLoader::new(vec![root.clone()]);
+            loader.infer_extension = infer_extension;
+            let mut linker = Linker::new(Box::new(loader));
+            linker.features.push(("import", import));
+            linker.link_raw(r#"(module (import "util" (file)) (func $main))"#)
+        };
+        let inferred = link(true);
+        let exact = link(false);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            format!("{}", inferred.unwrap()),
+            "(module (func $main) (func $util))"
+        );
+        assert!(exact.is_err());
+    }
+}
@@ -1,44 +1,67 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs::File;
-use std::io::{self, Read, Write};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 
 use clap::{Args, Parser, Subcommand};
 
-use anyhow::{anyhow, Result as AnyResult};
+use anyhow::{anyhow, Context, Result as AnyResult};
 use error::SWLError;
-use pretty::pretty_print;
-
-mod ast;
-mod error;
-mod eval;
-mod features;
-mod linker;
-mod loader;
-mod parser;
-mod pretty;
-mod utils;
-
-static FEATURES: &[(&str, features::Feature)] = &[
-    ("import", features::import::import),
-    ("sort", features::sort::sort),
-    ("size_adjust", features::size_adjust::size_adjust),
-    ("start_merge", features::start_merge::start_merge),
-    ("data_import", features::data_import::data_import),
-    ("constexpr", features::constexpr::constexpr),
-    ("numerals", features::numerals::numerals),
-];
+use pretty::{CommentStyle, PrettyPrinter};
+use silly_wat_linker::{ast, error, features, linker, loader, parser, pretty, utils};
+
+mod explain;
+mod split;
+
+static EXIT_CODES_HELP: &str = "EXIT CODES:
+    0    Success
+    2    Parse error
+    3    Link or feature error
+    4    I/O error
+    5    Binary compilation error";
 
 #[derive(Parser)]
-#[clap(author, version, about)]
+#[clap(author, version, about, after_help = EXIT_CODES_HELP)]
 struct Cli {
     #[clap(subcommand)]
     command: Command,
+
+    /// Colorize error output. `auto` colorizes if stderr is a terminal and `NO_COLOR` is not set.
+    #[clap(
+        long = "color",
+        global = true,
+        value_parser = ["auto", "always", "never"],
+        default_value = "auto"
+    )]
+    color: String,
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum Command {
     Compile(CompileOpts),
     Format(FormatOpts),
+    Split(SplitOpts),
+    Explain(ExplainOpts),
+}
+
+#[derive(Args)]
+struct ExplainOpts {
+    /// Name of the feature to explain, e.g. `constexpr`.
+    #[clap(value_parser)]
+    feature: String,
+}
+
+#[derive(Args)]
+struct SplitOpts {
+    /// Path to input file. "-" means stdin.
+    #[clap(value_parser, default_value = "-")]
+    input: String,
+
+    /// Directory to write one file per section into, e.g. `funcs.wat` and `data.wat`.
+    #[clap(long = "output-dir", value_parser)]
+    output_dir: PathBuf,
 }
 
 #[derive(Args)]
@@ -46,6 +69,22 @@ struct FormatOpts {
     /// Files to format
     #[clap(value_parser)]
     input: Vec<String>,
+
+    /// Path to a file listing additional files to format, one per line.
+    #[clap(long = "input-list", value_parser)]
+    input_list: Option<String>,
+
+    /// How to normalize line comments. `single-space` puts exactly one space after `;;`, `preserve` keeps comments as written and `none` removes the space.
+    #[clap(
+        long = "comment-style",
+        value_parser = ["preserve", "single-space", "none"],
+        default_value = "single-space"
+    )]
+    comment_style: String,
+
+    /// Re-escape all string literals canonically: printable ASCII as is, every other byte as a `\XX` escape sequence.
+    #[clap(long = "normalize-strings", default_value_t = false, value_parser)]
+    normalize_strings: bool,
 }
 
 #[derive(Args)]
@@ -54,6 +93,10 @@ struct CompileOpts {
     #[clap(value_parser, default_value = "-")]
     input: String,
 
+    /// Path to a file listing input files, one per line. All modules are written to the output one after another.
+    #[clap(long = "input-list", value_parser, conflicts_with = "input")]
+    input_list: Option<String>,
+
     /// Path to output file. "-" means stdout.
     #[clap(short = 'o', long = "output", default_value = "-")]
     output: String,
@@ -71,6 +114,19 @@ struct CompileOpts {
     #[clap(long = "pretty", default_value_t = false, value_parser)]
     pretty: bool,
 
+    /// Split string literals in data segments into chunks of at most this many bytes when pretty-printing.
+    #[clap(long = "data-wrap", requires = "pretty", value_parser, name = "BYTES")]
+    data_wrap: Option<usize>,
+
+    /// Fail if the binary is larger than this many bytes.
+    #[clap(
+        long = "max-binary-size",
+        requires = "emit-binary",
+        value_parser,
+        name = "MAX BYTES"
+    )]
+    max_binary_size: Option<usize>,
+
     /// Comma-separated list of additional flags to pass to wat2wasm.
     #[clap(
         long = "wat2wasm-flags",
@@ -84,46 +140,206 @@ struct CompileOpts {
     #[clap(
         long = "features",
         name = "FEATURE LIST",
-        default_value = "import, numerals, data_import, constexpr, size_adjust, start_merge, sort"
+        default_value = "import, repeat, numerals, data_import, constexpr, align_data, size_adjust, assert_memory, start_merge, sort"
     )]
     feature_list: String,
 
-    /// Root for import path resolution.
-    #[clap(short = 'r', long = "root", value_parser)]
-    root: Option<String>,
-}
+    /// Print the dependencies between features as a DOT graph and exit. An edge `a -> b` means `a` has to run before `b`.
+    #[clap(long = "print-feature-graph", default_value_t = false, value_parser)]
+    print_feature_graph: bool,
 
-fn feature_list_parser(compile_opts: &CompileOpts) -> AnyResult<Vec<features::Feature>> {
-    let list: Vec<AnyResult<features::Feature>> = compile_opts
-        .feature_list
-        .split(',')
-        .map(|item| {
-            let name = item.trim();
-            let feature = FEATURES
-                .iter()
-                .find(|&&(key, _)| key == name)
-                .map(|&(_, feature)| feature);
-            feature.ok_or(anyhow!("Unknown pass name {}", name))
-        })
-        .collect();
+    /// Print notices about what the linker does implicitly, like enabling features that a requested feature depends on.
+    #[clap(short = 'v', long = "verbose", default_value_t = false, value_parser)]
+    verbose: bool,
+
+    /// Write a Makefile-style rule to this path, listing all files the output depends on.
+    #[clap(long = "depfile", value_parser, name = "DEPFILE")]
+    depfile: Option<String>,
+
+    /// Treat the input as a `.wast` script: link all of its modules and discard commands like `assert_return`.
+    #[clap(
+        long = "strip-script",
+        default_value_t = false,
+        value_parser,
+        conflicts_with = "input-list"
+    )]
+    strip_script: bool,
+
+    /// Treat the input as a bare expression like `(i32.add (i32.const 1) (i32.const 2))` instead of a module. Numeric expressions are folded by `constexpr`, turning SWL into a WAT calculator.
+    #[clap(
+        long = "no-module-wrapper",
+        default_value_t = false,
+        value_parser,
+        conflicts_with_all = &["input-list", "strip-script", "parse-only"]
+    )]
+    no_module_wrapper: bool,
+
+    /// Treat the input as a file containing multiple `(module ...)` definitions, like spec-style test files, and link each of them independently.
+    #[clap(
+        long = "multi-module",
+        default_value_t = false,
+        value_parser,
+        conflicts_with_all = &["input-list", "strip-script", "no-module-wrapper", "parse-only"]
+    )]
+    multi_module: bool,
+
+    /// Write every linked module into its own file in this directory (`0.wat`, `1.wat`, ...) instead of writing all of them to the output.
+    #[clap(
+        long = "output-dir",
+        value_parser,
+        requires = "multi-module",
+        conflicts_with_all = &["output", "DEPFILE"]
+    )]
+    output_dir: Option<PathBuf>,
+
+    /// Inline all `(file)` and `(raw)` imports, enabling `import` and `data_import` even if they are not in the feature list, and fail if any import is left unresolved.
+    #[clap(
+        long = "self-contained",
+        default_value_t = false,
+        value_parser,
+        conflicts_with = "parse-only"
+    )]
+    self_contained: bool,
+
+    /// Remove all exports except this one, along with everything only they use. Enables `tree_shake`.
+    #[clap(long = "only-export", value_parser, name = "EXPORT")]
+    only_export: Option<String>,
+
+    /// Fail on top-level nodes that aren’t Wasm sections, like a misspelled `(fucn $x)`. Enables `check_known_sections`.
+    #[clap(long = "strict", default_value_t = false, value_parser)]
+    strict: bool,
+
+    /// Discard comments instead of keeping them in the output.
+    #[clap(long = "strip-comments", default_value_t = false, value_parser)]
+    strip_comments: bool,
+
+    /// Only parse and re-serialize the input without running any features.
+    #[clap(long = "parse-only", default_value_t = false, value_parser)]
+    parse_only: bool,
+
+    /// Run all features a second time and fail if any of them changes the module again. Debugging aid for feature authors, only available in debug builds.
+    #[cfg(debug_assertions)]
+    #[clap(long = "check-idempotence", default_value_t = false, value_parser)]
+    check_idempotence: bool,
+
+    /// Radix of folded integer constexprs. `preserve` renders hexadecimal if all literals in the expression were hexadecimal.
+    #[clap(
+        long = "constexpr-radix",
+        value_parser = ["preserve", "decimal", "hex"],
+        name = "RADIX"
+    )]
+    constexpr_radix: Option<String>,
+
+    /// Only fold constexprs that the built-in evaluator handles and fail on anything that would need the wasm3 runtime.
+    #[clap(long = "offline-constexpr", default_value_t = false, value_parser)]
+    offline_constexpr: bool,
+
+    /// Variable in the form `<KEY>=<VALUE>`, used by the `template` and `define_globals` features. Can be given multiple times.
+    #[clap(long = "define", value_parser, name = "DEFINE")]
+    defines: Vec<String>,
+
+    /// Feature option in the form `<feature>.<option>=<value>`. Can be given multiple times.
+    #[clap(long = "option", value_parser, name = "OPTION")]
+    options: Vec<String>,
+
+    /// Resolve file imports like `(import "util" (file))` to `util.wat` if no file with the exact name exists.
+    #[clap(long = "infer-extension", default_value_t = false, value_parser)]
+    infer_extension: bool,
+
+    /// Root for import path resolution. Can be given multiple times, in which case roots are tried in order.
+    #[clap(short = 'r', long = "root", value_parser)]
+    root: Vec<PathBuf>,
 
-    let result: Vec<features::Feature> = AnyResult::from_iter(list)?;
-    Ok(result)
+    /// Path of the file piped in via stdin. Without `--root`, its directory is the root for file and raw imports instead of the current directory.
+    #[clap(long = "stdin-filename", value_parser, name = "PATH")]
+    stdin_filename: Option<PathBuf>,
 }
 
-fn main() -> AnyResult<()> {
+fn main() {
     let cli = Cli::parse();
 
-    match cli.command {
-        Command::Compile(compile_opts) => compile(compile_opts)?,
-        Command::Format(format_opts) => formatter(format_opts)?,
+    let result = match cli.command {
+        Command::Compile(compile_opts) => compile(compile_opts),
+        Command::Format(format_opts) => formatter(format_opts),
+        Command::Split(split_opts) => split(split_opts),
+        Command::Explain(explain_opts) => explain(explain_opts),
     };
 
-    Ok(())
+    if let Err(err) = result {
+        let color = match cli.color.as_str() {
+            "always" => true,
+            "never" => false,
+            _ => io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        };
+        eprintln!("{}", render_error(&err, color));
+        std::process::exit(exit_code(&err));
+    }
+}
+
+/// Renders an error and its causes like anyhow’s debug output, optionally with ANSI colors.
+fn render_error(err: &anyhow::Error, color: bool) -> String {
+    if !color {
+        return format!("Error: {err:?}");
+    }
+    let mut out = format!("\x1b[1;31mError:\x1b[0m {err}");
+    let causes: Vec<String> = err.chain().skip(1).map(|cause| cause.to_string()).collect();
+    match causes.as_slice() {
+        [] => {}
+        [cause] => out.push_str(&format!("\n\n\x1b[36mCaused by:\x1b[0m\n    {cause}")),
+        causes => {
+            out.push_str("\n\n\x1b[36mCaused by:\x1b[0m");
+            for (idx, cause) in causes.iter().enumerate() {
+                out.push_str(&format!("\n    {idx}: {cause}"));
+            }
+        }
+    }
+    out
+}
+
+fn exit_code(err: &anyhow::Error) -> i32 {
+    if err.is::<wat::Error>() {
+        return 5;
+    }
+    if err.is::<io::Error>() {
+        return 4;
+    }
+    match err.downcast_ref::<SWLError>() {
+        Some(SWLError::ParserError(_)) => 2,
+        Some(SWLError::Other(inner)) if inner.is::<io::Error>() => 4,
+        _ => 3,
+    }
+}
+
+/// Reads a manifest of input files. Blank lines and lines starting with `#` are ignored. Paths are relative to the manifest’s directory.
+fn read_input_list(path: &str) -> AnyResult<Vec<String>> {
+    let contents = std::fs::read_to_string(path)?;
+    let base = env::current_dir()?.join(path);
+    let base = base.parent().unwrap();
+    Ok(contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line).to_str().unwrap().to_string())
+        .collect())
+}
+
+/// Reads an input file. "-" means stdin.
+fn read_input(path: &str) -> AnyResult<String> {
+    let mut content = String::new();
+    if path == "-" {
+        io::stdin().read_to_string(&mut content)?;
+    } else {
+        File::open(path)?.read_to_string(&mut content)?;
+    }
+    Ok(content)
 }
 
 fn formatter(format_opts: FormatOpts) -> AnyResult<()> {
-    for input_file in &format_opts.input {
+    let mut input_files = format_opts.input;
+    if let Some(input_list) = &format_opts.input_list {
+        input_files.extend(read_input_list(input_list)?);
+    }
+    for input_file in &input_files {
         let mut in_file: Box<dyn std::io::Read> = if input_file == "-" {
             Box::new(std::io::stdin())
         } else {
@@ -131,58 +347,281 @@ fn formatter(format_opts: FormatOpts) -> AnyResult<()> {
         };
         let mut buf = String::new();
         in_file.read_to_string(&mut buf)?;
-        let pretty_module = pretty_print(&buf)
-            .map_err(|err| SWLError::Simple(format!("Failure parsing {input_file}: {err}")))?;
+        let (shebang, buf) = utils::split_shebang(&buf);
+        let mut printer = PrettyPrinter::new();
+        printer.comment_style = format_opts
+            .comment_style
+            .parse::<CommentStyle>()
+            .map_err(|err| anyhow!(err))?;
+        printer.normalize_strings = format_opts.normalize_strings;
+        let pretty_module = printer
+            .print(buf)
+            .with_context(|| format!("Failure parsing {input_file}"))?;
+        let pretty_module = format!("{}{pretty_module}", shebang.unwrap_or_default());
         drop(in_file);
-        let mut out_file: Box<dyn std::io::Write> = if input_file == "-" {
-            Box::new(std::io::stdout())
+        if input_file == "-" {
+            io::stdout().write_all(pretty_module.as_bytes())?;
         } else {
-            Box::new(std::fs::File::options().write(true).open(input_file)?)
-        };
-        out_file.write_all(pretty_module.as_bytes())?;
+            write_atomically(Path::new(input_file), pretty_module.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn explain(explain_opts: ExplainOpts) -> AnyResult<()> {
+    println!("{}", explain::explain(&explain_opts.feature)?);
+    Ok(())
+}
+
+fn split(split_opts: SplitOpts) -> AnyResult<()> {
+    let module = parser::parse_only(&read_input(&split_opts.input)?)?;
+    std::fs::create_dir_all(&split_opts.output_dir)?;
+    for (file_name, section) in split::split_module(&module)? {
+        std::fs::write(split_opts.output_dir.join(file_name), format!("{section}"))?;
     }
     Ok(())
 }
 
 fn compile(compile_opts: CompileOpts) -> AnyResult<()> {
-    let feature_list = feature_list_parser(&compile_opts)?;
+    if compile_opts.print_feature_graph {
+        print!("{}", features::dependency_graph());
+        return Ok(());
+    }
 
-    let root = compile_opts
-        .root
-        .unwrap_or_else(|| env::current_dir().unwrap().to_str().unwrap().to_string());
+    let mut roots = compile_opts.root.clone();
+    let stdin_dir = compile_opts
+        .stdin_filename
+        .as_ref()
+        .filter(|_| compile_opts.input == "-")
+        .and_then(|path| path.parent());
+    if roots.is_empty() {
+        roots.push(env::current_dir()?.join(stdin_dir.unwrap_or(Path::new(""))));
+    }
 
-    let loader = loader::FileSystemLoader::new(root);
+    let mut loader = loader::FileSystemLoader::new(roots);
+    loader.infer_extension = compile_opts.infer_extension;
     let mut linker = linker::Linker::new(Box::new(loader));
-    for feature in feature_list.into_iter() {
-        linker.features.push(feature);
+    linker.keep_comments = !compile_opts.strip_comments;
+    let mut requested: Vec<&str> = compile_opts
+        .feature_list
+        .split(',')
+        .map(str::trim)
+        .collect();
+    if compile_opts.self_contained {
+        for (idx, name) in ["import", "data_import"].into_iter().enumerate() {
+            if !requested.contains(&name) {
+                if compile_opts.verbose {
+                    eprintln!("Enabling feature {name} for --self-contained");
+                }
+                requested.insert(idx, name);
+            }
+        }
+    }
+    if compile_opts.only_export.is_some() && !requested.contains(&"tree_shake") {
+        if compile_opts.verbose {
+            eprintln!("Enabling feature tree_shake for --only-export");
+        }
+        features::insert_feature(&mut requested, "tree_shake");
+    }
+    if compile_opts.strict && !requested.contains(&"check_known_sections") {
+        if compile_opts.verbose {
+            eprintln!("Enabling feature check_known_sections for --strict");
+        }
+        features::insert_feature(&mut requested, "check_known_sections");
+    }
+    let (feature_names, inserted) = features::resolve_dependencies(&requested);
+    if compile_opts.verbose {
+        for (dependency, dependent) in inserted {
+            eprintln!("Enabling feature {dependency}, which {dependent} depends on");
+        }
+    }
+    for name in &feature_names {
+        linker.enable_feature(name)?;
+    }
+    if !compile_opts.defines.is_empty() {
+        let mut defines = HashMap::new();
+        for define in &compile_opts.defines {
+            let (key, value) = define
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid define {define}, expected <KEY>=<VALUE>"))?;
+            defines.insert(key.trim().to_string(), value.to_string());
+        }
+        linker.defines = Some(defines);
+    }
+    for option in &compile_opts.options {
+        let (key, value) = option.split_once('=').ok_or_else(|| {
+            anyhow!("Invalid option {option}, expected <feature>.<option>=<value>")
+        })?;
+        linker
+            .options
+            .insert(key.trim().to_string(), value.trim().to_string());
+    }
+    if let Some(name) = &compile_opts.only_export {
+        linker.options.insert(
+            features::tree_shake::ONLY_EXPORT_OPTION.to_string(),
+            name.clone(),
+        );
+    }
+    if let Some(radix) = &compile_opts.constexpr_radix {
+        linker
+            .options
+            .insert(features::constexpr::RADIX_OPTION.to_string(), radix.clone());
+    }
+    if compile_opts.offline_constexpr {
+        linker.options.insert(
+            features::constexpr::OFFLINE_OPTION.to_string(),
+            "true".to_string(),
+        );
     }
 
-    let module = if compile_opts.input == "-" {
-        let mut content = String::new();
-        io::stdin().read_to_string(&mut content)?;
-        linker.link_raw(content)?
+    // Manifests list many files, which can’t share a single shebang.
+    let content = match &compile_opts.input_list {
+        Some(_) => String::new(),
+        None => read_input(&compile_opts.input)?,
+    };
+    let (shebang, content) = utils::split_shebang(&content);
+    let modules = if compile_opts.parse_only {
+        match &compile_opts.input_list {
+            Some(input_list) => read_input_list(input_list)?
+                .iter()
+                .map(|path| Ok(parser::parse_only(&read_input(path)?)?))
+                .collect::<AnyResult<Vec<_>>>()?,
+            None => vec![parser::parse_only(content)?],
+        }
+    } else if compile_opts.no_module_wrapper {
+        vec![linker.link_expression(content)?]
+    } else if compile_opts.multi_module {
+        linker.link_modules(parser::parse_modules(content)?)?
+    } else if compile_opts.strip_script {
+        parser::parse_script(content)?
+            .into_iter()
+            .map(|module| linker.link_module(module))
+            .collect::<Result<Vec<_>, _>>()?
+    } else if let Some(input_list) = &compile_opts.input_list {
+        let paths = read_input_list(input_list)?;
+        let paths: Vec<&str> = paths.iter().map(|path| path.as_str()).collect();
+        linker.link_all(&paths)?
+    } else if compile_opts.input == "-" {
+        vec![linker.link_raw(content)?]
     } else {
-        linker.link_file(&compile_opts.input)?
+        vec![linker.link_file(&compile_opts.input)?]
     };
-    let mut payload = format!("{module}");
+    if compile_opts.self_contained {
+        for module in &modules {
+            check_self_contained(module)?;
+        }
+    }
+    #[cfg(debug_assertions)]
+    if compile_opts.check_idempotence {
+        for module in &modules {
+            if let Some(name) = linker.find_non_idempotent_feature(module)? {
+                return Err(SWLError::Simple(format!("Feature {name} is not idempotent")).into());
+            }
+        }
+    }
+    if let Some(output_dir) = &compile_opts.output_dir {
+        std::fs::create_dir_all(output_dir)?;
+        let extension = if compile_opts.emit_binary {
+            "wasm"
+        } else {
+            "wat"
+        };
+        for (idx, module) in modules.iter().enumerate() {
+            let payload = render_modules(std::slice::from_ref(module), &compile_opts)?;
+            std::fs::write(output_dir.join(format!("{idx}.{extension}")), payload)?;
+        }
+        return Ok(());
+    }
+    if compile_opts.emit_binary && modules.len() != 1 {
+        return Err(
+            SWLError::Simple("--emit-binary requires exactly one input".to_string()).into(),
+        );
+    }
+    let mut payload = render_modules(&modules, &compile_opts)?;
+    if let (Some(shebang), false) = (shebang, compile_opts.emit_binary) {
+        payload.splice(0..0, shebang.bytes());
+    }
+
+    if let Some(depfile) = &compile_opts.depfile {
+        if compile_opts.output == "-" {
+            return Err(SWLError::Simple("--depfile requires an output file".to_string()).into());
+        }
+        std::fs::write(
+            depfile,
+            depfile_rule(&compile_opts.output, linker.touched_files()),
+        )?;
+    }
+
+    if compile_opts.output == "-" {
+        io::stdout().write_all(&payload)?;
+    } else {
+        write_atomically(Path::new(&compile_opts.output), &payload)?;
+    }
+
+    Ok(())
+}
+
+/// Writes `contents` to a temporary sibling of `path` and renames it into place, so `path` never holds a partially written file.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+    let result = std::fs::write(&tmp_path, contents).and_then(|_| std::fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Serializes linked modules one after another, pretty-printing or compiling them as requested.
+fn render_modules(modules: &[ast::Node], compile_opts: &CompileOpts) -> AnyResult<Vec<u8>> {
+    let mut payload = modules
+        .iter()
+        .map(|module| format!("{module}"))
+        .collect::<Vec<String>>()
+        .join("\n");
     if compile_opts.pretty {
-        payload = pretty_print(&payload)?;
+        let mut printer = PrettyPrinter::new();
+        printer.data_wrap = compile_opts.data_wrap;
+        payload = printer.print(&payload)?;
     }
     let mut payload = payload.into_bytes();
 
     if compile_opts.emit_binary {
         payload = compile_wat(&payload)?;
+        if let Some(max_binary_size) = compile_opts.max_binary_size {
+            if payload.len() > max_binary_size {
+                return Err(SWLError::Simple(format!(
+                    "Binary is {} bytes, but at most {max_binary_size} bytes are allowed",
+                    payload.len()
+                ))
+                .into());
+            }
+        }
     }
+    Ok(payload)
+}
 
-    let mut output: Box<dyn Write> = if compile_opts.output == "-" {
-        Box::new(io::stdout())
-    } else {
-        Box::new(File::create(compile_opts.output)?)
-    };
-
-    output.write_all(&payload)?;
+/// Fails if a module still contains `(file)` or `(raw)` imports, for example because they are nested somewhere the import features don’t look.
+fn check_self_contained(module: &ast::Node) -> Result<(), SWLError> {
+    let unresolved = module.node_iter().find(|node| {
+        node.name == "import"
+            && node
+                .immediate_node_iter()
+                .any(|child| child.name == "file" || child.name == "raw")
+    });
+    match unresolved {
+        Some(import) => Err(SWLError::Simple(format!(
+            "Unresolved import {import} in --self-contained output"
+        ))),
+        None => Ok(()),
+    }
+}
 
-    Ok(())
+/// Renders a Makefile rule like `out.wasm: main.wat util.wat`. Spaces in paths are escaped.
+fn depfile_rule(target: &str, dependencies: &[String]) -> String {
+    let escape = |path: &str| path.replace(' ', "\\ ");
+    let dependencies: Vec<String> = dependencies.iter().map(|path| escape(path)).collect();
+    format!("{}: {}\n", escape(target), dependencies.join(" "))
 }
 
 fn compile_wat(wat_str: &[u8]) -> AnyResult<Vec<u8>> {
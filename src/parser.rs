@@ -1,45 +1,289 @@
 use crate::ast::{Item, Node};
-use crate::error::Result;
+use crate::error::{Result, SWLError};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum ParserError {
     #[error("Unexpected EOF")]
     UnexpectedEOF,
-    #[error("Stray data: {0}")]
-    StrayData(String),
-    #[error("Unexpected token. Expected {expected}, got {got}")]
-    UnexpectedToken { expected: String, got: String },
+    #[error("Empty input: expected a top-level (module ...)")]
+    EmptyInput,
+    #[error("Stray data at {line}:{column}: {data}")]
+    StrayData {
+        data: String,
+        line: usize,
+        column: usize,
+    },
+    #[error("Unexpected token at {line}:{column}: expected {expected}, got {got}")]
+    UnexpectedToken {
+        expected: String,
+        got: String,
+        line: usize,
+        column: usize,
+    },
     #[error("Invalid escape sequence in string Litera")]
     InvalidEscapeSequence,
+    #[error("{line}:{column}: {error}")]
+    At {
+        line: usize,
+        column: usize,
+        error: Box<ParserError>,
+    },
+}
+
+/// Returns the 1-based line and column of a position in the input.
+pub(crate) fn line_column(input: &[char], pos: usize) -> (usize, usize) {
+    let before = &input[..pos.min(input.len())];
+    let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+    let column = before.iter().rev().take_while(|&&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+/// Parses a module without running any features. Useful to profile or fuzz the parser in isolation.
+pub fn parse_only(content: &str) -> Result<Node> {
+    Parser::new(content).parse()
+}
+
+/// Names of `.wast` script commands that are discarded by `parse_script`.
+static SCRIPT_COMMANDS: &[&str] = &[
+    "assert_return",
+    "assert_trap",
+    "assert_exhaustion",
+    "assert_exception",
+    "assert_malformed",
+    "assert_invalid",
+    "assert_unlinkable",
+    "invoke",
+    "get",
+    "register",
+];
+
+/// Parses a `.wast`-style script and returns only its textual modules. Script commands like `assert_return` as well as binary and quoted modules are discarded.
+pub fn parse_script(content: &str) -> Result<Vec<Node>> {
+    let mut parser = Parser::new(content);
+    let nodes = parser.parse_multiple()?;
+    let mut modules = vec![];
+    for node in nodes {
+        if SCRIPT_COMMANDS.contains(&node.name.as_str()) {
+            continue;
+        }
+        if node.name != "module" {
+            return Err(parser.stray_node(&node).into());
+        }
+        let is_textual = !node
+            .immediate_attribute_iter()
+            .any(|attr| attr == "binary" || attr == "quote");
+        if is_textual {
+            modules.push(node);
+        }
+    }
+    Ok(modules)
+}
+
+/// Parses a file containing one or more top-level `(module ...)` definitions.
+pub fn parse_modules(content: &str) -> Result<Vec<Node>> {
+    let mut parser = Parser::new(content);
+    let nodes = parser.parse_multiple()?;
+    if let Some(node) = nodes.iter().find(|node| node.name != "module") {
+        return Err(parser.stray_node(node).into());
+    }
+    Ok(nodes)
 }
 
 pub struct Parser {
     input: Vec<char>,
+    /// Byte offset of every char in `input`, followed by the length of the input in bytes.
+    byte_offsets: Vec<usize>,
     pos: usize,
     depth: usize,
+    /// If set, comments are kept as `Item::LineComment` and `Item::BlockComment` instead of being discarded. Comments before and after the top-level node end up in its `leading_comments` and `trailing_comments`.
+    pub keep_comments: bool,
+    /// Comments that were eaten as whitespace but not yet added to a node.
+    pending_comments: Vec<Item>,
+    /// Set by `parse_recovering`. Errors are collected in `errors` instead of aborting the parse.
+    recovering: bool,
+    errors: Vec<(usize, ParserError)>,
 }
 
-static ADDITIONAL_ALLOWED_CHARS: &str = "._-";
+// `/` and `:` appear in legacy instruction names like `i32.trunc_s:sat/f32`.
+static ADDITIONAL_ALLOWED_CHARS: &str = "._-/:";
 
 impl Parser {
     pub fn new<T: AsRef<str>>(input: T) -> Parser {
+        let input = input.as_ref();
         Parser {
-            input: input.as_ref().chars().collect(),
+            input: input.chars().collect(),
+            byte_offsets: input
+                .char_indices()
+                .map(|(offset, _)| offset)
+                .chain(std::iter::once(input.len()))
+                .collect(),
             pos: 0,
             depth: 0,
+            keep_comments: false,
+            pending_comments: vec![],
+            recovering: false,
+            errors: vec![],
         }
     }
 
     pub fn parse(&mut self) -> Result<Node> {
-        let node = self.parse_node()?;
+        let mut node = self.parse_top_level_node()?;
+        self.assert_eof()?;
+        node.trailing_comments = std::mem::take(&mut self.pending_comments);
+        Ok(node)
+    }
+
+    /// Like `parse`, but doesn’t stop at the first error. After an error, the parser skips ahead to the next node or to the end of the node the error occurred in, and continues from there. Unclosed nodes are closed at EOF. Returns whatever could be parsed along with all errors. Errors that don’t carry a position themselves are wrapped in `ParserError::At`.
+    pub fn parse_recovering(&mut self) -> (Option<Node>, Vec<ParserError>) {
+        self.recovering = true;
+        let mut node = match self.parse_top_level_node() {
+            Ok(node) => Some(node),
+            Err(err) => {
+                self.record_error(err);
+                None
+            }
+        };
+        if let Some(node) = &mut node {
+            if let Err(err) = self.assert_eof() {
+                self.record_error(err);
+            }
+            node.trailing_comments = std::mem::take(&mut self.pending_comments);
+        }
+        self.recovering = false;
+
+        let errors = std::mem::take(&mut self.errors)
+            .into_iter()
+            .map(|(pos, error)| match error {
+                ParserError::StrayData { .. } | ParserError::UnexpectedToken { .. } => error,
+                error => {
+                    let (line, column) = self.line_column(pos);
+                    ParserError::At {
+                        line,
+                        column,
+                        error: Box::new(error),
+                    }
+                }
+            })
+            .collect();
+        (node, errors)
+    }
+
+    fn parse_top_level_node(&mut self) -> Result<Node> {
         self.eat_whitespace()?;
-        if self.pos < self.input.len() {
-            return Err(ParserError::StrayData(self.remaining_str()).into());
+        if self.pos >= self.input.len() {
+            return Err(ParserError::EmptyInput.into());
         }
+        let leading_comments = std::mem::take(&mut self.pending_comments);
+        let mut node = self.parse_node()?;
+        node.leading_comments = leading_comments;
         Ok(node)
     }
 
+    fn assert_eof(&mut self) -> Result<()> {
+        self.eat_whitespace()?;
+        if self.pos < self.input.len() {
+            let (line, column) = self.line_column(self.pos);
+            return Err(ParserError::StrayData {
+                data: self.remaining_str(),
+                line,
+                column,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn line_column(&self, pos: usize) -> (usize, usize) {
+        line_column(&self.input, pos)
+    }
+
+    /// Reports a parsed top-level node as stray data, located at its start.
+    fn stray_node(&self, node: &Node) -> ParserError {
+        let start = node.span.map(|(start, _)| start).unwrap_or(0);
+        let pos = self
+            .byte_offsets
+            .binary_search(&start)
+            .unwrap_or_else(|pos| pos);
+        let (line, column) = self.line_column(pos);
+        ParserError::StrayData {
+            data: format!("{node}"),
+            line,
+            column,
+        }
+    }
+
+    fn record_error(&mut self, err: SWLError) {
+        let err = match err {
+            SWLError::ParserError(err) => err,
+            // The parser only returns parser errors.
+            _ => ParserError::UnexpectedEOF,
+        };
+        // Once EOF is hit, every unclosed node would report it again.
+        let is_repeated_eof = matches!(err, ParserError::UnexpectedEOF)
+            && matches!(self.errors.last(), Some((_, ParserError::UnexpectedEOF)));
+        if !is_repeated_eof {
+            self.errors.push((self.pos, err));
+        }
+    }
+
+    /// Skips ahead after an error inside a node at `depth`: past the end of all nodes that were opened since, up to the next sibling node or the end of the parent node.
+    fn synchronize(&mut self, depth: usize) {
+        let mut level = self.depth - depth;
+        self.depth = depth;
+        while let Some(c) = self.peek() {
+            if self.is_next("(;") {
+                drop(self.eat_comment());
+                continue;
+            }
+            if self.is_next(";;") {
+                drop(self.eat_line());
+                continue;
+            }
+            match c {
+                '(' | ')' if level == 0 => return,
+                '(' => level += 1,
+                ')' => {
+                    level -= 1;
+                    if level == 0 {
+                        self.pos += 1;
+                        return;
+                    }
+                }
+                '"' => {
+                    drop(self.eat_string());
+                    continue;
+                }
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Parses a sequence of top-level nodes until EOF.
+    pub fn parse_multiple(&mut self) -> Result<Vec<Node>> {
+        let mut nodes: Vec<Node> = vec![];
+        loop {
+            self.eat_whitespace()?;
+            if self.pos >= self.input.len() {
+                if let Some(node) = nodes.last_mut() {
+                    node.trailing_comments = std::mem::take(&mut self.pending_comments);
+                }
+                return Ok(nodes);
+            }
+            // Comments between two nodes lead the second one.
+            let leading_comments = std::mem::take(&mut self.pending_comments);
+            let mut node = self.parse_node()?;
+            node.leading_comments = leading_comments;
+            nodes.push(node);
+        }
+    }
+
+    fn byte_offset(&self) -> usize {
+        let pos = self.pos.min(self.input.len());
+        self.byte_offsets[pos]
+    }
+
     fn remaining_str(&self) -> String {
         if self.pos > self.input.len() {
             return "".to_string();
@@ -49,17 +293,49 @@ impl Parser {
 
     fn parse_node(&mut self) -> Result<Node> {
         self.eat_whitespace()?;
+        let start = self.byte_offset();
         self.assert_next("(")?;
         self.depth += 1;
         self.eat_whitespace()?;
         let ident = self.parse_identifier()?;
+        if ident.is_empty() {
+            let (line, column) = self.line_column(self.pos);
+            return Err(ParserError::UnexpectedToken {
+                expected: "identifier".to_string(),
+                got: self.must_peek()?.to_string(),
+                line,
+                column,
+            }
+            .into());
+        }
         self.eat_whitespace()?;
         let mut items: Vec<Item> = vec![];
-        while self.must_peek()? != ')' {
-            items.push(self.parse_item()?);
+        loop {
+            match self.peek() {
+                Some(')') => break,
+                None if self.recovering => {
+                    self.record_error(ParserError::UnexpectedEOF.into());
+                    break;
+                }
+                _ => {}
+            }
+            items.append(&mut self.pending_comments);
+            let depth = self.depth;
+            match self.parse_item() {
+                Ok(item) => items.push(item),
+                Err(err) if self.recovering => {
+                    self.record_error(err);
+                    self.synchronize(depth);
+                }
+                Err(err) => return Err(err),
+            }
             self.eat_whitespace()?;
         }
-        self.assert_next(")")?;
+        items.append(&mut self.pending_comments);
+        if !(self.recovering && self.peek().is_none()) {
+            self.assert_next(")")?;
+        }
+        let end = self.byte_offset();
         self.depth -= 1;
         self.eat_whitespace()?;
 
@@ -67,6 +343,9 @@ impl Parser {
             name: ident,
             depth: self.depth,
             items,
+            span: Some((start, end)),
+            leading_comments: vec![],
+            trailing_comments: vec![],
         })
     }
 
@@ -152,9 +431,12 @@ impl Parser {
         if !self.is_next(expected) {
             let s = self.remaining_str();
             let got = &s[0..s.len().min(expected.len())];
+            let (line, column) = self.line_column(self.pos);
             return Err(ParserError::UnexpectedToken {
                 expected: expected.to_string(),
                 got: got.to_string(),
+                line,
+                column,
             }
             .into());
         }
@@ -178,6 +460,10 @@ impl Parser {
 
     fn parse_identifier(&mut self) -> Result<String> {
         let start = self.pos;
+        // Annotations like `(@custom ...)` have names starting with `@`.
+        if self.is_next("@") {
+            self.pos += 1;
+        }
         while self.must_peek()?.is_alphanumeric()
             || ADDITIONAL_ALLOWED_CHARS.contains(self.must_peek()?)
         {
@@ -194,11 +480,21 @@ impl Parser {
                 None => return Ok(()),
             };
 
+            let start = self.pos;
             if self.is_next(";;") {
                 self.assert_next(";;")?;
                 drop(self.eat_line());
+                if self.keep_comments {
+                    let comment: String = self.input[start..self.pos].iter().collect();
+                    self.pending_comments
+                        .push(Item::LineComment(comment.trim_end().to_string()));
+                }
             } else if self.is_next("(;") {
                 drop(self.eat_comment());
+                if self.keep_comments {
+                    let comment = self.input[start..self.pos].iter().collect();
+                    self.pending_comments.push(Item::BlockComment(comment));
+                }
             } else if char.is_whitespace() {
                 self.pos += 1;
             } else {
@@ -207,14 +503,20 @@ impl Parser {
         }
     }
 
+    /// Eats everything up to and including the next line break. A line that ends at EOF is fine, too.
     fn eat_line(&mut self) -> Result<()> {
-        while self.must_next()? != '\n' {}
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '\n' {
+                break;
+            }
+        }
         Ok(())
     }
     fn eat_comment(&mut self) -> Result<()> {
         self.assert_next("(;")?;
         while !self.is_next(";)") {
-            self.pos += 1
+            self.must_next()?;
         }
         self.assert_next(";)")?;
         Ok(())
@@ -341,6 +643,78 @@ mod test {
         parse_and_compare(input, expected);
     }
 
+    #[test]
+    fn kept_comments() {
+        let mut parser = Parser::new(";; Outside\n(module ;; Line\n (func (; block ;) (nop)))");
+        parser.keep_comments = true;
+        let module = parser.parse().unwrap();
+        assert_eq!(
+            format!("{module}"),
+            ";; Outside\n(module ;; Line\n (func (; block ;) (nop)))"
+        );
+        assert_eq!(
+            module.leading_comments,
+            [Item::LineComment(";; Outside".to_string())]
+        );
+        let func = module.immediate_node_iter().next().unwrap();
+        assert!(func.items[0].is_comment());
+        assert_eq!(func.significant_items().count(), 1);
+    }
+
+    #[test]
+    fn line_comment_at_eof() {
+        let module = Parser::new("(module) ;; trailing comment").parse().unwrap();
+        assert_eq!(format!("{module}"), "(module)");
+
+        let mut parser = Parser::new("(module ;; Line\n) ;; trailing comment");
+        parser.keep_comments = true;
+        let module = parser.parse().unwrap();
+        assert_eq!(
+            format!("{module}"),
+            "(module ;; Line\n) ;; trailing comment\n"
+        );
+    }
+
+    #[test]
+    fn recovering() {
+        let input = "(module\n  (func $a ( \"x\") (nop))\n  (func $b (nop))\n  (func $c (nop) ())\n  (func $d (nop)";
+        let (node, errors) = Parser::new(input).parse_recovering();
+        assert_eq!(
+            format!("{}", node.unwrap()),
+            "(module (func $a (nop)) (func $b (nop)) (func $c (nop)) (func $d (nop)))"
+        );
+        let errors: Vec<String> = errors.iter().map(|err| format!("{err}")).collect();
+        assert_eq!(
+            errors,
+            [
+                "Unexpected token at 2:14: expected identifier, got \"",
+                "Unexpected token at 4:19: expected identifier, got )",
+                "5:17: Unexpected EOF",
+            ]
+        );
+
+        let (node, errors) = Parser::new("(module (func)").parse_recovering();
+        assert!(node.is_some());
+        assert_eq!(errors.len(), 1);
+
+        let (node, errors) = Parser::new("(module (func))").parse_recovering();
+        assert!(node.is_some());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn spans() {
+        let input = "(module ;; ünïcode\n  (func $f (i32.const 1)))";
+        let module = Parser::new(input).parse().unwrap();
+        assert_eq!(module.span, Some((0, input.len())));
+
+        let func = module.immediate_node_iter().next().unwrap();
+        let (start, end) = func.span.unwrap();
+        assert_eq!(&input[start..end], "(func $f (i32.const 1))");
+        let (start, end) = func.immediate_node_iter().next().unwrap().span.unwrap();
+        assert_eq!(&input[start..end], "(i32.const 1)");
+    }
+
     #[test]
     fn depth_test() {
         let input = r#"
@@ -359,6 +733,39 @@ mod test {
         }
     }
 
+    #[test]
+    fn empty_input() {
+        for input in ["", "  \n\t  ", "  ;; just a comment\n"] {
+            match Parser::new(input).parse() {
+                Err(SWLError::ParserError(ParserError::EmptyInput)) => {}
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_multiple() {
+        let input = r#"
+            ;; Fragment
+            (func $a (param i32))
+            (func $b (call $a (i32.const 0)))
+        "#;
+        let nodes = Parser::new(input).parse_multiple().unwrap();
+        let nodes: Vec<String> = nodes.iter().map(|node| format!("{node}")).collect();
+        assert_eq!(
+            nodes,
+            ["(func $a (param i32))", "(func $b (call $a (i32.const 0)))"]
+        );
+    }
+
+    #[test]
+    fn parse_multiple_single_module() {
+        let nodes = Parser::new("(module (func))").parse_multiple().unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].depth, 0);
+        assert_eq!(&format!("{}", nodes[0]), "(module (func))");
+    }
+
     #[test]
     fn stray_data() {
         let input = r#"
@@ -368,8 +775,35 @@ mod test {
 
         let mut parser = Parser::new(input);
         match parser.parse() {
-            Err(SWLError::ParserError(ParserError::StrayData(_))) => {}
+            Err(SWLError::ParserError(ParserError::StrayData { line, column, .. })) => {
+                assert_eq!((line, column), (3, 17));
+            }
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn parse_script() {
+        let modules = super::parse_script(
+            r#"
+                (module
+                    (func (export "f") (result i32) (i32.const 2)))
+                (assert_return (invoke "f") (i32.const 2))
+                (module binary "\00asm\01\00\00\00")
+                (invoke "f")
+            "#,
+        )
+        .unwrap();
+        let modules: Vec<String> = modules.iter().map(|m| format!("{m}")).collect();
+        assert_eq!(
+            modules,
+            [r#"(module (func (export "f") (result i32) (i32.const 2)))"#]
+        );
+
+        let err = super::parse_script("(module)\n  (func $f)").unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Parsing failed: Stray data at 2:3: (func $f)"
+        );
+    }
 }
@@ -1,6 +1,7 @@
 use crate::error::Result;
 
-use crate::parser::ParserError;
+use crate::parser::{line_column, ParserError};
+use crate::utils::{canonical_wat_string, unescape_wat_string};
 
 #[derive(Clone, Debug)]
 enum Item {
@@ -71,6 +72,9 @@ impl Parser {
         let mut items = vec![];
         while !self.is_eof() && !self.is_next(")") {
             self.eat_whitespace()?;
+            if self.is_eof() {
+                break;
+            }
             if self.is_next("(;") {
                 items.push(Item::BlockComment(self.parse_blockcomment()?));
             } else if self.is_next("(") {
@@ -145,11 +149,14 @@ impl Parser {
     fn parse_linecomment(&mut self) -> Result<String> {
         self.assert_next(";;")?;
         let start = self.pos;
-        while !self.is_next("\n") {
+        while self.pos < self.input.len() && !self.is_next("\n") {
             self.pos += 1;
         }
-        self.assert_next("\n")?;
-        let end = self.pos - 1;
+        let end = self.pos;
+        // A comment on the last line doesn’t need a line break.
+        if self.pos < self.input.len() {
+            self.assert_next("\n")?;
+        }
         Ok(self.input[start..end].iter().collect())
     }
 
@@ -178,9 +185,12 @@ impl Parser {
         if !self.is_next(expected) {
             let s = self.remaining_str();
             let got = &s[0..s.len().min(expected.len())];
+            let (line, column) = line_column(&self.input, self.pos);
             return Err(ParserError::UnexpectedToken {
                 expected: expected.to_string(),
                 got: got.to_string(),
+                line,
+                column,
             }
             .into());
         }
@@ -215,11 +225,113 @@ pub fn pretty_print(code: &str) -> Result<String> {
     PrettyPrinter::pretty_print(code)
 }
 
+/// Re-escapes all string literals in canonical form.
+fn normalize_string_literals(items: &mut [Item]) -> Result<()> {
+    for item in items {
+        match item {
+            Item::StringLiteral(s) => *s = canonical_wat_string(&unescape_wat_string(s)?),
+            Item::Parens(items) => normalize_string_literals(items)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Splits the contents of a string literal into chunks that each represent at most `max_bytes` bytes. Escape sequences are never split.
+fn split_string_literal(s: &str, max_bytes: usize) -> Vec<String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+    let mut chunk_bytes = 0;
+    let mut pos = 0;
+    while pos < chars.len() {
+        let start = pos;
+        let num_bytes = if chars[pos] != '\\' {
+            pos += 1;
+            chars[start].len_utf8()
+        } else if chars.get(pos + 1) == Some(&'u') {
+            pos = chars[start..]
+                .iter()
+                .position(|&c| c == '}')
+                .map(|idx| start + idx + 1)
+                .unwrap_or(chars.len());
+            let hex: String = chars
+                .get(start + 3..pos - 1)
+                .unwrap_or(&[])
+                .iter()
+                .collect();
+            u32::from_str_radix(&hex.replace('_', ""), 16)
+                .ok()
+                .and_then(char::from_u32)
+                .map(|c| c.len_utf8())
+                .unwrap_or(1)
+        } else if chars
+            .get(pos + 1)
+            .map(|c| c.is_ascii_hexdigit())
+            .unwrap_or(false)
+        {
+            pos += 3;
+            1
+        } else {
+            pos += 2;
+            1
+        };
+        pos = pos.min(chars.len());
+        if chunk_bytes > 0 && chunk_bytes + num_bytes > max_bytes {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_bytes = 0;
+        }
+        chunk.extend(&chars[start..pos]);
+        chunk_bytes += num_bytes;
+    }
+    if !chunk.is_empty() || chunks.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// How the text of `;;` line comments is normalized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CommentStyle {
+    /// Keep the comment text exactly as written, including `;;;` headers.
+    Preserve,
+    /// Separate the comment text from `;;` with exactly one space.
+    #[default]
+    SingleSpace,
+    /// Remove all whitespace between `;;` and the comment text.
+    None,
+}
+
+impl std::str::FromStr for CommentStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(CommentStyle::Preserve),
+            "single-space" => Ok(CommentStyle::SingleSpace),
+            "none" => Ok(CommentStyle::None),
+            other => Err(format!("Unknown comment style {other}")),
+        }
+    }
+}
+
 pub struct PrettyPrinter {
     buffer: String,
     newline_emitted: usize,
     inside_component: bool,
     inside_module: bool,
+    inside_data: bool,
+    /// If set, string literals inside `data` segments are split into multiple literals of at most this many bytes each.
+    pub data_wrap: Option<usize>,
+    pub comment_style: CommentStyle,
+    /// If set, string literals are re-escaped with `utils::canonical_wat_string`, so byte-equal strings print identically.
+    pub normalize_strings: bool,
+}
+
+impl Default for PrettyPrinter {
+    fn default() -> Self {
+        PrettyPrinter::new()
+    }
 }
 
 impl PrettyPrinter {
@@ -229,6 +341,10 @@ impl PrettyPrinter {
             newline_emitted: 0,
             inside_component: false,
             inside_module: false,
+            inside_data: false,
+            data_wrap: None,
+            comment_style: CommentStyle::default(),
+            normalize_strings: false,
         }
     }
 
@@ -237,15 +353,21 @@ impl PrettyPrinter {
     }
 
     pub fn pretty_print(code: &str) -> Result<String> {
-        let items = Parser::new(code).parse()?;
-        let mut printer = PrettyPrinter::new();
+        PrettyPrinter::new().print(code)
+    }
+
+    pub fn print(&mut self, code: &str) -> Result<String> {
+        let mut items = Parser::new(code).parse()?;
+        if self.normalize_strings {
+            normalize_string_literals(&mut items)?;
+        }
         for (idx, item) in items.iter().enumerate() {
-            printer.pretty_print_item(item, 0);
-            if idx < items.len() - 1 {
-                printer.buffer += "\n";
+            if idx > 0 {
+                self.buffer += "\n";
             }
+            self.pretty_print_item(item, 0);
         }
-        Ok(printer.finalize())
+        Ok(self.finalize())
     }
 
     fn emit<T: AsRef<str>>(&mut self, v: T) {
@@ -368,6 +490,37 @@ impl PrettyPrinter {
         self.emit(")");
     }
 
+    fn is_if_first_line_item(item: &Item) -> bool {
+        match item {
+            Item::Ident(lit) => lit.starts_with('$'),
+            Item::Parens(items) => ["result", "param", "type"]
+                .into_iter()
+                .any(|name| PrettyPrinter::items_start_with_ident(items, name)),
+            _ => false,
+        }
+    }
+
+    /// Prints a folded `if`. The label and block type stay on the first line, while the condition and the `then` and `else` arms are aligned underneath.
+    fn pretty_print_if(&mut self, items: &[Item], level: usize) {
+        self.emit("(");
+        self.emit(items[0].as_literal().unwrap());
+        let mut it = items.iter().skip(1).peekable();
+
+        while PrettyPrinter::item_matches_predicate(it.peek(), PrettyPrinter::is_if_first_line_item)
+        {
+            self.emit(" ");
+            self.pretty_print_item_as_single_line(it.next().unwrap(), level)
+        }
+
+        for item in it {
+            self.emit_newlines(1);
+            self.emit(INDENT.repeat(level + 1).as_str());
+            self.pretty_print_item(item, level + 1);
+        }
+        self.undo_newlines();
+        self.emit(")");
+    }
+
     fn pretty_print_component(&mut self, items: &[Item], level: usize) {
         assert!(PrettyPrinter::items_start_with_ident(items, "component"));
         self.emit("(");
@@ -401,18 +554,36 @@ impl PrettyPrinter {
             Item::LineComment(comment) => self.pretty_print_line_comment(comment, level),
             Item::Ident(lit) => self.pretty_print_literal(lit, level),
             Item::Parens(items) => self.pretty_print_parens(items.as_slice(), level),
-            Item::StringLiteral(_) => self.pretty_print_item_as_single_line(item, level),
+            Item::StringLiteral(str) => match self.data_wrap {
+                Some(max_bytes) if self.inside_data => {
+                    let chunks = split_string_literal(str, max_bytes);
+                    for (idx, chunk) in chunks.iter().enumerate() {
+                        if idx > 0 {
+                            self.emit_newlines(1);
+                            self.emit(INDENT.repeat(level));
+                        }
+                        self.emit(format!(r#""{chunk}""#));
+                    }
+                }
+                _ => self.pretty_print_item_as_single_line(item, level),
+            },
         }
     }
 
     fn pretty_print_line_comment(&mut self, mut comment: &str, _level: usize) {
         self.emit(";;");
-        if comment.starts_with(char::is_whitespace) {
-            comment = &comment[1..]
-        }
-        if !comment.trim().is_empty() {
-            self.emit(" ");
-            self.emit(comment);
+        match self.comment_style {
+            CommentStyle::Preserve => self.emit(comment.trim_end()),
+            CommentStyle::SingleSpace => {
+                if comment.starts_with(char::is_whitespace) {
+                    comment = &comment[1..]
+                }
+                if !comment.trim().is_empty() {
+                    self.emit(" ");
+                    self.emit(comment);
+                }
+            }
+            CommentStyle::None => self.emit(comment.trim()),
         }
     }
 
@@ -495,6 +666,8 @@ impl PrettyPrinter {
             self.pretty_print_parens_as_single_line(items, level);
         } else if PrettyPrinter::items_is_type(items, "func") {
             self.pretty_print_func(items, level);
+        } else if PrettyPrinter::items_start_with_ident(items, "if") {
+            self.pretty_print_if(items, level);
         } else if PrettyPrinter::items_start_with_ident(items, "component") {
             let previous = std::mem::replace(&mut self.inside_component, true);
             self.pretty_print_component(items, level);
@@ -503,6 +676,10 @@ impl PrettyPrinter {
             let previous = std::mem::replace(&mut self.inside_module, true);
             self.pretty_print_generic_parens(items, level);
             self.inside_module = previous;
+        } else if PrettyPrinter::items_start_with_ident(items, "data") {
+            let previous = std::mem::replace(&mut self.inside_data, true);
+            self.pretty_print_generic_parens(items, level);
+            self.inside_data = previous;
         } else {
             self.pretty_print_generic_parens(items, level);
         }
@@ -899,6 +1076,28 @@ mod test {
         assert_eq!(pretty_print(input).unwrap(), expected);
     }
 
+    #[test]
+    fn folded_if_then_else() {
+        let input = r#"
+            (if $check (result i32) (i32.eqz (local.get $x))
+                (then (i32.const 1))
+                (else (call $f) (i32.const 2)))
+        "#;
+        let expected = unindent(
+            "
+                (if $check (result i32)
+                \t(i32.eqz
+                \t\t(local.get $x))
+                \t(then
+                \t\t(i32.const 1))
+                \t(else
+                \t\t(call $f)
+                \t\t(i32.const 2)))
+            ",
+        );
+        assert_eq!(pretty_print(input).unwrap(), expected);
+    }
+
     #[test]
     fn import() {
         let input = r#"
@@ -917,6 +1116,16 @@ mod test {
         assert_eq!(pretty_print(input).unwrap(), expected);
     }
 
+    #[test]
+    fn empty_input() {
+        assert_eq!(pretty_print("").unwrap(), "");
+    }
+
+    #[test]
+    fn whitespace_only_input() {
+        assert_eq!(pretty_print(" \n\t\n  ").unwrap(), "");
+    }
+
     #[test]
     fn multiple_singleline_comments() {
         let input = r#"
@@ -1029,6 +1238,114 @@ mod test {
         assert_eq!(pretty_print(input).unwrap(), expected);
     }
 
+    #[test]
+    fn comment_style_single_space() {
+        let input = "(module\n;;comment 1\n;;   comment 2\n(func))";
+        let expected = unindent(
+            "
+                (module
+                \t;; comment 1
+                \t;;   comment 2
+                \t(func))
+            ",
+        );
+        assert_eq!(pretty_print(input).unwrap(), expected);
+    }
+
+    #[test]
+    fn annotations() {
+        let got = PrettyPrinter::new()
+            .print(r#"(module (@custom "note" (after func) "\00") (func $a (@name "a")))"#)
+            .unwrap();
+        let expected = unindent(
+            r#"
+                (module
+                	(@custom "note"
+                		(after func)
+                		"\00")
+
+                	(func $a
+                		(@name "a")))
+            "#,
+        );
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn line_comment_at_eof() {
+        let got = PrettyPrinter::new()
+            .print("(module (func)) ;; trailing comment")
+            .unwrap();
+        assert_eq!(got, "(module\n\t(func))\n;; trailing comment");
+    }
+
+    #[test]
+    fn comment_style_preserve() {
+        let input = "(module\n;;; Section\n;;comment\n(func))";
+        let expected = unindent(
+            "
+                (module
+                \t;;; Section
+                \t;;comment
+                \t(func))
+            ",
+        );
+        let mut printer = PrettyPrinter::new();
+        printer.comment_style = CommentStyle::Preserve;
+        assert_eq!(printer.print(input).unwrap(), expected);
+    }
+
+    #[test]
+    fn data_wrap() {
+        let input = r#"
+            (module
+                (data (i32.const 0) "\00\01\02\03\04\05\06\07\08\09abc\n\u{e9}"))
+        "#;
+        let expected = unindent(
+            r#"
+                (module
+                	(data
+                		(i32.const 0)
+                		"\00\01\02\03"
+                		"\04\05\06\07"
+                		"\08\09ab"
+                		"c\n\u{e9}"))
+            "#,
+        );
+        let mut printer = PrettyPrinter::new();
+        printer.data_wrap = Some(4);
+        let got = printer.print(input).unwrap();
+        assert_eq!(got, expected);
+
+        let original = crate::parser::Parser::new(input).parse().unwrap();
+        let wrapped = crate::parser::Parser::new(&got).parse().unwrap();
+        let data_bytes = |module: &crate::ast::Node| -> Vec<u8> {
+            module.items[0]
+                .as_node()
+                .unwrap()
+                .immediate_attribute_iter()
+                .flat_map(|s| crate::utils::unescape_wat_string(&s[1..s.len() - 1]).unwrap())
+                .collect()
+        };
+        assert_eq!(data_bytes(&original), data_bytes(&wrapped));
+    }
+
+    #[test]
+    fn normalize_strings() {
+        let mut printer = PrettyPrinter::new();
+        printer.normalize_strings = true;
+        let a = printer
+            .print(r#"(data (i32.const 0) "a\62\n\u{e9}\"\00")"#)
+            .unwrap();
+        let mut printer = PrettyPrinter::new();
+        printer.normalize_strings = true;
+        let b = printer
+            .print(r#"(data (i32.const 0) "\61b\0a\c3\a9\22\00")"#)
+            .unwrap();
+        assert_eq!(a, b);
+        assert!(a.contains(r#""ab\0a\c3\a9\22\00""#));
+    }
+
     #[test]
     fn component() {
         let input = r#"
@@ -1098,4 +1415,15 @@ mod test {
         );
         assert_eq!(pretty_print(input).unwrap(), expected);
     }
+
+    #[test]
+    fn roundtrip() {
+        let mut rng = crate::ast::arbitrary::Rng::new(0x5eed);
+        for _ in 0..500 {
+            let node = crate::ast::arbitrary::node(&mut rng, 0, 4);
+            let printed = pretty_print(&format!("{node}")).unwrap();
+            let parsed = crate::parser::Parser::new(&printed).parse().unwrap();
+            assert_eq!(parsed, node, "{printed}");
+        }
+    }
 }
@@ -0,0 +1,93 @@
+use crate::ast::{Item, Node};
+use crate::error::{Result, SWLError};
+use crate::utils;
+
+/// Returns the file name used for the nodes of a section, e.g. `funcs.wat` for `func`.
+fn section_file_name(name: &str) -> String {
+    let plural = match name {
+        "type" => "types",
+        "import" => "imports",
+        "func" => "funcs",
+        "table" => "tables",
+        "memory" => "memories",
+        "global" => "globals",
+        "export" => "exports",
+        "elem" => "elems",
+        name => name,
+    };
+    format!("{plural}.wat")
+}
+
+/// Splits the top-level nodes of a module into one module per section, in the order in which the sections first appear.
+pub fn split_module(module: &Node) -> Result<Vec<(String, Node)>> {
+    if !utils::is_module(module) {
+        return Err(SWLError::Simple("Only modules can be split".to_string()));
+    }
+    let mut sections: Vec<(String, Node)> = vec![];
+    for node in module.immediate_node_iter() {
+        let file_name = section_file_name(&node.name);
+        let section = match sections.iter_mut().find(|(name, _)| *name == file_name) {
+            Some((_, section)) => section,
+            None => {
+                sections.push((
+                    file_name,
+                    Node {
+                        name: "module".to_string(),
+                        depth: module.depth,
+                        items: vec![],
+                        span: None,
+                        leading_comments: vec![],
+                        trailing_comments: vec![],
+                    },
+                ));
+                &mut sections.last_mut().unwrap().1
+            }
+        };
+        section.items.push(Item::Node(node.clone()));
+    }
+    Ok(sections)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn split_by_section() {
+        let module = Parser::new(
+            r#"
+                (module
+                    (type $t (func))
+                    (func $a (nop))
+                    (data (i32.const 0) "a")
+                    (func $b (type $t))
+                    (data (i32.const 1) "b"))
+            "#,
+        )
+        .parse()
+        .unwrap();
+        let sections: Vec<(String, String)> = split_module(&module)
+            .unwrap()
+            .into_iter()
+            .map(|(name, module)| (name, format!("{module}")))
+            .collect();
+        assert_eq!(
+            sections,
+            [
+                (
+                    "types.wat".to_string(),
+                    "(module (type $t (func)))".to_string()
+                ),
+                (
+                    "funcs.wat".to_string(),
+                    "(module (func $a (nop)) (func $b (type $t)))".to_string()
+                ),
+                (
+                    "data.wat".to_string(),
+                    r#"(module (data (i32.const 0) "a") (data (i32.const 1) "b"))"#.to_string()
+                ),
+            ]
+        );
+    }
+}
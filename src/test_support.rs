@@ -0,0 +1,87 @@
+use crate::ast::Node;
+use crate::pretty::PrettyPrinter;
+
+/// Pretty-prints WAT code for diffing, falling back to the input if it can’t be parsed.
+fn pretty_lines(code: &str) -> Vec<String> {
+    PrettyPrinter::new()
+        .print(code)
+        .unwrap_or_else(|_| code.to_string())
+        .lines()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Returns a line-based diff of the pretty-printed forms of two modules. Lines only in `expected` are prefixed with `-`, lines only in `got` with `+`.
+pub fn module_diff(expected: &str, got: &str) -> String {
+    let expected = pretty_lines(expected);
+    let got = pretty_lines(got);
+
+    // Longest common subsequence of lines, computed from the back.
+    let mut lcs = vec![vec![0usize; got.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..got.len()).rev() {
+            lcs[i][j] = if expected[i] == got[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < got.len() {
+        if i < expected.len() && j < got.len() && expected[i] == got[j] {
+            out.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j < got.len() && (i == expected.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            out.push(format!("+ {}", got[j]));
+            j += 1;
+        } else {
+            out.push(format!("- {}", expected[i]));
+            i += 1;
+        }
+    }
+    out.join("\n")
+}
+
+/// Asserts that a module serializes to `expected`, ignoring surrounding whitespace. On mismatch, panics with a diff of both sides.
+#[track_caller]
+pub fn assert_module_eq(got: &Node, expected: &str) {
+    let got = format!("{got}");
+    let expected = expected.trim();
+    if got != expected {
+        panic!(
+            "Modules differ (- expected, + got):\n{}\n\nexpected: {expected}\n     got: {got}",
+            module_diff(expected, &got)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn readable_diff() {
+        let diff = module_diff(
+            "(module (func $a (nop)) (func $b))",
+            "(module (func $a (drop (i32.const 1))) (func $b))",
+        );
+        let changed: Vec<&str> = diff
+            .lines()
+            .filter(|line| line.starts_with('-') || line.starts_with('+'))
+            .collect();
+        assert!(changed
+            .iter()
+            .any(|line| line.starts_with('-') && line.contains("nop")));
+        assert!(changed
+            .iter()
+            .any(|line| line.starts_with('+') && line.contains("i32.const 1")));
+        assert!(diff
+            .lines()
+            .any(|line| line.starts_with("  ") && line.contains("$b")));
+        assert_eq!(module_diff("(module)", "(module)"), "  (module)");
+    }
+}
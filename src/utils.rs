@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use crate::ast::{Item, Node};
 use crate::error::{Result, SWLError};
 use crate::parser::ParserError;
@@ -7,6 +9,32 @@ pub fn is_module(a: &Node) -> bool {
     a.depth == 0 && a.name == "module"
 }
 
+/// Moves all nodes of the `child` module into `parent`, renumbering their depths to sit directly below `parent`. Attributes of `child`, like its id, are dropped.
+pub fn merge_module_into(parent: &mut Node, child: Node) -> Result<()> {
+    if child.name != "module" {
+        return Err(SWLError::Simple(format!(
+            "Can only merge modules, got {}",
+            child.name
+        )));
+    }
+    for item in child.items {
+        let mut node = match item {
+            Item::Node(node) => node,
+            item if item.is_comment() => {
+                parent.items.push(item);
+                continue;
+            }
+            _ => continue,
+        };
+        let offset = parent.depth + 1;
+        let base = node.depth;
+        node.node_iter_mut()
+            .for_each(|node| node.depth = node.depth - base + offset);
+        parent.items.push(Item::Node(node));
+    }
+    Ok(())
+}
+
 /// Returns true if a string represents a string literal.
 pub fn is_string_literal(s: &str) -> bool {
     if s.len() <= 2 {
@@ -15,28 +43,9 @@ pub fn is_string_literal(s: &str) -> bool {
     s.starts_with('\"') && s.chars().nth(s.len() - 1).unwrap() == '"'
 }
 
-/// Returns the number of bytes a string needs in memory. Handles single-letter escape sequences and dual-digit hexadecimal escape sequences.
+/// Returns the number of bytes a string needs in memory. Handles all escape sequences `unescape_wat_string` handles, including `\u{...}`, and counts characters by their UTF-8 length.
 pub fn interpreted_string_length(s: &str) -> Result<usize> {
-    let mut it = s.chars();
-    let mut count = 0;
-    loop {
-        let char = match it.next() {
-            None => break,
-            Some(c) => c,
-        };
-        count += 1;
-        if char != '\\' {
-            continue;
-        }
-        let char = it
-            .next()
-            .ok_or::<SWLError>(ParserError::InvalidEscapeSequence.into())?;
-        if char.is_ascii_digit() {
-            it.next()
-                .ok_or::<SWLError>(ParserError::InvalidEscapeSequence.into())?;
-        }
-    }
-    Ok(count)
+    Ok(unescape_wat_string(s)?.len())
 }
 
 /// Finds the ID attribute of a node. Named IDs (like “$x”) get preference over numeric IDs.
@@ -64,41 +73,344 @@ pub fn parse_number_literal<T: AsRef<str>>(
 ) -> std::result::Result<isize, std::num::ParseIntError> {
     if v.as_ref().starts_with("0x") {
         isize::from_str_radix(&v.as_ref()[2..], 16)
-    } else if v.as_ref().starts_with('0') {
+    } else if v.as_ref().starts_with('0') && v.as_ref().len() > 1 {
         isize::from_str_radix(&v.as_ref()[1..], 8)
     } else {
         v.as_ref().parse::<isize>()
     }
 }
 
+/// Parses a WAT float literal: decimal like `1.5e3`, hexadecimal like `0x1.8p3`, `inf` and `nan` with an optional payload like `nan:0x200000`, each with an optional sign. The payload is applied to an `f64` NaN.
+pub fn parse_float_literal(v: &str) -> Option<f64> {
+    let v = v.replace('_', "");
+    let (negative, v) = match v.strip_prefix('-') {
+        Some(v) => (true, v),
+        None => (false, v.strip_prefix('+').unwrap_or(&v)),
+    };
+    let magnitude = if v == "inf" {
+        f64::INFINITY
+    } else if v == "nan" {
+        f64::NAN
+    } else if let Some(payload) = v.strip_prefix("nan:0x") {
+        let payload = u64::from_str_radix(payload, 16).ok()?;
+        if payload == 0 || payload >= 1 << 52 {
+            return None;
+        }
+        f64::from_bits(0x7ff0_0000_0000_0000 | payload)
+    } else if let Some(hex) = v.strip_prefix("0x") {
+        let (mantissa, exponent) = match hex.split_once(['p', 'P']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().ok()?),
+            None => (hex, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if int.is_empty() && frac.is_empty() {
+            return None;
+        }
+        let mut value = 0f64;
+        for c in int.chars().chain(frac.chars()) {
+            value = value * 16.0 + c.to_digit(16)? as f64;
+        }
+        value * 2f64.powi(exponent - 4 * frac.len() as i32)
+    } else if v.starts_with(|c: char| c.is_ascii_digit()) {
+        v.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+/// Escapes arbitrary bytes so they can be placed inside a WAT string literal. Every byte is emitted as a dual-digit hexadecimal escape sequence. The surrounding quotes are not included.
+pub fn escape_wat_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|v| format!("\\{v:02x}")).collect()
+}
+
+/// Escapes bytes in a canonical form: printable ASCII is kept as is, every other byte as well as `"` and `\` is emitted as a dual-digit hexadecimal escape sequence. Byte-equal strings therefore always escape to the same text. The surrounding quotes are not included.
+pub fn canonical_wat_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&v| match v {
+            0x20..=0x7e if v != b'"' && v != b'\\' => (v as char).to_string(),
+            _ => format!("\\{v:02x}"),
+        })
+        .collect()
+}
+
+/// Decodes standard base64 with optional padding. Whitespace is ignored, so line-wrapped files work. Returns `None` for any other character or a truncated input.
+pub fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let mut sextets = vec![];
+    for c in s.chars().filter(|c| !c.is_whitespace()) {
+        let v = match c {
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' => 62,
+            '/' => 63,
+            '=' => break,
+            _ => return None,
+        };
+        sextets.push(v);
+    }
+    if sextets.len() % 4 == 1 {
+        return None;
+    }
+    let mut bytes = vec![];
+    for chunk in sextets.chunks(4) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (idx, v)| bits | v << (18 - 6 * idx));
+        bytes.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(bytes)
+}
+
+/// Returns the bytes a WAT string literal represents. Expects the contents of the literal without the surrounding quotes.
+pub fn unescape_wat_string(s: &str) -> Result<Vec<u8>> {
+    let mut it = s.chars().peekable();
+    let mut bytes = vec![];
+    while let Some(char) = it.next() {
+        if char != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(char.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        let char = it
+            .next()
+            .ok_or::<SWLError>(ParserError::InvalidEscapeSequence.into())?;
+        match char {
+            't' => bytes.push(b'\t'),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            '"' => bytes.push(b'"'),
+            '\'' => bytes.push(b'\''),
+            '\\' => bytes.push(b'\\'),
+            'u' => {
+                if it.next() != Some('{') {
+                    return Err(ParserError::InvalidEscapeSequence.into());
+                }
+                let mut hex = String::new();
+                loop {
+                    match it.next() {
+                        Some('}') => break,
+                        Some(c) => hex.push(c),
+                        None => return Err(ParserError::InvalidEscapeSequence.into()),
+                    }
+                }
+                let c = u32::from_str_radix(&hex.replace('_', ""), 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or::<SWLError>(ParserError::InvalidEscapeSequence.into())?;
+                let mut buf = [0u8; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            high => {
+                let low = it
+                    .next()
+                    .ok_or::<SWLError>(ParserError::InvalidEscapeSequence.into())?;
+                let v = [high, low]
+                    .iter()
+                    .map(|c| c.to_digit(16))
+                    .collect::<Option<Vec<u32>>>()
+                    .ok_or::<SWLError>(ParserError::InvalidEscapeSequence.into())?;
+                bytes.push((v[0] * 16 + v[1]) as u8);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Replaces all `${KEY}` tokens in `content` with the value of `KEY`. Unknown keys are an error.
+/// Splits a leading `#!...` line, including its line break, off of `content`, so executable scripts can be parsed.
+pub fn split_shebang(content: &str) -> (Option<&str>, &str) {
+    if !content.starts_with("#!") {
+        return (None, content);
+    }
+    let end = content
+        .find('\n')
+        .map(|idx| idx + 1)
+        .unwrap_or(content.len());
+    let (shebang, rest) = content.split_at(end);
+    (Some(shebang), rest)
+}
+
+pub fn substitute_template(content: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("${") {
+        out += &rest[..start];
+        let end = rest[start..].find('}').ok_or_else(|| {
+            SWLError::Simple(format!(
+                "Unterminated template variable {}",
+                rest[start..].lines().next().unwrap_or("")
+            ))
+        })?;
+        let key = &rest[start + 2..start + end];
+        let value = vars
+            .get(key)
+            .ok_or_else(|| SWLError::Simple(format!("Undefined template variable {key}")))?;
+        out += value;
+        rest = &rest[start + end + 1..];
+    }
+    out += rest;
+    Ok(out)
+}
+
+/// Computes the CRC-32 (IEEE 802.3, as used by zlib and PNG) of the given bytes.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn parse_number_literal_test() {
+        assert_eq!(parse_number_literal("0"), Ok(0));
+        assert_eq!(parse_number_literal("010"), Ok(8));
+        assert_eq!(parse_number_literal("0x10"), Ok(16));
+        assert_eq!(parse_number_literal("10"), Ok(10));
+    }
+
     #[test]
     fn interpreted_string_length_test() {
-        let table = [(r#"1234"#, 4), (r#"123\00"#, 4), (r#"\01\02\03\04"#, 4)];
+        let table = [
+            (r#"1234"#, 4),
+            (r#"123\00"#, 4),
+            (r#"\01\02\03\04"#, 4),
+            (r#"\u{41}"#, 1),
+            (r#"\u{e9}"#, 2),
+            ("\u{e9}", 2),
+            (r#"a\u{1F600}b"#, 6),
+        ];
         for (input, expected) in table {
             assert_eq!(interpreted_string_length(input).unwrap(), expected);
         }
+        let module = crate::parser::Parser::new(r#"(module (data (i32.const 0) "\u{1F600}\""))"#)
+            .parse()
+            .unwrap();
+        assert_eq!(
+            module.items[0].as_node().unwrap().items[1].as_attribute(),
+            Some(r#""\u{1F600}\"""#)
+        );
+    }
+
+    #[test]
+    fn escape_roundtrip_test() {
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        // Cheap LCG so the test is deterministic without pulling in a dependency.
+        let mut state: u32 = 0x1234_5678;
+        let pseudo_random: Vec<u8> = (0..1024)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        for input in [vec![], all_bytes, pseudo_random] {
+            let escaped = escape_wat_string(&input);
+            assert_eq!(unescape_wat_string(&escaped).unwrap(), input);
+            assert_eq!(interpreted_string_length(&escaped).unwrap(), input.len());
+            let canonical = canonical_wat_string(&input);
+            assert_eq!(unescape_wat_string(&canonical).unwrap(), input);
+        }
     }
-}
 
-pub fn run_wat<V: wasm3::WasmType>(wat: &str) -> Result<V> {
-    let binary = wat::parse_str(wat).map_err(|err| SWLError::Other(err.into()))?;
-    let env = wasm3::Environment::new().map_err(|err| SWLError::Simple(err.to_string()))?;
-    let rt = env
-        .create_runtime(1024)
-        .map_err(|err| SWLError::Simple(err.to_string()))?;
-
-    let module =
-        wasm3::Module::parse(&env, binary).map_err(|err| SWLError::Simple(err.to_string()))?;
-    let module = rt
-        .load_module(module)
-        .map_err(|err| SWLError::Simple(err.to_string()))?;
-    let f = module
-        .find_function::<(), V>("main")
-        .map_err(|err| SWLError::Simple(err.to_string()))?;
-    let result = f.call().map_err(|err| SWLError::Simple(err.to_string()))?;
-    Ok(result)
+    #[test]
+    fn parse_float_literal_test() {
+        let table = [
+            ("1.5", 1.5),
+            ("-2e3", -2000.0),
+            ("0x1.8p1", 3.0),
+            ("0x10", 16.0),
+            ("0x.8", 0.5),
+            ("-0x1p-2", -0.25),
+            ("0x1_0.4P0", 16.25),
+            ("inf", f64::INFINITY),
+            ("-inf", f64::NEG_INFINITY),
+        ];
+        for (input, expected) in table {
+            assert_eq!(parse_float_literal(input), Some(expected), "{input}");
+        }
+        assert!(parse_float_literal("nan").unwrap().is_nan());
+        assert_eq!(
+            parse_float_literal("nan:0x1").unwrap().to_bits(),
+            0x7ff0_0000_0000_0001
+        );
+        for input in ["0x", "0x1.gp1", "nan:0x0", "abc", "0x1p"] {
+            assert_eq!(parse_float_literal(input), None, "{input}");
+        }
+    }
+
+    #[test]
+    fn decode_base64_test() {
+        assert_eq!(decode_base64("QUJD").unwrap(), b"ABC");
+        assert_eq!(decode_base64("QUI=").unwrap(), b"AB");
+        assert_eq!(decode_base64("QQ==\n").unwrap(), b"A");
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert_eq!(decode_base64("/+8=").unwrap(), [0xff, 0xef]);
+        assert!(decode_base64("QUJDR").is_none());
+        assert!(decode_base64("QU!D").is_none());
+    }
+
+    #[test]
+    fn merge_module_into_test() {
+        let mut parent = crate::parser::Parser::new("(module (func $a))")
+            .parse()
+            .unwrap();
+        // Pretend the parent is nested inside something else.
+        parent.node_iter_mut().for_each(|node| node.depth += 2);
+        let child = crate::parser::Parser::new("(module $child (func $b (block (nop))))")
+            .parse()
+            .unwrap();
+        merge_module_into(&mut parent, child).unwrap();
+        assert_eq!(
+            format!("{parent}"),
+            "(module (func $a) (func $b (block (nop))))"
+        );
+        let depths: Vec<(String, usize)> = parent
+            .node_iter()
+            .map(|node| (node.name.clone(), node.depth))
+            .collect();
+        assert_eq!(
+            depths,
+            [
+                ("module".to_string(), 2),
+                ("func".to_string(), 3),
+                ("func".to_string(), 3),
+                ("block".to_string(), 4),
+                ("nop".to_string(), 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn crc32_test() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn unescape_test() {
+        let table: [(&str, &[u8]); 5] = [
+            (r#"abc"#, b"abc"),
+            (r#"\t\n\r\"\'\\"#, b"\t\n\r\"'\\"),
+            (r#"\00\ff\7F"#, &[0x00, 0xff, 0x7f]),
+            (r#"\u{41}\u{e9}"#, "A\u{e9}".as_bytes()),
+            ("\u{e9}", "\u{e9}".as_bytes()),
+        ];
+        for (input, expected) in table {
+            assert_eq!(unescape_wat_string(input).unwrap(), expected);
+        }
+        for input in [r#"\"#, r#"\0"#, r#"\zz"#, r#"\u{110000}"#, r#"\u{41"#] {
+            assert!(unescape_wat_string(input).is_err());
+        }
+    }
 }
@@ -0,0 +1,41 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn failed_compile_keeps_output() {
+    let dir = std::env::temp_dir().join(format!("swl-atomic-output-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("broken.wat"), "(module (func $a)").unwrap();
+    fs::write(dir.join("valid.wat"), "(module (func $b))").unwrap();
+    fs::write(dir.join("out.wat"), "(module (func $old))").unwrap();
+
+    let failed = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "--features", "sort", "-o"])
+        .arg(dir.join("out.wat"))
+        .arg(dir.join("broken.wat"))
+        .output()
+        .unwrap()
+        .status;
+    let after_failure = fs::read_to_string(dir.join("out.wat")).unwrap();
+    let succeeded = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "--features", "sort", "-o"])
+        .arg(dir.join("out.wat"))
+        .arg(dir.join("valid.wat"))
+        .status()
+        .unwrap();
+    let after_success = fs::read_to_string(dir.join("out.wat")).unwrap();
+    let mut files: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(!failed.success());
+    assert_eq!(after_failure, "(module (func $old))");
+    assert!(succeeded.success());
+    assert_eq!(after_success, "(module (func $b))");
+    assert_eq!(files, ["broken.wat", "out.wat", "valid.wat"]);
+}
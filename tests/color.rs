@@ -0,0 +1,31 @@
+#![cfg(feature = "std")]
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn stderr_with_color(color: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "--features", "sort", "--color", color])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"(module").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn never() {
+    let stderr = stderr_with_color("never");
+    assert!(stderr.starts_with("Error: "), "{stderr}");
+    assert!(!stderr.contains('\x1b'), "{stderr}");
+}
+
+#[test]
+fn always() {
+    let stderr = stderr_with_color("always");
+    assert!(stderr.contains("\x1b[1;31mError:\x1b[0m"), "{stderr}");
+}
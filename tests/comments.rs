@@ -0,0 +1,28 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn default_features_keep_comments() {
+    let dir = std::env::temp_dir().join(format!("swl-comments-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("main.wat"),
+        ";; Header\n(module\n  (memory 1)\n  ;; Data\n  (data (offset (; base ;) (i32.const 0x10000)) \"x\"))\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "main.wat"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(";; Header\n(module"));
+    assert!(stdout.contains("(memory 2)"));
+    assert!(stdout.contains(";; Data\n"));
+}
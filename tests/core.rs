@@ -0,0 +1,14 @@
+use silly_wat_linker::{parser::Parser, pretty::PrettyPrinter};
+
+// Only uses the parts of the crate that are available with `--no-default-features`.
+#[test]
+fn parse_and_format_without_std_feature() {
+    let module = Parser::new("(module (func $main (nop)))").parse().unwrap();
+    assert_eq!(module.name, "module");
+    assert_eq!(format!("{module}"), "(module (func $main (nop)))");
+
+    let formatted = PrettyPrinter::new()
+        .print("(module (func $main (nop)))")
+        .unwrap();
+    assert!(formatted.starts_with("(module\n"));
+}
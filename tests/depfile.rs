@@ -0,0 +1,68 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn depfile_lists_imports() {
+    let dir = std::env::temp_dir().join(format!("swl-depfile-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("main.wat"),
+        r#"(module (import "util.wat" (file)) (memory 1) (data (i32.const 0) (import "data.bin" (raw))))"#,
+    )
+    .unwrap();
+    fs::write(dir.join("util.wat"), "(module (func $util))").unwrap();
+    fs::write(dir.join("data.bin"), "abc").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "main.wat", "-o", "out.wat", "--depfile", "out.d"])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    let depfile = fs::read_to_string(dir.join("out.d"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(status.success());
+    let root = dir.to_str().unwrap();
+    assert_eq!(
+        depfile.unwrap(),
+        format!("out.wat: {root}/main.wat {root}/util.wat {root}/data.bin\n")
+    );
+}
+
+#[test]
+fn depfile_lists_shebang_input() {
+    let dir = std::env::temp_dir().join(format!("swl-depfile-shebang-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("main.wat"),
+        "#!/usr/bin/env run-wat\n(module (import \"util.wat\" (file)))",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("util.wat"),
+        "#!/usr/bin/env run-wat\n(module (func $util))",
+    )
+    .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "main.wat", "-o", "out.wat", "--depfile", "out.d"])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    let output = fs::read_to_string(dir.join("out.wat"));
+    let depfile = fs::read_to_string(dir.join("out.d"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(status.success());
+    assert_eq!(
+        output.unwrap(),
+        "#!/usr/bin/env run-wat\n(module (func $util))"
+    );
+    let root = dir.to_str().unwrap();
+    assert_eq!(
+        depfile.unwrap(),
+        format!("out.wat: {root}/main.wat {root}/util.wat\n")
+    );
+}
@@ -0,0 +1,53 @@
+#![cfg(feature = "std")]
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn run_with_stdin(args: &[&str], stdin: &str) -> Option<i32> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait().unwrap().code()
+}
+
+#[test]
+fn success() {
+    assert_eq!(
+        run_with_stdin(&["compile", "--features", "sort"], "(module)"),
+        Some(0)
+    );
+}
+
+#[test]
+fn parse_error() {
+    assert_eq!(
+        run_with_stdin(&["compile", "--features", "sort"], "(module"),
+        Some(2)
+    );
+}
+
+#[test]
+fn empty_input() {
+    assert_eq!(
+        run_with_stdin(&["compile", "--features", "sort"], "  ;; nothing\n"),
+        Some(2)
+    );
+}
+
+#[test]
+fn io_error() {
+    assert_eq!(
+        run_with_stdin(&["compile", "./does-not-exist.wat"], ""),
+        Some(4)
+    );
+}
@@ -0,0 +1,18 @@
+#![cfg(feature = "std")]
+
+use std::process::Command;
+
+#[test]
+fn explain_import() {
+    let output = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["explain", "import"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Imports the contents of other .wat files"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("(file (as \"u\"))"), "{stdout}");
+}
@@ -0,0 +1,16 @@
+#![cfg(feature = "std")]
+
+use std::process::Command;
+
+#[test]
+fn print_feature_graph() {
+    let output = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "--print-feature-graph"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert!(stdout.starts_with("digraph features {"));
+    assert!(stdout.contains("numerals -> constexpr;"));
+}
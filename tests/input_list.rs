@@ -0,0 +1,33 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn format_input_list() {
+    let dir = std::env::temp_dir().join(format!("swl-input-list-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a.wat"), "(module (func $a (nop)))").unwrap();
+    fs::write(dir.join("sub/b.wat"), "(module (func $b (nop)))").unwrap();
+    fs::write(dir.join("untouched.wat"), "(module (func $c (nop)))").unwrap();
+    fs::write(
+        dir.join("manifest.txt"),
+        "# Files to format\na.wat\n\n  sub/b.wat  \n",
+    )
+    .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["format", "--input-list"])
+        .arg(dir.join("manifest.txt"))
+        .status()
+        .unwrap();
+    let a = fs::read_to_string(dir.join("a.wat")).unwrap();
+    let b = fs::read_to_string(dir.join("sub/b.wat")).unwrap();
+    let untouched = fs::read_to_string(dir.join("untouched.wat")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(status.success());
+    assert_eq!(a, "(module\n\t(func $a\n\t\t(nop)))");
+    assert_eq!(b, "(module\n\t(func $b\n\t\t(nop)))");
+    assert_eq!(untouched, "(module (func $c (nop)))");
+}
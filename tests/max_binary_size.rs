@@ -0,0 +1,42 @@
+#![cfg(feature = "std")]
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn compile(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "--features", "sort", "-c"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+static MODULE: &str =
+    r#"(module (memory 1) (data (i32.const 0) "0123456789012345678901234567890123456789"))"#;
+
+#[test]
+fn exceeds_limit() {
+    let output = compile(&["--max-binary-size", "16"], MODULE);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("but at most 16 bytes are allowed"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn within_limit() {
+    let output = compile(&["--max-binary-size", "1024"], MODULE);
+    assert!(output.status.success());
+}
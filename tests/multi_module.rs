@@ -0,0 +1,58 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn link_multiple_modules() {
+    let dir = std::env::temp_dir().join(format!("swl-multi-module-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("common.wat"), "(module (func $common))").unwrap();
+    fs::write(
+        dir.join("main.wat"),
+        r#"
+            (module (import "common.wat" (file)) (func $a))
+            (module (import "common.wat" (file)) (func $b))
+        "#,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args([
+            "compile",
+            "--multi-module",
+            "--features",
+            "import",
+            "--root",
+        ])
+        .arg(&dir)
+        .arg(dir.join("main.wat"))
+        .output()
+        .unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args([
+            "compile",
+            "--multi-module",
+            "--features",
+            "import",
+            "--root",
+        ])
+        .arg(&dir)
+        .arg("--output-dir")
+        .arg(dir.join("out"))
+        .arg(dir.join("main.wat"))
+        .status()
+        .unwrap();
+    let first = fs::read_to_string(dir.join("out/0.wat")).unwrap();
+    let second = fs::read_to_string(dir.join("out/1.wat")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "(module (func $a) (func $common))\n(module (func $b) (func $common))"
+    );
+    assert!(status.success());
+    assert_eq!(first, "(module (func $a) (func $common))");
+    assert_eq!(second, "(module (func $b) (func $common))");
+}
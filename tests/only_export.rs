@@ -0,0 +1,41 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn only_export_drops_other_exports() {
+    let dir = std::env::temp_dir().join(format!("swl-only-export-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("main.wat"),
+        r#"
+            (module
+                (func $main (export "main") (result i32) (call $double (i32.const 21)))
+                (func $double (param $x i32) (result i32) (i32.mul (local.get $x) (i32.const 2)))
+                (func $other (export "other") (call $other_helper))
+                (func $other_helper))
+        "#,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "main.wat", "--only-export", "main"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    let missing = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "main.wat", "--only-export", "missing"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("$main"));
+    assert!(stdout.contains("$double"));
+    assert!(!stdout.contains("$other"));
+    assert!(!stdout.contains("\"other\""));
+    assert!(!missing.status.success());
+}
@@ -0,0 +1,31 @@
+#![cfg(feature = "std")]
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn compile(args: &[&str], stdin: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .arg("compile")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn parse_only_keeps_literals() {
+    let input = "(module (func $b) (data (i32.const 0x1_0) \"x\") (func $a (i32.const 0b101)))";
+    assert_eq!(compile(&["--parse-only"], input), input);
+    assert_ne!(compile(&[], input), input);
+}
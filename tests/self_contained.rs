@@ -0,0 +1,44 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn self_contained_inlines_imports() {
+    let dir = std::env::temp_dir().join(format!("swl-self-contained-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("main.wat"),
+        r#"(module (import "util.wat" (file)) (memory 1) (data (i32.const 0) (import "data.bin" (raw))))"#,
+    )
+    .unwrap();
+    fs::write(dir.join("util.wat"), "(module (func $util))").unwrap();
+    fs::write(dir.join("data.bin"), "abc").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args([
+            "compile",
+            "main.wat",
+            "--features",
+            "sort",
+            "--self-contained",
+        ])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    fs::remove_file(dir.join("data.bin")).unwrap();
+    let unresolved = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "main.wat", "--self-contained"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("(file)"));
+    assert!(!stdout.contains("(raw)"));
+    assert!(stdout.contains("(func $util)"));
+    assert!(stdout.contains(r#""\61\62\63""#));
+    assert!(!unresolved.status.success());
+}
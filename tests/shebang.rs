@@ -0,0 +1,34 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn format_keeps_shebang() {
+    let dir = std::env::temp_dir().join(format!("swl-shebang-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("script.wat");
+    fs::write(&file, "#!/usr/bin/env run-wat\n(module (func $main))").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .arg("format")
+        .arg(&file)
+        .status()
+        .unwrap();
+    let formatted = fs::read_to_string(&file).unwrap();
+    let compiled = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args(["compile", "--features", "sort"])
+        .arg(&file)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(status.success());
+    assert_eq!(formatted.lines().next(), Some("#!/usr/bin/env run-wat"));
+    assert!(formatted.contains("(func $main)"));
+    assert!(compiled.status.success());
+    assert_eq!(
+        String::from_utf8(compiled.stdout).unwrap(),
+        "#!/usr/bin/env run-wat\n(module (func $main))"
+    );
+}
@@ -0,0 +1,40 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn imports_resolve_next_to_stdin_filename() {
+    let dir = std::env::temp_dir().join(format!("swl-stdin-filename-{}", std::process::id()));
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("src/util.wat"), "(module (func $util))").unwrap();
+    fs::write(dir.join("src/data.bin"), "ab").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_silly-wat-linker"))
+        .args([
+            "compile",
+            "--features",
+            "import,data_import",
+            "--stdin-filename",
+            "src/main.wat",
+        ])
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(br#"(module (import "util.wat" (file)) (data (i32.const 0) (import "data.bin" (raw))))"#)
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("(func $util)"));
+    assert!(stdout.contains(r#""\61\62""#));
+}